@@ -0,0 +1,17 @@
+
+CREATE TABLE IF NOT EXISTS coinbase_buys_sells(
+    id TEXT NOT NULL,
+    transaction_id TEXT NOT NULL,
+    resource TEXT NOT NULL,
+    status TEXT NOT NULL,
+    fee_amount TEXT NOT NULL,
+    fee_currency TEXT NOT NULL,
+    subtotal_amount TEXT,
+    subtotal_currency TEXT,
+    total_amount TEXT,
+    total_currency TEXT,
+
+    PRIMARY KEY (id)
+) ;
+
+CREATE INDEX IF NOT EXISTS coinbase_buys_sells_transaction_id ON coinbase_buys_sells(transaction_id);
@@ -0,0 +1,11 @@
+
+CREATE TABLE IF NOT EXISTS statement_rows(
+    format TEXT NOT NULL,
+    id TEXT NOT NULL,
+    file TEXT NOT NULL,
+    line INTEGER NOT NULL,
+    fields TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (format, id)
+) ;
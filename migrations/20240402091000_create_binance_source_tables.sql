@@ -0,0 +1,76 @@
+
+CREATE TABLE IF NOT EXISTS binance_my_trades(
+    symbol TEXT NOT NULL,
+    base_asset TEXT NOT NULL,
+    quote_asset TEXT NOT NULL,
+    id INTEGER NOT NULL,
+    order_id INTEGER NOT NULL,
+    order_list_id INTEGER NOT NULL,
+    price TEXT NOT NULL,
+    qty TEXT NOT NULL,
+    quote_qty TEXT NOT NULL,
+    commission TEXT NOT NULL,
+    commission_asset TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    is_buyer INTEGER NOT NULL,
+    is_maker INTEGER NOT NULL,
+    is_best_match INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (symbol, id)
+) ;
+
+CREATE TABLE IF NOT EXISTS binance_deposits(
+    id TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    coin TEXT NOT NULL,
+    network TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    tx_id TEXT NOT NULL,
+    insert_time INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (id)
+) ;
+
+CREATE TABLE IF NOT EXISTS binance_withdrawals(
+    id TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    transaction_fee TEXT NOT NULL,
+    coin TEXT NOT NULL,
+    network TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    tx_id TEXT,
+    apply_time TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (id)
+) ;
+
+CREATE TABLE IF NOT EXISTS binance_converts(
+    order_id INTEGER NOT NULL,
+    quote_id TEXT NOT NULL,
+    order_status TEXT NOT NULL,
+    from_asset TEXT NOT NULL,
+    from_amount TEXT NOT NULL,
+    to_asset TEXT NOT NULL,
+    to_amount TEXT NOT NULL,
+    create_time INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (order_id)
+) ;
+
+CREATE TABLE IF NOT EXISTS binance_dust(
+    trans_id INTEGER NOT NULL,
+    from_asset TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    transfered_amount TEXT NOT NULL,
+    service_charge_amount TEXT NOT NULL,
+    operate_time INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (trans_id, from_asset)
+) ;
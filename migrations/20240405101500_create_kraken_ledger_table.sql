@@ -0,0 +1,16 @@
+
+CREATE TABLE IF NOT EXISTS kraken_ledger(
+    id TEXT NOT NULL,
+    refid TEXT NOT NULL,
+    time REAL NOT NULL,
+    type TEXT NOT NULL,
+    subtype TEXT NOT NULL,
+    aclass TEXT NOT NULL,
+    asset TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    fee TEXT NOT NULL,
+    balance TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (id)
+) ;
@@ -0,0 +1,49 @@
+
+CREATE TABLE IF NOT EXISTS evm_wallets(
+    network TEXT NOT NULL,
+    address TEXT NOT NULL,
+    name TEXT NOT NULL,
+    last_block INTEGER,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (network, address)
+) ;
+
+CREATE TABLE IF NOT EXISTS evm_transactions(
+    network TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    from_address TEXT NOT NULL,
+    to_address TEXT,
+    value TEXT NOT NULL,
+    input TEXT NOT NULL,
+    gas_used TEXT NOT NULL,
+    gas_price TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (network, hash)
+) ;
+
+CREATE TABLE IF NOT EXISTS evm_logs(
+    network TEXT NOT NULL,
+    tx_hash TEXT NOT NULL,
+    log_index INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    topics TEXT NOT NULL,
+    data TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (network, tx_hash, log_index)
+) ;
+
+CREATE TABLE IF NOT EXISTS evm_tokens(
+    network TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    decimals INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (network, contract_address)
+) ;
@@ -0,0 +1,34 @@
+
+CREATE TABLE IF NOT EXISTS bitcoin_wallets(
+    name TEXT NOT NULL PRIMARY KEY,
+    descriptor TEXT NOT NULL,
+    network TEXT NOT NULL,
+    created_at TEXT NOT NULL
+) ;
+
+CREATE TABLE IF NOT EXISTS bitcoin_addresses(
+    wallet TEXT NOT NULL,
+    address TEXT NOT NULL,
+    script_pubkey TEXT NOT NULL,
+    is_change INTEGER NOT NULL,
+    derivation_index INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (wallet, address)
+) ;
+
+CREATE TABLE IF NOT EXISTS bitcoin_transactions(
+    txid TEXT NOT NULL PRIMARY KEY,
+    raw TEXT NOT NULL,
+    created_at TEXT NOT NULL
+) ;
+
+CREATE TABLE IF NOT EXISTS bitcoin_wallet_transactions(
+    wallet TEXT NOT NULL,
+    txid TEXT NOT NULL,
+    height INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (wallet, txid)
+) ;
@@ -0,0 +1,31 @@
+
+CREATE TABLE IF NOT EXISTS solana_wallets(
+    address TEXT NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at TEXT NOT NULL
+) ;
+
+CREATE TABLE IF NOT EXISTS solana_accounts(
+    wallet TEXT NOT NULL,
+    account TEXT NOT NULL,
+    last_signature TEXT,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (wallet, account)
+) ;
+
+CREATE TABLE IF NOT EXISTS solana_transactions(
+    signature TEXT NOT NULL PRIMARY KEY,
+    slot INTEGER NOT NULL,
+    block_time INTEGER,
+    data TEXT NOT NULL,
+    created_at TEXT NOT NULL
+) ;
+
+CREATE TABLE IF NOT EXISTS solana_wallet_transactions(
+    wallet TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+
+    PRIMARY KEY (wallet, signature)
+) ;
@@ -0,0 +1,45 @@
+
+CREATE TABLE IF NOT EXISTS sources(
+    name TEXT NOT NULL PRIMARY KEY,
+    mapping_version INTEGER NOT NULL,
+    mapped_at TEXT
+) ;
+
+CREATE TABLE IF NOT EXISTS transactions(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source TEXT NOT NULL REFERENCES sources(name) ON DELETE CASCADE,
+    raw_table TEXT NOT NULL,
+    raw_id TEXT NOT NULL,
+    tx_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    application TEXT,
+    usd_amount REAL NOT NULL,
+    timestamp TEXT NOT NULL,
+    note TEXT NOT NULL,
+    created_at TEXT NOT NULL
+) ;
+
+CREATE INDEX IF NOT EXISTS transactions_source ON transactions(source) ;
+CREATE INDEX IF NOT EXISTS transactions_raw ON transactions(raw_table, raw_id) ;
+
+CREATE TABLE IF NOT EXISTS transaction_legs(
+    transaction_id INTEGER NOT NULL REFERENCES transactions(id) ON DELETE CASCADE,
+    role TEXT NOT NULL,
+    asset TEXT NOT NULL,
+    contract_address TEXT,
+    amount REAL NOT NULL,
+    store_type TEXT,
+    store_name TEXT,
+    store_network TEXT,
+    store_address TEXT,
+
+    PRIMARY KEY (transaction_id, role)
+) ;
+
+CREATE TABLE IF NOT EXISTS transaction_fees(
+    transaction_id INTEGER NOT NULL PRIMARY KEY REFERENCES transactions(id) ON DELETE CASCADE,
+    asset TEXT NOT NULL,
+    contract_address TEXT,
+    amount REAL NOT NULL,
+    usd_amount REAL NOT NULL
+) ;
@@ -0,0 +1,7 @@
+
+CREATE TABLE IF NOT EXISTS manual_transactions(
+    tx_id TEXT NOT NULL PRIMARY KEY,
+    transaction_data TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+) ;
@@ -0,0 +1,15 @@
+
+CREATE TABLE IF NOT EXISTS transaction_overrides(
+    source TEXT NOT NULL,
+    tx_id TEXT NOT NULL,
+    kind TEXT,
+    amount REAL,
+    source_amount REAL,
+    destination_amount REAL,
+    usd_amount REAL,
+    note TEXT,
+    excluded INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (source, tx_id)
+) ;
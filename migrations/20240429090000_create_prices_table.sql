@@ -0,0 +1,10 @@
+
+CREATE TABLE IF NOT EXISTS prices(
+    asset TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    date TEXT NOT NULL,
+    usd_price REAL NOT NULL,
+    source TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (asset, contract_address, date)
+) ;
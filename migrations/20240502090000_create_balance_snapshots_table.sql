@@ -0,0 +1,14 @@
+
+CREATE TABLE IF NOT EXISTS balance_snapshots(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source TEXT NOT NULL,
+    taken_at TEXT NOT NULL
+) ;
+
+CREATE TABLE IF NOT EXISTS balance_snapshot_entries(
+    snapshot_id INTEGER NOT NULL REFERENCES balance_snapshots(id) ON DELETE CASCADE,
+    account TEXT NOT NULL,
+    asset TEXT NOT NULL,
+    amount REAL NOT NULL,
+    PRIMARY KEY (snapshot_id, account, asset)
+) ;
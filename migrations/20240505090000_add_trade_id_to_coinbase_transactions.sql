@@ -0,0 +1,2 @@
+
+ALTER TABLE coinbase_transactions ADD COLUMN trade_id TEXT ;
@@ -0,0 +1,13 @@
+-- A raw row may map to several transactions, they are told apart by their position
+ALTER TABLE transactions ADD COLUMN raw_index INTEGER NOT NULL DEFAULT 0 ;
+
+UPDATE transactions SET raw_index = (
+    SELECT COUNT(*) FROM transactions t
+    WHERE t.source = transactions.source
+        AND t.raw_table = transactions.raw_table
+        AND t.raw_id = transactions.raw_id
+        AND t.id < transactions.id
+) ;
+
+CREATE UNIQUE INDEX IF NOT EXISTS transactions_raw_key
+    ON transactions(source, raw_table, raw_id, raw_index) ;
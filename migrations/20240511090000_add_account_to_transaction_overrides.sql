@@ -0,0 +1,27 @@
+-- Overrides are keyed by the id of the transaction across sources, which takes the account
+CREATE TABLE IF NOT EXISTS transaction_overrides_by_account(
+    source TEXT NOT NULL,
+    account TEXT NOT NULL,
+    tx_id TEXT NOT NULL,
+    kind TEXT,
+    amount REAL,
+    source_amount REAL,
+    destination_amount REAL,
+    usd_amount REAL,
+    note TEXT,
+    excluded INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (source, account, tx_id)
+) ;
+
+-- Each existing override goes to every account that had a transaction of its tx_id
+INSERT OR IGNORE INTO transaction_overrides_by_account
+SELECT o.source, COALESCE(t.account, ''), o.tx_id, o.kind, o.amount, o.source_amount,
+    o.destination_amount, o.usd_amount, o.note, o.excluded, o.created_at, o.updated_at
+FROM transaction_overrides o
+LEFT JOIN transactions t ON t.source = o.source AND t.tx_id = o.tx_id ;
+
+DROP TABLE transaction_overrides ;
+
+ALTER TABLE transaction_overrides_by_account RENAME TO transaction_overrides ;
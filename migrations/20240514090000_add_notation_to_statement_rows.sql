@@ -0,0 +1 @@
+ALTER TABLE statement_rows ADD COLUMN notation TEXT ;
@@ -0,0 +1,262 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use sqlx::{Pool, Sqlite};
+
+use crate::{
+    command_line_interface::CostMethod,
+    data::{Asset, Transaction, ValueStore},
+    input::{InputError, MappedSource},
+    lots::LotEngine,
+    normalized::{self, Record},
+    prices::PriceStore,
+};
+
+/// The quantity of an asset and what it cost in usd
+#[derive(Debug, Clone, Copy)]
+pub struct Holding {
+    pub quantity: f64,
+    /// None if some of it was acquired in transactions without a value
+    pub cost: Option<f64>,
+}
+
+impl Default for Holding {
+    fn default() -> Self {
+        Holding {
+            quantity: 0.0,
+            cost: Some(0.0),
+        }
+    }
+}
+
+pub fn store_label(store: &ValueStore) -> String {
+    match store {
+        ValueStore::Cex(name) => name.clone(),
+        ValueStore::Wallet { name, network, .. } => format!("{name} ({network})"),
+    }
+}
+
+/// The stores of the accounts of the sources by source and account, taken from their
+/// transfers, as trades and airdrops have no store of their own. Manual transactions share
+/// one account across all stores, so they are left out and go by their application.
+pub type AccountStores = HashMap<(String, String), String>;
+
+pub fn account_stores(records: &[Record]) -> AccountStores {
+    let mut stores = AccountStores::new();
+    for record in records {
+        if record.source == MappedSource::Manual.name() {
+            continue;
+        }
+        let store = match &record.transaction {
+            Transaction::Deposit(transfer) | Transaction::Withdrawal(transfer) => &transfer.store,
+            Transaction::Bridge(bridge) => &bridge.source,
+            _ => continue,
+        };
+        stores
+            .entry((record.source.clone(), record.account.clone()))
+            .or_insert_with(|| store_label(store));
+    }
+
+    stores
+}
+
+/// The store of a transaction without one, falling back to the application it ran on
+pub fn store_of(stores: &AccountStores, record: &Record, application: Option<&str>) -> String {
+    stores
+        .get(&(record.source.clone(), record.account.clone()))
+        .cloned()
+        .or(application.map(str::to_string))
+        .unwrap_or_else(|| record.account.clone())
+}
+
+/// The holdings at the end of the day by store and asset, the lots left of all
+/// transactions before summed up
+pub fn holdings(records: &[Record], until: DateTime<Utc>) -> Vec<(String, Asset, Holding)> {
+    let engine = LotEngine::run_until(records, CostMethod::Fifo, until);
+
+    let mut holdings = BTreeMap::<(String, String, Option<String>), (Asset, Holding)>::new();
+    for (store, lot) in engine.holdings() {
+        let key = (
+            store.to_string(),
+            lot.asset.name.clone(),
+            lot.asset.contract_address.clone(),
+        );
+        let (_, holding) = holdings
+            .entry(key)
+            .or_insert_with(|| (lot.asset.clone(), Holding::default()));
+        holding.quantity += lot.quantity;
+        holding.cost = holding.cost.zip(lot.cost).map(|(cost, lot)| cost + lot);
+    }
+
+    holdings
+        .into_iter()
+        .map(|((store, _, _), (asset, holding))| (store, asset, holding))
+        .collect()
+}
+
+/// Leftovers of rounding are no holding
+pub const DUST: f64 = 1e-9;
+
+/// The end of the day, the start of the next one, which the holdings of the day run until
+fn end_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.succ_opt()
+        .unwrap_or(date)
+        .and_time(NaiveTime::MIN)
+        .and_utc()
+}
+
+/// Prints the holdings at the end of the day, valued by the prices of the day
+pub async fn print_balances(db: &Pool<Sqlite>, date: NaiveDate) -> Result<(), InputError> {
+    let records = normalized::load_all(db).await?;
+    let prices = PriceStore::load(db).await?;
+    let until = end_of_day(date);
+
+    println!("Holdings at the end of {date}");
+    println!(
+        "{:<28} {:<12} {:>20} {:>16} {:>16}",
+        "Store", "Asset", "Quantity", "Cost (USD)", "Value (USD)"
+    );
+
+    let mut total_cost = 0.0;
+    let mut total_value = 0.0;
+    let mut unpriced = 0;
+    let mut uncosted = 0;
+    for (store, asset, holding) in holdings(&records, until) {
+        if holding.quantity.abs() < DUST {
+            continue;
+        }
+
+        let value = prices
+            .usd_price(&asset, date)
+            .map(|price| price * holding.quantity);
+        match holding.cost {
+            Some(cost) => total_cost += cost,
+            None => uncosted += 1,
+        }
+        match value {
+            Some(value) => total_value += value,
+            None => unpriced += 1,
+        }
+
+        println!(
+            "{:<28} {:<12} {:>20.8} {:>16} {:>16}",
+            store,
+            asset.name,
+            holding.quantity,
+            holding
+                .cost
+                .map_or("unvalued".to_string(), |cost| format!("{cost:.2}")),
+            value.map_or("no price".to_string(), |value| format!("{value:.2}"))
+        );
+    }
+
+    println!(
+        "{:<28} {:<12} {:>20} {:>16.2} {:>16.2}",
+        "Total", "", "", total_cost, total_value
+    );
+    if unpriced > 0 {
+        println!("{unpriced} holdings have no price, add them with the price command");
+    }
+    if uncosted > 0 {
+        println!(
+            "{uncosted} holdings come from trades or deposits without a value and have no cost, \
+            the total cost leaves them out"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::data::{Amount, Application, Trade, Transfer};
+
+    fn amount(amount: f64, name: &str) -> Amount {
+        Amount {
+            amount,
+            asset: Asset {
+                name: name.to_string(),
+                contract_address: None,
+            },
+        }
+    }
+
+    fn manual(transaction: Transaction) -> Record {
+        Record {
+            source: MappedSource::Manual.name().to_string(),
+            account: "manual".to_string(),
+            transaction,
+        }
+    }
+
+    #[test]
+    fn manual_trades_stay_in_the_store_they_ran_on() {
+        let at = Utc.with_ymd_and_hms(2024, 1, 2, 12, 0, 0).unwrap();
+        let records = [
+            manual(Transaction::Deposit(Transfer {
+                tx_id: "m1".to_string(),
+                store: ValueStore::Cex("Kraken".to_string()),
+                amount: amount(1000.0, "USD"),
+                usd_amount: 1000.0,
+                comission: None,
+                timestamp: at,
+                note: "".to_string(),
+            })),
+            manual(Transaction::Trade(Trade {
+                application: Application("Bison".to_string()),
+                tx_id: "m2".to_string(),
+                source: amount(500.0, "EUR"),
+                destination: amount(0.01, "BTC"),
+                comission: None,
+                usd_amount: 550.0,
+                timestamp: at,
+            })),
+        ];
+
+        let stores = account_stores(&records);
+
+        assert_eq!(store_of(&stores, &records[1], Some("Bison")), "Bison");
+        let btc = holdings(&records, at + Duration::days(1))
+            .into_iter()
+            .find(|(_, asset, _)| asset.name == "BTC")
+            .map(|(store, _, holding)| (store, holding.quantity));
+        assert_eq!(btc, Some(("Bison".to_string(), 0.01)));
+    }
+
+    #[test]
+    fn holdings_are_those_at_the_end_of_the_day() {
+        let at = |day| Utc.with_ymd_and_hms(2024, 1, day, 12, 0, 0).unwrap();
+        let buy = |tx_id: &str, btc: f64, day| Record {
+            source: "Kraken".to_string(),
+            account: "kraken".to_string(),
+            transaction: Transaction::Trade(Trade {
+                application: Application("Kraken".to_string()),
+                tx_id: tx_id.to_string(),
+                source: amount(btc * 40_000.0, "USD"),
+                destination: amount(btc, "BTC"),
+                comission: None,
+                usd_amount: btc * 40_000.0,
+                timestamp: at(day),
+            }),
+        };
+        let records = [buy("t1", 0.1, 2), buy("t2", 0.2, 5)];
+        let btc = |day| {
+            let date = NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+            holdings(&records, end_of_day(date))
+                .into_iter()
+                .filter(|(_, asset, _)| asset.name == "BTC")
+                .map(|(_, _, holding)| (holding.quantity, holding.cost))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(btc(1), []);
+        assert_eq!(btc(2), [(0.1, Some(4000.0))]);
+        assert_eq!(btc(4), [(0.1, Some(4000.0))]);
+        let (quantity, cost) = btc(5)[0];
+        assert!((quantity - 0.3).abs() < 1e-9);
+        assert_eq!(cost, Some(12000.0));
+    }
+}
@@ -1,10 +1,30 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+
 use clap::{Parser, Subcommand, ValueEnum};
 
+/// The exit codes, kept in line with `ErrorCategory::exit_code`
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  success
+  2  invalid arguments
+  7  configuration error, e.g. a missing API key
+  4  database error
+  3  network error
+  5  file error, e.g. an unreadable statement or recording
+  6  data error, e.g. a record that cannot be parsed or a missing rate
+When errors of several kinds occur, the code of the first of them in this list is returned.";
+
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_help = EXIT_CODES)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Command,
+
+    #[arg(short, long, global = true)]
+    /// keep going on bad records and print an error summary at the end
+    pub keep_going: bool,
 }
 
 #[derive(Subcommand)]
@@ -14,7 +34,128 @@ pub enum Command {
         #[arg(short, long)]
         /// specify the exchange to fetch data from
         exchange: Option<Exchange>,
+
+        #[arg(long, conflicts_with = "replay")]
+        /// archive the raw responses in the directory, secrets are left out
+        record: Option<PathBuf>,
+
+        #[arg(long)]
+        /// read the responses from a directory written by --record instead of the exchanges
+        replay: Option<PathBuf>,
+
+        #[arg(short, long, default_value_t = 4)]
+        /// the maximum number of requests in flight per exchange
+        concurrency: usize,
+    },
+    /// Import a CSV statement exported from an exchange, e.g. to backfill what the API misses
+    Import {
+        #[arg(short, long)]
+        /// the format of the statement
+        format: StatementFormat,
+        /// the statement file
+        file: PathBuf,
+    },
+    /// Add a transaction no source knows of by hand, e.g. an OTC trade or lost coins
+    Add {
+        /// the transaction as JSON, in the shape display prints it
+        transaction: String,
+    },
+    /// Replace a transaction added by hand
+    Edit {
+        /// the tx_id of the transaction to replace
+        tx_id: String,
+        /// the new transaction as JSON, in the shape display prints it
+        transaction: String,
+    },
+    /// Delete a transaction added by hand
+    Delete {
+        /// the tx_id of the transaction to delete
+        tx_id: String,
+    },
+    /// Correct a mapped transaction, the correction outlives fetching its source anew
+    Override {
+        /// the id of the transaction as source/account/tx_id, e.g. the id in the note of
+        /// its export without the leg
+        id: String,
+
+        #[arg(long)]
+        /// reclassify the transaction
+        kind: Option<TransactionKind>,
+
+        #[arg(long, allow_negative_numbers = true)]
+        /// the amount of an airdrop, bridge, deposit or withdrawal
+        amount: Option<f64>,
+
+        #[arg(long, allow_negative_numbers = true)]
+        /// the sold amount of a trade or currency exchange
+        source_amount: Option<f64>,
+
+        #[arg(long, allow_negative_numbers = true)]
+        /// the bought amount of a trade or currency exchange
+        destination_amount: Option<f64>,
+
+        #[arg(long, allow_negative_numbers = true)]
+        /// the value of the transaction in usd
+        usd_amount: Option<f64>,
+
+        #[arg(long)]
+        /// the note of an airdrop, deposit or withdrawal
+        note: Option<String>,
+
+        #[arg(long, conflicts_with = "include")]
+        /// leave the transaction out of everything
+        exclude: bool,
+
+        #[arg(long)]
+        /// take an excluded transaction back in
+        include: bool,
+
+        #[arg(long, conflicts_with_all = ["kind", "amount", "source_amount", "destination_amount", "usd_amount", "note", "exclude", "include"])]
+        /// remove all corrections of the transaction
+        clear: bool,
+    },
+    /// Show the holdings per store and asset with their cost and value
+    Balances {
+        #[arg(short, long)]
+        /// the day to show the holdings at the end of, defaulting to today
+        date: Option<NaiveDate>,
+    },
+    /// Show the gain of every disposal and the realized and unrealized gains per asset
+    Gains {
+        #[arg(short, long, value_enum, default_value_t = CostMethod::Fifo)]
+        /// the order in which lots are disposed of
+        method: CostMethod,
+
+        #[arg(short, long)]
+        /// only show the disposals of the year
+        year: Option<i32>,
+    },
+    /// Show the German tax report of the year, private sales (§23 EStG) with lots used up
+    /// first in, first out per wallet and income from staking and airdrops (§22 Nr. 3 EStG)
+    Tax {
+        #[arg(short, long)]
+        /// the year to report
+        year: i32,
+    },
+    /// Compare the balances MEXC and Coinbase reported on the last fetch with the computed ones
+    Reconcile,
+    /// Set the usd price of an asset on a day, used to value the holdings
+    Price {
+        /// the ticker of the asset, e.g. BTC
+        asset: String,
+        /// the price of one unit in usd
+        usd_price: f64,
+
+        #[arg(short, long)]
+        /// the day of the price, defaulting to today
+        date: Option<NaiveDate>,
+
+        #[arg(long)]
+        /// the contract address of a token
+        contract_address: Option<String>,
     },
+    /// Fetch the daily euro reference rates of the ECB, used to convert fiat to usd
+    Rates,
     /// Display data from exchanges, defaulting to all
     Display,
     /// Export data from exchanges, defaulting to all
@@ -34,4 +175,51 @@ pub enum FetchOptions {
 pub enum Exchange {
     Coinbase,
     MEXC,
+    Binance,
+    Kraken,
+    /// the wallets of `EVM_WALLETS` on the networks of `EVM_NETWORKS`
+    Evm,
+    /// the wallets of `BITCOIN_WALLETS` through the Electrum server of `ELECTRUM_URL`
+    Bitcoin,
+    /// the wallets of `SOLANA_WALLETS` with their token accounts
+    Solana,
+}
+
+/// The order in which the lots of an asset are disposed of
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CostMethod {
+    /// first in, first out
+    Fifo,
+    /// last in, first out
+    Lifo,
+    /// highest cost first
+    Hifo,
+    /// all lots at their average cost
+    Average,
+}
+
+/// The kinds a transaction can be reclassified as
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum TransactionKind {
+    Trade,
+    CurrencyExchange,
+    Airdrop,
+    Deposit,
+    Withdrawal,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum StatementFormat {
+    /// the transaction history report of Coinbase
+    Coinbase,
+    /// the spot trade history export of MEXC
+    MexcSpot,
+    /// the transaction history export of Binance
+    Binance,
+    /// the ledgers.csv export of Kraken
+    Kraken,
+    /// the transaction history export of Bitpanda
+    Bitpanda,
+    /// the transaction export of Bison
+    Bison,
 }
@@ -1,11 +1,11 @@
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 pub type Network = String;
 
 /// An ValueStore is any place an crypto currency or fiat can be stored
 /// e.g. CEX, Wallets, Banks, etc
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ValueStore {
     Cex(String),
     Wallet {
@@ -20,7 +20,7 @@ pub enum ValueStore {
 }
 
 /// An Asset is any crypto currency or fiat
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Asset {
     /// The unique ticker of the asset
     pub name: String,
@@ -28,8 +28,33 @@ pub struct Asset {
     pub contract_address: Option<String>,
 }
 
+impl Asset {
+    /// Whether the asset is a fiat currency, e.g. USD or EUR
+    pub fn is_fiat(&self) -> bool {
+        matches!(self.name.as_str(), "USD" | "EUR" | "GBP" | "CHF")
+    }
+
+    /// The fiat currency a stablecoin is pegged to, if the asset is one
+    pub fn stablecoin_peg(&self) -> Option<&'static str> {
+        match self.name.as_str() {
+            "USDC" | "USDT" | "DAI" | "PYUSD" | "BUSD" | "FDUSD" | "TUSD" => Some("USD"),
+            "EURC" | "EURT" => Some("EUR"),
+            _ => None,
+        }
+    }
+
+    /// The fiat currency the asset is valued in, for fiat and stablecoins
+    pub fn cash_currency(&self) -> Option<&str> {
+        if self.is_fiat() {
+            Some(&self.name)
+        } else {
+            self.stablecoin_peg()
+        }
+    }
+}
+
 /// An Amount is the amount and asset used in other structs
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Amount {
     /// The amount of the asset stored, traded, etc
     pub amount: f64,
@@ -39,26 +64,103 @@ pub struct Amount {
 
 /// An Application is any place an crypto currency or fiat can be traded or bridged
 /// e.g. Uniswap, PancakeSwap, etc
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Application(pub String);
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Transaction {
     Trade(Trade),
     /// An Airdrop is a transaction where an asset is given to an account for free
     Airdrop(Airdrop),
     /// A Bridge is a transaction that moves an asset from one network to another
     Bridge(Bridge),
+    /// A CurrencyExchange is a transaction where fiat or stablecoins are exchanged for each other
+    CurrencyExchange(CurrencyExchange),
+    /// A Deposit is a transaction where an asset is moved into a ValueStore
+    Deposit(Transfer),
+    /// A Withdrawal is a transaction where an asset is moved out of a ValueStore
+    Withdrawal(Transfer),
+}
+
+impl Transaction {
+    /// The unique id of the transaction
+    pub fn tx_id(&self) -> &str {
+        match self {
+            Transaction::Trade(trade) => &trade.tx_id,
+            Transaction::Airdrop(airdrop) => &airdrop.tx_id,
+            Transaction::Bridge(bridge) => &bridge.tx_id,
+            Transaction::CurrencyExchange(exchange) => &exchange.tx_id,
+            Transaction::Deposit(transfer) | Transaction::Withdrawal(transfer) => &transfer.tx_id,
+        }
+    }
+
+    /// The time the transaction took place
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        match self {
+            Transaction::Trade(trade) => trade.timestamp,
+            Transaction::Airdrop(airdrop) => airdrop.timestamp,
+            Transaction::Bridge(bridge) => bridge.timestamp,
+            Transaction::CurrencyExchange(exchange) => exchange.timestamp,
+            Transaction::Deposit(transfer) | Transaction::Withdrawal(transfer) => {
+                transfer.timestamp
+            }
+        }
+    }
+
+    /// The name of the kind of the transaction, e.g. Trade or Deposit
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Transaction::Trade(_) => "Trade",
+            Transaction::Airdrop(_) => "Airdrop",
+            Transaction::Bridge(_) => "Bridge",
+            Transaction::CurrencyExchange(_) => "CurrencyExchange",
+            Transaction::Deposit(_) => "Deposit",
+            Transaction::Withdrawal(_) => "Withdrawal",
+        }
+    }
+
+    /// Exchanges between fiat and stablecoins are currency exchanges, everything else is a trade
+    pub fn exchange(
+        application: Application,
+        tx_id: String,
+        source: Amount,
+        destination: Amount,
+        comission: Option<Comission>,
+        usd_amount: f64,
+        timestamp: DateTime<Utc>,
+    ) -> Transaction {
+        if source.asset.cash_currency().is_some() && destination.asset.cash_currency().is_some() {
+            Transaction::CurrencyExchange(CurrencyExchange {
+                application,
+                tx_id,
+                source,
+                destination,
+                comission,
+                usd_amount,
+                timestamp,
+            })
+        } else {
+            Transaction::Trade(Trade {
+                application,
+                tx_id,
+                source,
+                destination,
+                comission,
+                usd_amount,
+                timestamp,
+            })
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Comission {
     pub amount: Amount,
     pub usd_amount: f64,
 }
 
 /// A Trade is a transaction where one asset is exchanged for another
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
     /// The application that made the trade
     pub application: Application,
@@ -78,8 +180,29 @@ pub struct Trade {
     pub timestamp: DateTime<Utc>,
 }
 
-///
-#[derive(Debug, Clone, Serialize)]
+/// A CurrencyExchange is a transaction where one fiat currency or stablecoin is exchanged for another
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurrencyExchange {
+    /// The application that made the exchange
+    pub application: Application,
+    /// The unique id of the transaction
+    pub tx_id: String,
+
+    /// The currency being sold
+    pub source: Amount,
+    /// The currency being bought
+    pub destination: Amount,
+    /// The comission paid for the exchange
+    pub comission: Option<Comission>,
+    /// The value of the whole exchange in usd, comission included
+    pub usd_amount: f64,
+
+    /// The timestamp the trasaction took place
+    pub timestamp: DateTime<Utc>,
+}
+
+/// An Airdrop is a transaction where an asset is given to an account for free
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Airdrop {
     /// The unique id of the transaction
     pub tx_id: String,
@@ -97,7 +220,7 @@ pub struct Airdrop {
 }
 
 /// A Bridge is a transaction that moves an asset from one network to another
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Bridge {
     /// The application that made the bridge
     pub application: Application,
@@ -114,5 +237,27 @@ pub struct Bridge {
     pub comission: Amount,
 
     /// The timestamp the trasaction took place
-    timestamp: DateTime<Utc>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A Transfer is a transaction that moves an asset into or out of a ValueStore
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transfer {
+    /// The unique id of the transaction
+    pub tx_id: String,
+
+    /// The place the asset is moved into or out of
+    pub store: ValueStore,
+    /// The amount of the asset being moved
+    pub amount: Amount,
+    /// The comission paid for the transfer
+    pub comission: Option<Comission>,
+    /// The value of the whole transfer in usd
+    pub usd_amount: f64,
+
+    /// The timestamp the trasaction took place
+    pub timestamp: DateTime<Utc>,
+
+    /// The reason for the transfer
+    pub note: String,
 }
@@ -1,20 +1,26 @@
-use chrono::{NaiveDate, NaiveTime};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 use csv::Writer;
 use sqlx::{Pool, Sqlite};
 
 use crate::{
-    data::{Airdrop, Asset, Trade, Transaction},
-    input::{self, InputError},
+    data::{Airdrop, Asset, Comission, CurrencyExchange, Trade, Transaction},
+    input::InputError,
+    normalized::{self, Record},
+    prices::PriceStore,
 };
 
 #[derive(Debug, Clone, serde::Serialize)]
 enum ExportTradeType {
     Buy,
     Sell,
+    #[serde(rename = "Transfer (Outbound)")]
+    TransferOutbound,
 }
 
 #[derive(Debug, serde::Serialize)]
 struct ExportTransaction {
+    /// The global id of the row, to tell on a re-import whether the row is new
+    pub id: String,
     pub application: String,
     pub tx_id: String,
 
@@ -30,78 +36,127 @@ struct ExportTransaction {
     pub fiat_amount: f64,
     pub comission_amount: f64,
 
+    /// The receiving account of a cash transfer
+    pub offset_account: Option<String>,
+    /// EUR or USD of the receiving account
+    pub offset_currency: Option<String>,
+    /// The amount credited to the receiving account
+    pub offset_amount: Option<f64>,
+    /// The rate from the currency into the offset currency
+    pub exchange_rate: Option<f64>,
+
     pub note: String,
 
     pub date: NaiveDate,
     pub time: NaiveTime,
 }
 
-fn is_usd(asset: &Asset) -> bool {
-    asset.name == "USD"
+/// The comission in the asset of the given cash account, a fee in another asset is converted
+/// at the prices of the day and fails without one
+fn comission_in(
+    prices: &PriceStore,
+    comission: Option<Comission>,
+    account: &Asset,
+    at: DateTime<Utc>,
+) -> Result<f64, InputError> {
+    let Some(comission) = comission else {
+        return Ok(0.0);
+    };
+    if comission.amount.asset.name == account.name {
+        return Ok(comission.amount.amount);
+    }
+
+    let date = at.date_naive();
+    let missing = |asset: &Asset| InputError::MissingRateError {
+        currency: asset.name.clone(),
+        date,
+    };
+    let usd = prices
+        .usd_value(&comission.amount, date)
+        .ok_or_else(|| missing(&comission.amount.asset))?;
+    let price = prices
+        .usd_price(account, date)
+        .filter(|price| *price != 0.0)
+        .ok_or_else(|| missing(account))?;
+
+    Ok(usd / price)
 }
 
-fn map_trade(trade: Trade) -> Vec<ExportTransaction> {
-    let currency: String;
-    let asset: String;
-    let ticker: String;
-    let crypto_amount: f64;
-    let fiat_amount: f64;
-    let comission_amount: f64;
-
-    if is_usd(&trade.source.asset) {
-        currency = trade.source.asset.name;
-
-        asset = trade.destination.asset.name;
-        ticker = asset.clone();
-
-        crypto_amount = trade.destination.amount;
-        comission_amount = trade.comission.map(|com| com.usd_amount).unwrap_or(0.0);
-        fiat_amount = trade.source.amount - comission_amount;
-
-        vec![ExportTransaction {
-            application: trade.application.clone().0,
-            tx_id: trade.tx_id.clone(),
-            currency: currency.clone(),
-            account: currency.clone(),
+/// Trades paid or sold for cash, including stablecoins, are booked against the cash account
+/// of that asset in the currency it is pegged to
+fn map_trade(prices: &PriceStore, trade: Trade) -> Result<Vec<ExportTransaction>, InputError> {
+    if let Some(currency) = trade.source.asset.cash_currency() {
+        let currency = currency.to_string();
+        let comission_amount = comission_in(
+            prices,
+            trade.comission,
+            &trade.source.asset,
+            trade.timestamp,
+        )?;
+        let account = trade.source.asset.name;
+        let asset = trade.destination.asset.name;
+
+        let fiat_amount = trade.source.amount - comission_amount;
+
+        Ok(vec![ExportTransaction {
+            id: String::new(),
+            application: trade.application.0,
+            tx_id: trade.tx_id,
+            currency,
+            account,
             asset: asset.clone(),
-            ticker: ticker.clone(),
+            ticker: asset,
             r#type: ExportTradeType::Buy,
-            crypto_amount,
+            crypto_amount: trade.destination.amount,
             fiat_amount,
             comission_amount,
+            offset_account: None,
+            offset_currency: None,
+            offset_amount: None,
+            exchange_rate: None,
             note: "".to_string(),
             date: trade.timestamp.date_naive(),
             time: trade.timestamp.time(),
-        }]
-    } else if is_usd(&trade.destination.asset) {
-        currency = trade.destination.clone().asset.name;
-        asset = trade.source.asset.name;
-        ticker = asset.clone();
-
-        crypto_amount = trade.source.amount;
-        comission_amount = trade.comission.map(|com| com.usd_amount).unwrap_or(0.0);
-        fiat_amount = trade.destination.amount - comission_amount;
-
-        vec![ExportTransaction {
-            application: trade.application.clone().0,
-            tx_id: trade.tx_id.clone(),
-            currency: currency.clone(),
-            account: currency.clone(),
+        }])
+    } else if let Some(currency) = trade.destination.asset.cash_currency() {
+        let currency = currency.to_string();
+        let comission_amount = comission_in(
+            prices,
+            trade.comission,
+            &trade.destination.asset,
+            trade.timestamp,
+        )?;
+        let account = trade.destination.asset.name;
+        let asset = trade.source.asset.name;
+
+        let fiat_amount = trade.destination.amount - comission_amount;
+
+        Ok(vec![ExportTransaction {
+            id: String::new(),
+            application: trade.application.0,
+            tx_id: trade.tx_id,
+            currency,
+            account,
             asset: asset.clone(),
-            ticker: ticker.clone(),
+            ticker: asset,
             r#type: ExportTradeType::Sell,
-            crypto_amount,
+            crypto_amount: trade.source.amount,
             fiat_amount,
             comission_amount,
+            offset_account: None,
+            offset_currency: None,
+            offset_amount: None,
+            exchange_rate: None,
             note: "".to_string(),
             date: trade.timestamp.date_naive(),
             time: trade.timestamp.time(),
-        }]
+        }])
     } else {
         let comission = trade.comission.map(|com| com.usd_amount).unwrap_or(0.0);
 
-        vec![
+        Ok(vec![
             ExportTransaction {
+                id: String::new(),
                 application: trade.application.clone().0,
                 tx_id: trade.tx_id.clone(),
                 currency: "USD".to_string(),
@@ -112,11 +167,16 @@ fn map_trade(trade: Trade) -> Vec<ExportTransaction> {
                 crypto_amount: trade.source.amount,
                 fiat_amount: trade.usd_amount - comission,
                 comission_amount: comission,
+                offset_account: None,
+                offset_currency: None,
+                offset_amount: None,
+                exchange_rate: None,
                 note: "".to_string(),
                 date: trade.timestamp.date_naive(),
                 time: trade.timestamp.time(),
             },
             ExportTransaction {
+                id: String::new(),
                 application: trade.application.0,
                 tx_id: trade.tx_id,
                 currency: "USD".to_string(),
@@ -127,16 +187,21 @@ fn map_trade(trade: Trade) -> Vec<ExportTransaction> {
                 crypto_amount: trade.destination.amount,
                 fiat_amount: trade.usd_amount - comission,
                 comission_amount: 0.0,
+                offset_account: None,
+                offset_currency: None,
+                offset_amount: None,
+                exchange_rate: None,
                 note: "".to_string(),
                 date: trade.timestamp.date_naive(),
                 time: trade.timestamp.time(),
             },
-        ]
+        ])
     }
 }
 
 fn map_airdrop(airdrop: Airdrop) -> Vec<ExportTransaction> {
     vec![ExportTransaction {
+        id: String::new(),
         application: airdrop.note.clone(),
         tx_id: airdrop.tx_id,
         currency: "USD".to_string(),
@@ -149,26 +214,106 @@ fn map_airdrop(airdrop: Airdrop) -> Vec<ExportTransaction> {
         // It needs to be 0.01, otherwise portfolio performance will claim an error
         fiat_amount: 0.01,
         comission_amount: 0.0,
+        offset_account: None,
+        offset_currency: None,
+        offset_amount: None,
+        exchange_rate: None,
         note: "".to_string(),
         date: airdrop.timestamp.date_naive(),
         time: airdrop.timestamp.time(),
     }]
 }
 
-fn map_transaction(transaction: Transaction) -> Vec<ExportTransaction> {
-    match transaction {
-        Transaction::Trade(trade) => map_trade(trade),
-        Transaction::Airdrop(airdrop) => map_airdrop(airdrop),
-        Transaction::Bridge(_) => todo!(),
+fn map_currency_exchange(
+    prices: &PriceStore,
+    exchange: CurrencyExchange,
+) -> Result<Vec<ExportTransaction>, InputError> {
+    let currency = exchange
+        .source
+        .asset
+        .cash_currency()
+        .unwrap_or("USD")
+        .to_string();
+    let offset_currency = exchange
+        .destination
+        .asset
+        .cash_currency()
+        .unwrap_or("USD")
+        .to_string();
+
+    let comission_amount = comission_in(
+        prices,
+        exchange.comission,
+        &exchange.source.asset,
+        exchange.timestamp,
+    )?;
+    let fiat_amount = exchange.source.amount - comission_amount;
+
+    Ok(vec![ExportTransaction {
+        id: String::new(),
+        application: exchange.application.0,
+        tx_id: exchange.tx_id,
+        currency,
+        account: exchange.source.asset.name,
+        asset: "".to_string(),
+        ticker: "".to_string(),
+        r#type: ExportTradeType::TransferOutbound,
+        crypto_amount: 0.0,
+        fiat_amount,
+        comission_amount,
+        offset_account: Some(exchange.destination.asset.name),
+        offset_currency: Some(offset_currency),
+        offset_amount: Some(exchange.destination.amount),
+        // Without an amount left after the fee there is no rate
+        exchange_rate: (fiat_amount != 0.0).then(|| exchange.destination.amount / fiat_amount),
+        note: "".to_string(),
+        date: exchange.timestamp.date_naive(),
+        time: exchange.timestamp.time(),
+    }])
+}
+
+fn map_transaction(
+    prices: &PriceStore,
+    record: Record,
+) -> Result<Vec<ExportTransaction>, InputError> {
+    let rows = match record.transaction.clone() {
+        Transaction::Trade(trade) => map_trade(prices, trade),
+        Transaction::Airdrop(airdrop) => Ok(map_airdrop(airdrop)),
+        Transaction::CurrencyExchange(exchange) => map_currency_exchange(prices, exchange),
+        // Moving assets between own accounts does not change the portfolio
+        Transaction::Bridge(_) | Transaction::Deposit(_) | Transaction::Withdrawal(_) => Ok(vec![]),
     }
+    .map_err(|error| InputError::InvalidError(format!("cannot export {}: {error}", record.id())))?;
+
+    // A transaction has at most one row of each type, which tells the leg
+    let rows = rows
+        .into_iter()
+        .map(|row| {
+            let leg = match row.r#type {
+                ExportTradeType::Buy => "buy",
+                ExportTradeType::Sell => "sell",
+                ExportTradeType::TransferOutbound => "transfer",
+            };
+            let id = record.global_id(leg);
+            // Portfolio Performance only keeps the note, so the id goes in there as well
+            let note = match row.note.as_str() {
+                "" => id.clone(),
+                note => format!("{note} [{id}]"),
+            };
+
+            ExportTransaction { id, note, ..row }
+        })
+        .collect();
+
+    Ok(rows)
 }
 
 pub async fn export_data(db: &Pool<Sqlite>) -> Result<(), InputError> {
-    let trades = input::get_all_trades(db)
-        .await?
-        .into_iter()
-        .flat_map(map_transaction)
-        .collect::<Vec<_>>();
+    let prices = PriceStore::load(db).await?;
+    let mut trades = vec![];
+    for record in normalized::load_all(db).await? {
+        trades.extend(map_transaction(&prices, record)?);
+    }
 
     let mut wtr = Writer::from_path("trades.csv")?;
 
@@ -182,3 +327,118 @@ pub async fn export_data(db: &Pool<Sqlite>) -> Result<(), InputError> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone};
+
+    use super::*;
+    use crate::data::{Amount, Application};
+
+    fn amount(amount: f64, name: &str) -> Amount {
+        Amount {
+            amount,
+            asset: Asset {
+                name: name.to_string(),
+                contract_address: None,
+            },
+        }
+    }
+
+    fn eur(quantity: f64) -> Amount {
+        amount(quantity, "EUR")
+    }
+
+    #[test]
+    fn stablecoins_are_cash_accounts_in_trades() {
+        let record = |tx_id: &str, source, destination| Record {
+            source: "coinbase".to_string(),
+            account: "coinbase".to_string(),
+            transaction: Transaction::exchange(
+                Application("Coinbase".to_string()),
+                tx_id.to_string(),
+                source,
+                destination,
+                None,
+                1100.0,
+                Utc::now(),
+            ),
+        };
+
+        let prices = PriceStore::default();
+        let deposit = map_transaction(&prices, record("c1", eur(1000.0), amount(1100.0, "USDC")));
+        let buy = map_transaction(
+            &prices,
+            record("t1", amount(1100.0, "USDC"), amount(0.05, "BTC")),
+        );
+        let (deposit, buy) = (deposit.unwrap(), buy.unwrap());
+
+        assert_eq!(deposit.len(), 1);
+        assert!(matches!(
+            deposit[0].r#type,
+            ExportTradeType::TransferOutbound
+        ));
+        assert_eq!(deposit[0].offset_account.as_deref(), Some("USDC"));
+        assert_eq!(deposit[0].offset_currency.as_deref(), Some("USD"));
+
+        assert_eq!(buy.len(), 1);
+        assert!(matches!(buy[0].r#type, ExportTradeType::Buy));
+        assert_eq!(buy[0].account, "USDC");
+        assert_eq!(buy[0].currency, "USD");
+        assert_eq!(buy[0].asset, "BTC");
+        assert_eq!(buy[0].fiat_amount, 1100.0);
+        assert_eq!(buy[0].crypto_amount, 0.05);
+    }
+
+    #[test]
+    fn fees_in_another_asset_are_converted_into_the_currency_of_the_row() {
+        let at = Utc.with_ymd_and_hms(2024, 3, 4, 12, 0, 0).unwrap();
+        let trade = Trade {
+            application: Application("Binance".to_string()),
+            tx_id: "t1".to_string(),
+            source: eur(1000.0),
+            destination: amount(0.02, "BTC"),
+            comission: Some(Comission {
+                amount: amount(0.01, "BNB"),
+                usd_amount: 0.0,
+            }),
+            usd_amount: 1200.0,
+            timestamp: at,
+        };
+
+        let mut prices = PriceStore::default();
+        assert!(map_trade(&prices, trade.clone()).is_err());
+
+        let day = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        prices.insert(&amount(0.0, "BNB").asset, day, 300.0);
+        assert!(map_trade(&prices, trade.clone()).is_err());
+
+        prices.insert(&eur(0.0).asset, day, 1.2);
+        let rows = map_trade(&prices, trade).unwrap();
+        assert_eq!(rows[0].comission_amount, 2.5);
+        assert_eq!(rows[0].fiat_amount, 997.5);
+    }
+
+    #[test]
+    fn exchange_without_amount_after_fee_has_no_rate() {
+        let rows = map_currency_exchange(
+            &PriceStore::default(),
+            CurrencyExchange {
+                application: Application("Coinbase".to_string()),
+                tx_id: "x".to_string(),
+                source: eur(1.0),
+                destination: amount(0.0, "USD"),
+                comission: Some(Comission {
+                    amount: eur(1.0),
+                    usd_amount: 1.09,
+                }),
+                usd_amount: 1.09,
+                timestamp: Utc::now(),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(rows[0].fiat_amount, 0.0);
+        assert_eq!(rows[0].exchange_rate, None);
+    }
+}
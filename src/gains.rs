@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Utc};
+use sqlx::{Pool, Sqlite};
+
+use crate::{
+    command_line_interface::CostMethod, input::InputError, lots::LotEngine, normalized,
+    prices::PriceStore,
+};
+
+/// The realized and unrealized gains per asset
+#[derive(Default)]
+struct AssetGains {
+    realized: f64,
+    quantity: f64,
+    cost: f64,
+    /// Whether some of the held lots have no cost
+    uncosted: bool,
+    value: Option<f64>,
+}
+
+/// An usd amount, or what it is missing for
+fn usd(amount: Option<f64>) -> String {
+    amount.map_or("unvalued".to_string(), |amount| format!("{amount:.2}"))
+}
+
+/// Prints every disposal with its gain, then the realized and unrealized gains per asset
+pub async fn print_gains(
+    db: &Pool<Sqlite>,
+    method: CostMethod,
+    year: Option<i32>,
+) -> Result<(), InputError> {
+    let records = normalized::load_all(db).await?;
+    let prices = PriceStore::load(db).await?;
+    let engine = LotEngine::run(&records, method);
+    let in_year = |date: chrono::DateTime<Utc>| year.is_none_or(|year| date.year() == year);
+
+    println!(
+        "{:<10} {:<20} {:<10} {:>18} {:>10} {:>14} {:>14} {:>14}",
+        "Disposed", "Store", "Asset", "Quantity", "Acquired", "Proceeds", "Cost", "Gain"
+    );
+
+    let mut gains = BTreeMap::<String, AssetGains>::new();
+    let mut unknown = 0;
+    let mut unvalued = 0;
+    for disposal in engine.disposals.iter().filter(|d| in_year(d.disposed_at)) {
+        let acquired = match disposal.acquired_at {
+            Some(acquired_at) => acquired_at.date_naive().to_string(),
+            None => {
+                unknown += 1;
+                "unknown".to_string()
+            }
+        };
+
+        println!(
+            "{:<10} {:<20} {:<10} {:>18.8} {:>10} {:>14} {:>14} {:>14}",
+            disposal.disposed_at.date_naive(),
+            disposal.store,
+            disposal.asset.name,
+            disposal.quantity,
+            acquired,
+            usd(disposal.proceeds),
+            usd(disposal.cost),
+            usd(disposal.gain())
+        );
+        // Disposals without a value are left out rather than taken as sold for nothing
+        let gains = gains.entry(disposal.asset.name.clone()).or_default();
+        match disposal.gain() {
+            Some(gain) => gains.realized += gain,
+            None => unvalued += 1,
+        }
+    }
+
+    let today = Utc::now().date_naive();
+    // Money has no gains of its own
+    for (_, lot) in engine.holdings().filter(|(_, lot)| !lot.asset.is_fiat()) {
+        let gains = gains.entry(lot.asset.name.clone()).or_default();
+        gains.quantity += lot.quantity;
+        match lot.cost {
+            Some(cost) => gains.cost += cost,
+            None => gains.uncosted = true,
+        }
+        if let Some(price) = prices.usd_price(&lot.asset, today) {
+            *gains.value.get_or_insert(0.0) += price * lot.quantity;
+        }
+    }
+
+    println!();
+    println!(
+        "{:<10} {:>14} {:>18} {:>14} {:>14} {:>14}",
+        "Asset", "Realized", "Held", "Cost", "Value", "Unrealized"
+    );
+    let mut uncosted = 0;
+    for (asset, gains) in &gains {
+        let cost = (!gains.uncosted).then_some(gains.cost);
+        let (value, unrealized) = match gains.value {
+            Some(value) => (
+                format!("{value:.2}"),
+                cost.map_or("".to_string(), |cost| format!("{:.2}", value - cost)),
+            ),
+            None if gains.quantity > 0.0 => ("no price".to_string(), "".to_string()),
+            None => ("".to_string(), "".to_string()),
+        };
+        if gains.uncosted {
+            uncosted += 1;
+        }
+
+        println!(
+            "{:<10} {:>14.2} {:>18.8} {:>14} {:>14} {:>14}",
+            asset,
+            gains.realized,
+            gains.quantity,
+            usd(cost),
+            value,
+            unrealized
+        );
+    }
+
+    let realized = gains.values().map(|gains| gains.realized).sum::<f64>();
+    let income = engine
+        .income
+        .iter()
+        .filter(|income| in_year(income.received_at))
+        .map(|income| income.value)
+        .sum::<f64>();
+    println!();
+    println!("Realized gains: {realized:.2} USD");
+    println!("Income from airdrops and rewards: {income:.2} USD");
+    if unknown > 0 {
+        println!("{unknown} disposals have no lot to come from, their history is missing");
+    }
+    if unvalued > 0 {
+        println!(
+            "{unvalued} disposals have no value and are left out, the trades or lots they come \
+            from were not valued, set the price of an asset with `price`"
+        );
+    }
+    if uncosted > 0 {
+        println!(
+            "{uncosted} assets are held in lots without a cost, their unrealized gain is unknown"
+        );
+    }
+    if !engine.unmatched.is_empty() {
+        println!(
+            "{} withdrawals arrived in no own store and count as disposals: {}",
+            engine.unmatched.len(),
+            engine.unmatched.join(", ")
+        );
+    }
+    if !engine.unsourced.is_empty() {
+        println!(
+            "{} transfers and bridges moved more than their store held, the rest has no \
+            history and no cost: {}",
+            engine.unsourced.len(),
+            engine.unsourced.join(", ")
+        );
+    }
+
+    Ok(())
+}
@@ -1,24 +1,103 @@
-use std::sync::Arc;
+use std::{
+    fmt::{self, Display},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
 
 use futures::join;
 use hmac::Hmac;
 use sha2::Sha256;
-use sqlx::{Pool, Sqlite};
+use sqlx::{query, Pool, Sqlite};
 
 use crate::{command_line_interface::Exchange, data::Transaction};
 
+use self::http::HttpOptions;
+
+pub mod binance;
+pub mod bitcoin;
+pub mod clock;
 pub mod coinbase;
+pub mod evm;
+pub mod http;
+pub mod kraken;
+pub mod manual;
 pub mod mexc;
+pub mod solana;
+pub mod statement;
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// The origin of the data an error occurred in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Binance,
+    Bison,
+    Bitcoin,
+    Bitpanda,
+    Coinbase,
+    Evm,
+    Kraken,
+    Manual,
+    Mexc,
+    Solana,
+}
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Binance => write!(f, "Binance"),
+            Source::Bison => write!(f, "Bison"),
+            Source::Bitcoin => write!(f, "Bitcoin"),
+            Source::Bitpanda => write!(f, "Bitpanda"),
+            Source::Coinbase => write!(f, "Coinbase"),
+            Source::Evm => write!(f, "EVM"),
+            Source::Kraken => write!(f, "Kraken"),
+            Source::Manual => write!(f, "Manual"),
+            Source::Mexc => write!(f, "MEXC"),
+            Source::Solana => write!(f, "Solana"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum InputError {
     RequestError(Arc<reqwest::Error>),
     SqlError(Arc<sqlx::Error>),
     CsvError(Arc<csv::Error>),
     IoError(Arc<std::io::Error>),
-    StatusError(u16),
+    JsonError(Arc<serde_json::Error>),
+    /// The exchange answered with a non success status and the given body
+    StatusError(u16, String),
+    /// A field of a record could not be parsed
+    ParseError {
+        field: &'static str,
+        value: String,
+    },
+    /// A required setting, e.g. an api key, is missing
+    ConfigError(String),
+    /// A replayed request was never recorded
+    MissingRecordingError(PathBuf),
+    /// A record uses something that is not supported yet, e.g. an unknown currency
+    UnsupportedError(String),
+    /// A transaction entered by hand does not check out, e.g. it uses an unknown asset
+    InvalidError(String),
+    /// No usd rate of the fiat currency is known around the day
+    MissingRateError {
+        currency: String,
+        date: NaiveDate,
+    },
+    /// An error while gathering or mapping the data of a whole source
+    SourceError {
+        source: Source,
+        error: Box<InputError>,
+    },
+    /// An error while gathering or mapping a single record of a source
+    RecordError {
+        source: Source,
+        record_id: String,
+        error: Box<InputError>,
+    },
 }
 impl From<reqwest::Error> for InputError {
     fn from(error: reqwest::Error) -> Self {
@@ -40,39 +119,522 @@ impl From<std::io::Error> for InputError {
         InputError::IoError(Arc::new(error))
     }
 }
+impl From<serde_json::Error> for InputError {
+    fn from(error: serde_json::Error) -> Self {
+        InputError::JsonError(Arc::new(error))
+    }
+}
+impl Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::RequestError(error) => write!(f, "request failed: {error}"),
+            InputError::SqlError(error) => write!(f, "database error: {error}"),
+            InputError::CsvError(error) => write!(f, "csv error: {error}"),
+            InputError::IoError(error) => write!(f, "io error: {error}"),
+            InputError::JsonError(error) => write!(f, "json error: {error}"),
+            InputError::StatusError(status, body) => write!(f, "status {status}: {body}"),
+            InputError::ParseError { field, value } => {
+                write!(f, "could not parse {field} '{value}'")
+            }
+            InputError::ConfigError(message) => write!(f, "configuration error: {message}"),
+            InputError::MissingRecordingError(path) => {
+                write!(f, "no recording at {}", path.display())
+            }
+            InputError::UnsupportedError(message) => write!(f, "unsupported: {message}"),
+            InputError::InvalidError(message) => write!(f, "invalid: {message}"),
+            InputError::MissingRateError { currency, date } => write!(
+                f,
+                "no usd rate of {currency} around {date}, fetch them with `rates` \
+                or set one with `price {currency}`"
+            ),
+            InputError::SourceError { source, error } => write!(f, "{source}: {error}"),
+            InputError::RecordError {
+                source,
+                record_id,
+                error,
+            } => write!(f, "{source} record '{record_id}': {error}"),
+        }
+    }
+}
+impl std::error::Error for InputError {}
+
+/// The kind of an error, used to group the error summary and to pick the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    Config,
+    Network,
+    Database,
+    File,
+    Data,
+}
+impl ErrorCategory {
+    /// The categories from the most to the least severe, the error summary exits with the
+    /// code of the most severe one: without a configuration or database nothing runs, while
+    /// network, file and data errors only hit some of the records
+    pub const BY_SEVERITY: [ErrorCategory; 5] = [
+        ErrorCategory::Config,
+        ErrorCategory::Database,
+        ErrorCategory::Network,
+        ErrorCategory::File,
+        ErrorCategory::Data,
+    ];
+
+    /// The exit codes as documented in the help of the command line interface
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ErrorCategory::Config => 7,
+            ErrorCategory::Network => 3,
+            ErrorCategory::Database => 4,
+            ErrorCategory::File => 5,
+            ErrorCategory::Data => 6,
+        }
+    }
+}
+impl Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCategory::Config => write!(f, "Configuration"),
+            ErrorCategory::Network => write!(f, "Network"),
+            ErrorCategory::Database => write!(f, "Database"),
+            ErrorCategory::File => write!(f, "File"),
+            ErrorCategory::Data => write!(f, "Data"),
+        }
+    }
+}
+
+impl InputError {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            InputError::ConfigError(_) => ErrorCategory::Config,
+            InputError::RequestError(_) | InputError::StatusError(..) => ErrorCategory::Network,
+            InputError::SqlError(_) => ErrorCategory::Database,
+            InputError::CsvError(_)
+            | InputError::IoError(_)
+            | InputError::MissingRecordingError(_) => ErrorCategory::File,
+            InputError::JsonError(_)
+            | InputError::ParseError { .. }
+            | InputError::UnsupportedError(_)
+            | InputError::InvalidError(_)
+            | InputError::MissingRateError { .. } => ErrorCategory::Data,
+            InputError::SourceError { error, .. } | InputError::RecordError { error, .. } => {
+                error.category()
+            }
+        }
+    }
+
+    /// Adds the source the error occurred in
+    pub fn for_source(self, source: Source) -> Self {
+        match self {
+            InputError::SourceError { .. } | InputError::RecordError { .. } => self,
+            error => InputError::SourceError {
+                source,
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Adds the source and the id of the record the error occurred in
+    pub fn for_record(self, source: Source, record_id: &str) -> Self {
+        InputError::RecordError {
+            source,
+            record_id: record_id.to_string(),
+            error: Box::new(self),
+        }
+    }
+}
+
+/// Reads a setting from the environment or the `.env` file at runtime
+pub fn env_var(name: &str) -> Result<String, InputError> {
+    dotenv::var(name).map_err(|_| InputError::ConfigError(format!("{name} is not set")))
+}
+
+/// Whether the setting a source needs is present, sources without it are left out of a full fetch
+pub fn is_configured(name: &str) -> bool {
+    dotenv::var(name).is_ok_and(|value| !value.is_empty())
+}
+
+pub fn parse_number(field: &'static str, value: &str) -> Result<f64, InputError> {
+    value.parse().map_err(|_| InputError::ParseError {
+        field,
+        value: value.to_string(),
+    })
+}
+
+pub fn parse_timestamp(field: &'static str, value: &str) -> Result<DateTime<Utc>, InputError> {
+    value.parse().map_err(|_| InputError::ParseError {
+        field,
+        value: value.to_string(),
+    })
+}
+
+/// Decides whether an error aborts the run or is collected so that all good records are processed
+#[derive(Debug, Clone, Default)]
+pub struct ErrorCollector {
+    keep_going: bool,
+    errors: Arc<Mutex<Vec<InputError>>>,
+}
+impl ErrorCollector {
+    pub fn new(keep_going: bool) -> Self {
+        ErrorCollector {
+            keep_going,
+            errors: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Passes good results through, bad results are either returned or collected
+    pub fn collect<T>(&self, result: Result<T, InputError>) -> Result<Option<T>, InputError> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if self.keep_going => {
+                self.errors.lock().expect("error list poisoned").push(error);
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
 
-pub async fn gather_data(db: &Pool<Sqlite>, exchange: Option<Exchange>) -> Result<(), InputError> {
+    pub fn take_errors(&self) -> Vec<InputError> {
+        std::mem::take(&mut *self.errors.lock().expect("error list poisoned"))
+    }
+}
+
+/// Prints all errors grouped by category and returns the exit code of the most severe one
+pub fn print_error_summary(errors: &[InputError]) -> Option<u8> {
+    let categories = ErrorCategory::BY_SEVERITY
+        .into_iter()
+        .filter(|category| errors.iter().any(|e| e.category() == *category))
+        .collect::<Vec<_>>();
+
+    for category in &categories {
+        let errors = errors
+            .iter()
+            .filter(|e| e.category() == *category)
+            .collect::<Vec<_>>();
+
+        eprintln!("{category} errors ({}):", errors.len());
+        for error in errors {
+            eprintln!("  {error}");
+        }
+    }
+
+    categories.first().map(|c| c.exit_code())
+}
+
+pub async fn gather_data(
+    db: &Pool<Sqlite>,
+    exchange: Option<Exchange>,
+    options: &HttpOptions,
+    errors: &ErrorCollector,
+) -> Result<(), InputError> {
     if let Some(exchange) = exchange {
         match exchange {
-            Exchange::MEXC => mexc::gather_data(db).await?,
-            Exchange::Coinbase => coinbase::gather_data(db).await?,
+            Exchange::MEXC => mexc::gather_data(db, &mexc::requests::client(options), errors)
+                .await
+                .map_err(|e| e.for_source(Source::Mexc))?,
+            Exchange::Coinbase => coinbase::gather_data(db, &coinbase::client(options), errors)
+                .await
+                .map_err(|e| e.for_source(Source::Coinbase))?,
+            Exchange::Kraken => kraken::gather_data(db, &kraken::requests::client(options), errors)
+                .await
+                .map_err(|e| e.for_source(Source::Kraken))?,
+            Exchange::Binance => {
+                binance::gather_data(db, &binance::requests::client(options), errors)
+                    .await
+                    .map_err(|e| e.for_source(Source::Binance))?
+            }
+            Exchange::Evm => evm::gather_data(db, options, errors)
+                .await
+                .map_err(|e| e.for_source(Source::Evm))?,
+            Exchange::Bitcoin => bitcoin::gather_data(db, options, errors)
+                .await
+                .map_err(|e| e.for_source(Source::Bitcoin))?,
+            Exchange::Solana => solana::gather_data(db, options, errors)
+                .await
+                .map_err(|e| e.for_source(Source::Solana))?,
         }
     } else {
-        let mexc = mexc::gather_data(db);
-        let coinbase = coinbase::gather_data(db);
+        let mexc_client = mexc::requests::client(options);
+        let coinbase_client = coinbase::client(options);
+        let binance_client = binance::requests::client(options);
+        let kraken_client = kraken::requests::client(options);
+
+        let mexc = async {
+            mexc::gather_data(db, &mexc_client, errors)
+                .await
+                .map_err(|e| e.for_source(Source::Mexc))
+        };
+        let coinbase = async {
+            coinbase::gather_data(db, &coinbase_client, errors)
+                .await
+                .map_err(|e| e.for_source(Source::Coinbase))
+        };
 
-        let result = join!(mexc, coinbase);
-        result.0?;
-        result.1?;
+        let binance = async {
+            if !is_configured("BINANCE_API_KEY") {
+                return Ok(());
+            }
+            binance::gather_data(db, &binance_client, errors)
+                .await
+                .map_err(|e| e.for_source(Source::Binance))
+        };
+        let kraken = async {
+            if !is_configured("KRAKEN_API_KEY") {
+                return Ok(());
+            }
+            kraken::gather_data(db, &kraken_client, errors)
+                .await
+                .map_err(|e| e.for_source(Source::Kraken))
+        };
+        let evm = async {
+            if !is_configured("EVM_WALLETS") {
+                return Ok(());
+            }
+            evm::gather_data(db, options, errors)
+                .await
+                .map_err(|e| e.for_source(Source::Evm))
+        };
+        let bitcoin = async {
+            if !is_configured("BITCOIN_WALLETS") {
+                return Ok(());
+            }
+            bitcoin::gather_data(db, options, errors)
+                .await
+                .map_err(|e| e.for_source(Source::Bitcoin))
+        };
+        let solana = async {
+            if !is_configured("SOLANA_WALLETS") {
+                return Ok(());
+            }
+            solana::gather_data(db, options, errors)
+                .await
+                .map_err(|e| e.for_source(Source::Solana))
+        };
+
+        let result = join!(mexc, coinbase, binance, kraken, evm, bitcoin, solana);
+        errors.collect(result.0)?;
+        errors.collect(result.1)?;
+        errors.collect(result.2)?;
+        errors.collect(result.3)?;
+        errors.collect(result.4)?;
+        errors.collect(result.5)?;
+        errors.collect(result.6)?;
     };
 
     Ok(())
 }
 
-pub async fn get_all_trades(db: &Pool<Sqlite>) -> Result<Vec<Transaction>, InputError> {
-    let mut trades = vec![];
+/// The balance of an asset on an account as reported by an exchange
+#[derive(Debug, Clone)]
+pub struct ReportedBalance {
+    pub account: String,
+    pub asset: String,
+    pub amount: f64,
+}
 
-    trades.append(&mut mexc::get_all_trades(db).await?);
-    trades.append(&mut coinbase::get_all_trades(db).await?);
+/// Stores the balances reported by the source as one snapshot, empty balances are left out
+pub async fn save_balances(
+    db: &Pool<Sqlite>,
+    source: Source,
+    balances: &[ReportedBalance],
+) -> Result<(), InputError> {
+    let source = source.to_string();
+    let taken_at = Utc::now();
+    let mut tx = db.begin().await?;
 
-    Ok(trades)
-}
+    let snapshot_id = query!(
+        "INSERT INTO balance_snapshots (source, taken_at) VALUES ($1, $2)",
+        source,
+        taken_at
+    )
+    .execute(&mut *tx)
+    .await?
+    .last_insert_rowid();
 
-pub async fn list_all_trades(db: &Pool<Sqlite>) -> Result<(), InputError> {
-    let trades = get_all_trades(db).await?;
-    let json = serde_json::to_string(&trades).unwrap();
+    for balance in balances.iter().filter(|balance| balance.amount != 0.0) {
+        query!(
+            "INSERT INTO balance_snapshot_entries (snapshot_id, account, asset, amount)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (snapshot_id, account, asset) DO UPDATE
+            SET amount = amount + excluded.amount",
+            snapshot_id,
+            balance.account,
+            balance.asset,
+            balance.amount
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
 
-    println!("{}", json);
+    tx.commit().await?;
 
     Ok(())
 }
+
+/// A transaction together with the raw row it was mapped from
+#[derive(Debug, Clone)]
+pub struct MappedTransaction {
+    /// The table of the raw row
+    pub raw_table: &'static str,
+    /// The key of the raw row in its table, rows grouped into one transaction share it
+    pub raw_id: String,
+    /// The account of the source the transaction belongs to, e.g. a wallet
+    pub account: String,
+    pub transaction: Transaction,
+}
+
+impl MappedTransaction {
+    pub fn new(
+        raw_table: &'static str,
+        raw_id: &str,
+        account: &str,
+        transaction: Transaction,
+    ) -> Self {
+        MappedTransaction {
+            raw_table,
+            raw_id: raw_id.to_string(),
+            account: account.to_string(),
+            transaction,
+        }
+    }
+}
+
+/// The sources whose raw rows are mapped as a whole, statements of all exchanges count as one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappedSource {
+    Mexc,
+    Coinbase,
+    Binance,
+    Kraken,
+    Evm,
+    Bitcoin,
+    Solana,
+    Manual,
+    Statement,
+}
+
+impl MappedSource {
+    /// Statements come last, as they only backfill what the other sources miss
+    pub const ALL: [MappedSource; 9] = [
+        MappedSource::Mexc,
+        MappedSource::Coinbase,
+        MappedSource::Binance,
+        MappedSource::Kraken,
+        MappedSource::Evm,
+        MappedSource::Bitcoin,
+        MappedSource::Solana,
+        MappedSource::Manual,
+        MappedSource::Statement,
+    ];
+
+    /// The name the source is stored with
+    pub fn name(&self) -> &'static str {
+        match self {
+            MappedSource::Mexc => "MEXC",
+            MappedSource::Coinbase => "Coinbase",
+            MappedSource::Binance => "Binance",
+            MappedSource::Kraken => "Kraken",
+            MappedSource::Evm => "EVM",
+            MappedSource::Bitcoin => "Bitcoin",
+            MappedSource::Solana => "Solana",
+            MappedSource::Manual => "Manual",
+            MappedSource::Statement => "Statement",
+        }
+    }
+
+    /// The sources a fetch writes raw rows of
+    pub fn fetched_by(exchange: Option<&Exchange>) -> Vec<MappedSource> {
+        match exchange {
+            Some(Exchange::MEXC) => vec![MappedSource::Mexc],
+            Some(Exchange::Coinbase) => vec![MappedSource::Coinbase],
+            Some(Exchange::Binance) => vec![MappedSource::Binance],
+            Some(Exchange::Kraken) => vec![MappedSource::Kraken],
+            Some(Exchange::Evm) => vec![MappedSource::Evm],
+            Some(Exchange::Bitcoin) => vec![MappedSource::Bitcoin],
+            Some(Exchange::Solana) => vec![MappedSource::Solana],
+            None => MappedSource::ALL.to_vec(),
+        }
+    }
+}
+
+/// Maps all raw rows of the source
+pub async fn map_source(
+    db: &Pool<Sqlite>,
+    source: MappedSource,
+    errors: &ErrorCollector,
+) -> Result<Vec<MappedTransaction>, InputError> {
+    match source {
+        MappedSource::Mexc => mexc::get_all_trades(db, errors)
+            .await
+            .map_err(|e| e.for_source(Source::Mexc)),
+        MappedSource::Coinbase => coinbase::get_all_trades(db, errors)
+            .await
+            .map_err(|e| e.for_source(Source::Coinbase)),
+        MappedSource::Binance => binance::get_all_trades(db, errors)
+            .await
+            .map_err(|e| e.for_source(Source::Binance)),
+        MappedSource::Kraken => kraken::get_all_trades(db, errors)
+            .await
+            .map_err(|e| e.for_source(Source::Kraken)),
+        MappedSource::Evm => evm::get_all_trades(db, errors)
+            .await
+            .map_err(|e| e.for_source(Source::Evm)),
+        MappedSource::Bitcoin => bitcoin::get_all_trades(db, errors)
+            .await
+            .map_err(|e| e.for_source(Source::Bitcoin)),
+        MappedSource::Solana => solana::get_all_trades(db, errors)
+            .await
+            .map_err(|e| e.for_source(Source::Solana)),
+        MappedSource::Manual => manual::get_all_trades(db, errors)
+            .await
+            .map_err(|e| e.for_source(Source::Manual)),
+        MappedSource::Statement => statement::get_all_trades(db, errors).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bad_record(id: &str) -> Result<f64, InputError> {
+        parse_number("amount", "x").map_err(|e| e.for_record(Source::Kraken, id))
+    }
+
+    #[test]
+    fn keep_going_collects_bad_records_and_passes_good_ones() {
+        let errors = ErrorCollector::new(true);
+
+        assert_eq!(
+            errors.collect(parse_number("amount", "1.5")).unwrap(),
+            Some(1.5)
+        );
+        assert_eq!(errors.collect(bad_record("L1")).unwrap(), None);
+        assert_eq!(errors.collect(bad_record("L2")).unwrap(), None);
+
+        let collected = errors.take_errors();
+        assert_eq!(collected.len(), 2);
+        assert!(errors.take_errors().is_empty());
+    }
+
+    #[test]
+    fn without_keep_going_the_first_bad_record_stops() {
+        let errors = ErrorCollector::new(false);
+
+        assert!(errors.collect(bad_record("L1")).is_err());
+        assert!(errors.take_errors().is_empty());
+    }
+
+    #[test]
+    fn the_summary_exits_with_the_code_of_the_most_severe_error() {
+        let data = || bad_record("L1").unwrap_err();
+        let network = || InputError::StatusError(503, "unavailable".to_string());
+        let config = || {
+            InputError::ConfigError("KRAKEN_API_KEY is not set".to_string())
+                .for_source(Source::Kraken)
+        };
+
+        assert_eq!(print_error_summary(&[]), None);
+        assert_eq!(print_error_summary(&[data()]), Some(6));
+        assert_eq!(print_error_summary(&[data(), network()]), Some(3));
+        assert_eq!(print_error_summary(&[network(), data(), config()]), Some(7));
+    }
+}
@@ -0,0 +1,907 @@
+use std::collections::BTreeSet;
+
+use chrono::{Duration, NaiveDateTime, TimeZone, Utc};
+use futures::{stream, StreamExt};
+use sqlx::{query, query_as, Pool, Sqlite};
+
+use crate::{
+    data::{Amount, Application, Asset, Comission, Transaction, Transfer, ValueStore},
+    prices::PriceStore,
+};
+
+use self::requests::{request, request_signed};
+
+use super::{
+    http::HttpClient, parse_number, ErrorCollector, InputError, MappedTransaction, Source,
+};
+
+pub mod requests;
+
+/// Binance opened in July 2017, there is no history before
+const HISTORY_START: &str = "2017-07-01T00:00:00Z";
+
+/// Assets trading pairs are looked up against, besides the assets of the account
+const QUOTE_ASSETS: [&str; 9] = [
+    "USDT", "BUSD", "USDC", "FDUSD", "TUSD", "BTC", "ETH", "BNB", "EUR",
+];
+
+/// The deposit status of a successful deposit
+const DEPOSIT_SUCCESS: i64 = 1;
+/// The withdrawal status of a completed withdrawal
+const WITHDRAWAL_COMPLETED: i64 = 6;
+
+fn asset(name: &str) -> Asset {
+    Asset {
+        name: name.to_string(),
+        contract_address: None,
+    }
+}
+
+fn timestamp_millis(field: &'static str, millis: i64) -> Result<chrono::DateTime<Utc>, InputError> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .ok_or(InputError::ParseError {
+            field,
+            value: millis.to_string(),
+        })
+}
+
+/// The time windows from `start` until now, the history endpoints only accept short ones
+fn windows(start: i64, days: i64) -> Vec<(i64, i64)> {
+    let now = Utc::now().timestamp_millis();
+    let length = Duration::days(days).num_milliseconds();
+
+    (0..)
+        .map(|i| start + i * length)
+        .take_while(|from| *from < now)
+        .map(|from| (from, (from + length - 1).min(now)))
+        .collect()
+}
+
+/// Starts one day before the newest stored record, or at the beginning of Binance
+fn history_start(newest: Option<i64>) -> i64 {
+    let beginning = HISTORY_START
+        .parse::<chrono::DateTime<Utc>>()
+        .expect("valid timestamp")
+        .timestamp_millis();
+
+    newest
+        .map(|newest| newest - Duration::days(1).num_milliseconds())
+        .unwrap_or(beginning)
+        .max(beginning)
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SymbolResult {
+    symbol: String,
+    base_asset: String,
+    quote_asset: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ExchangeInfoResult {
+    symbols: Vec<SymbolResult>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BalanceResult {
+    asset: String,
+    free: String,
+    locked: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AccountResult {
+    balances: Vec<BalanceResult>,
+}
+
+/// The symbols to fetch trades for, `BINANCE_SYMBOLS` (comma separated) overrides them.
+/// Otherwise every pair of an asset the account ever held with a common quote asset is used.
+async fn get_symbols(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+) -> Result<Vec<SymbolResult>, InputError> {
+    let exchange_info = request(client, "/api/v3/exchangeInfo")
+        .await?
+        .json::<ExchangeInfoResult>()?;
+
+    if let Ok(symbols) = dotenv::var("BINANCE_SYMBOLS") {
+        let symbols = symbols.split(',').map(|s| s.trim()).collect::<Vec<_>>();
+
+        return Ok(exchange_info
+            .symbols
+            .into_iter()
+            .filter(|s| symbols.contains(&s.symbol.as_str()))
+            .collect());
+    }
+
+    let account = request_signed(client, "/api/v3/account", "")
+        .await?
+        .json::<AccountResult>()?;
+
+    let mut assets = account
+        .balances
+        .into_iter()
+        .filter(|b| {
+            parse_number("free", &b.free).unwrap_or(0.0)
+                + parse_number("locked", &b.locked).unwrap_or(0.0)
+                > 0.0
+        })
+        .map(|b| b.asset)
+        .collect::<BTreeSet<_>>();
+
+    let known = query!(
+        "SELECT coin AS asset FROM binance_deposits
+        UNION SELECT coin FROM binance_withdrawals
+        UNION SELECT from_asset FROM binance_converts
+        UNION SELECT to_asset FROM binance_converts
+        UNION SELECT base_asset FROM binance_my_trades"
+    )
+    .fetch_all(db)
+    .await?;
+    assets.extend(known.into_iter().map(|row| row.asset));
+
+    Ok(exchange_info
+        .symbols
+        .into_iter()
+        .filter(|s| {
+            (assets.contains(&s.base_asset) && QUOTE_ASSETS.contains(&s.quote_asset.as_str()))
+                || (assets.contains(&s.quote_asset)
+                    && QUOTE_ASSETS.contains(&s.base_asset.as_str()))
+        })
+        .collect())
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MyTradesResult {
+    symbol: String,
+    id: i64,
+    order_id: i64,
+    order_list_id: i64,
+    price: String,
+    qty: String,
+    quote_qty: String,
+    commission: String,
+    commission_asset: String,
+    time: i64,
+    is_buyer: bool,
+    is_maker: bool,
+    is_best_match: bool,
+}
+
+/// The maximum number of trades Binance returns per request
+const TRADES_LIMIT: usize = 1000;
+
+async fn retrieve_and_save_trades_for_symbol(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+    symbol: &SymbolResult,
+) -> Result<(), InputError> {
+    let newest = query!(
+        "SELECT MAX(id) AS id FROM binance_my_trades WHERE symbol = $1",
+        symbol.symbol
+    )
+    .fetch_one(db)
+    .await?
+    .id;
+    let mut from_id = newest.map(|id| id + 1).unwrap_or(0);
+
+    loop {
+        let trades = request_signed(
+            client,
+            "/api/v3/myTrades",
+            &format!(
+                "symbol={}&fromId={from_id}&limit={TRADES_LIMIT}",
+                symbol.symbol
+            ),
+        )
+        .await?
+        .json::<Vec<MyTradesResult>>()?;
+
+        for trade in &trades {
+            query!(
+                "INSERT OR IGNORE INTO binance_my_trades (
+                    symbol, base_asset, quote_asset, id, order_id,
+                    order_list_id, price, qty, quote_qty,
+                    commission, commission_asset, time,
+                    is_buyer, is_maker, is_best_match, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, CURRENT_TIMESTAMP)",
+                trade.symbol,
+                symbol.base_asset,
+                symbol.quote_asset,
+                trade.id,
+                trade.order_id,
+                trade.order_list_id,
+                trade.price,
+                trade.qty,
+                trade.quote_qty,
+                trade.commission,
+                trade.commission_asset,
+                trade.time,
+                trade.is_buyer,
+                trade.is_maker,
+                trade.is_best_match
+            )
+            .execute(db)
+            .await?;
+        }
+
+        match trades.last() {
+            Some(last) if trades.len() == TRADES_LIMIT => from_id = last.id + 1,
+            _ => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DepositResult {
+    id: String,
+    amount: String,
+    coin: String,
+    network: String,
+    status: i64,
+    address: String,
+    tx_id: String,
+    insert_time: i64,
+}
+
+async fn retrieve_and_save_deposits(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+) -> Result<(), InputError> {
+    let newest = query!("SELECT MAX(insert_time) AS time FROM binance_deposits")
+        .fetch_one(db)
+        .await?
+        .time;
+
+    for (start, end) in windows(history_start(newest), 90) {
+        let deposits = request_signed(
+            client,
+            "/sapi/v1/capital/deposit/hisrec",
+            &format!("startTime={start}&endTime={end}&limit=1000"),
+        )
+        .await?
+        .json::<Vec<DepositResult>>()?;
+
+        for deposit in deposits {
+            query!(
+                "INSERT OR REPLACE INTO binance_deposits (
+                    id, amount, coin, network, status,
+                    address, tx_id, insert_time, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, CURRENT_TIMESTAMP)",
+                deposit.id,
+                deposit.amount,
+                deposit.coin,
+                deposit.network,
+                deposit.status,
+                deposit.address,
+                deposit.tx_id,
+                deposit.insert_time
+            )
+            .execute(db)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WithdrawalResult {
+    id: String,
+    amount: String,
+    transaction_fee: String,
+    coin: String,
+    network: String,
+    status: i64,
+    address: String,
+    tx_id: Option<String>,
+    apply_time: String,
+}
+
+fn parse_apply_time(apply_time: &str) -> Result<chrono::DateTime<Utc>, InputError> {
+    NaiveDateTime::parse_from_str(apply_time, "%Y-%m-%d %H:%M:%S")
+        .map(|time| time.and_utc())
+        .map_err(|_| InputError::ParseError {
+            field: "applyTime",
+            value: apply_time.to_string(),
+        })
+}
+
+async fn retrieve_and_save_withdrawals(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+) -> Result<(), InputError> {
+    let newest = query!("SELECT MAX(apply_time) AS time FROM binance_withdrawals")
+        .fetch_one(db)
+        .await?
+        .time
+        .and_then(|time| parse_apply_time(&time).ok())
+        .map(|time| time.timestamp_millis());
+
+    for (start, end) in windows(history_start(newest), 90) {
+        let withdrawals = request_signed(
+            client,
+            "/sapi/v1/capital/withdraw/history",
+            &format!("startTime={start}&endTime={end}&limit=1000"),
+        )
+        .await?
+        .json::<Vec<WithdrawalResult>>()?;
+
+        for withdrawal in withdrawals {
+            query!(
+                "INSERT OR REPLACE INTO binance_withdrawals (
+                    id, amount, transaction_fee, coin, network,
+                    status, address, tx_id, apply_time, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, CURRENT_TIMESTAMP)",
+                withdrawal.id,
+                withdrawal.amount,
+                withdrawal.transaction_fee,
+                withdrawal.coin,
+                withdrawal.network,
+                withdrawal.status,
+                withdrawal.address,
+                withdrawal.tx_id,
+                withdrawal.apply_time
+            )
+            .execute(db)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConvertResult {
+    quote_id: String,
+    order_id: i64,
+    order_status: String,
+    from_asset: String,
+    from_amount: String,
+    to_asset: String,
+    to_amount: String,
+    create_time: i64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ConvertFlowResult {
+    list: Vec<ConvertResult>,
+}
+
+async fn retrieve_and_save_converts(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+) -> Result<(), InputError> {
+    let newest = query!("SELECT MAX(create_time) AS time FROM binance_converts")
+        .fetch_one(db)
+        .await?
+        .time;
+
+    for (start, end) in windows(history_start(newest), 30) {
+        let converts = request_signed(
+            client,
+            "/sapi/v1/convert/tradeFlow",
+            &format!("startTime={start}&endTime={end}&limit=1000"),
+        )
+        .await?
+        .json::<ConvertFlowResult>()?;
+
+        for convert in converts.list {
+            query!(
+                "INSERT OR REPLACE INTO binance_converts (
+                    order_id, quote_id, order_status,
+                    from_asset, from_amount, to_asset, to_amount,
+                    create_time, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, CURRENT_TIMESTAMP)",
+                convert.order_id,
+                convert.quote_id,
+                convert.order_status,
+                convert.from_asset,
+                convert.from_amount,
+                convert.to_asset,
+                convert.to_amount,
+                convert.create_time
+            )
+            .execute(db)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DustDetailResult {
+    trans_id: i64,
+    from_asset: String,
+    amount: String,
+    transfered_amount: String,
+    service_charge_amount: String,
+    operate_time: i64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DustResult {
+    user_asset_dribblet_details: Vec<DustDetailResult>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DustLogResult {
+    user_asset_dribblets: Vec<DustResult>,
+}
+
+async fn retrieve_and_save_dust(db: &Pool<Sqlite>, client: &HttpClient) -> Result<(), InputError> {
+    let newest = query!("SELECT MAX(operate_time) AS time FROM binance_dust")
+        .fetch_one(db)
+        .await?
+        .time;
+
+    for (start, end) in windows(history_start(newest), 90) {
+        let dust_log = request_signed(
+            client,
+            "/sapi/v1/asset/dribblet",
+            &format!("startTime={start}&endTime={end}"),
+        )
+        .await?
+        .json::<DustLogResult>()?;
+
+        for detail in dust_log
+            .user_asset_dribblets
+            .into_iter()
+            .flat_map(|d| d.user_asset_dribblet_details)
+        {
+            query!(
+                "INSERT OR IGNORE INTO binance_dust (
+                    trans_id, from_asset, amount, transfered_amount,
+                    service_charge_amount, operate_time, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP)",
+                detail.trans_id,
+                detail.from_asset,
+                detail.amount,
+                detail.transfered_amount,
+                detail.service_charge_amount,
+                detail.operate_time
+            )
+            .execute(db)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn gather_data(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+    errors: &ErrorCollector,
+) -> Result<(), InputError> {
+    errors.collect(
+        retrieve_and_save_deposits(db, client)
+            .await
+            .map_err(|e| e.for_record(Source::Binance, "deposits")),
+    )?;
+    errors.collect(
+        retrieve_and_save_withdrawals(db, client)
+            .await
+            .map_err(|e| e.for_record(Source::Binance, "withdrawals")),
+    )?;
+    errors.collect(
+        retrieve_and_save_converts(db, client)
+            .await
+            .map_err(|e| e.for_record(Source::Binance, "converts")),
+    )?;
+    errors.collect(
+        retrieve_and_save_dust(db, client)
+            .await
+            .map_err(|e| e.for_record(Source::Binance, "dust")),
+    )?;
+
+    let symbols = get_symbols(db, client).await?;
+    let retrieved_trades = symbols.iter().map(|symbol| async move {
+        retrieve_and_save_trades_for_symbol(db, client, symbol)
+            .await
+            .map_err(|e| e.for_record(Source::Binance, &symbol.symbol))
+    });
+
+    let mut retrieved_trades =
+        stream::iter(retrieved_trades).buffer_unordered(client.concurrency());
+    while let Some(result) = retrieved_trades.next().await {
+        errors.collect(result)?;
+    }
+
+    Ok(())
+}
+
+struct MyTradeRow {
+    symbol: String,
+    base_asset: String,
+    quote_asset: String,
+    id: i64,
+    price: String,
+    qty: String,
+    quote_qty: String,
+    commission: String,
+    commission_asset: String,
+    time: i64,
+    is_buyer: i64,
+}
+
+fn map_trade(row: MyTradeRow, prices: &PriceStore) -> Result<Transaction, InputError> {
+    let base = Amount {
+        amount: parse_number("qty", &row.qty)?,
+        asset: asset(&row.base_asset),
+    };
+    let quote = Amount {
+        amount: parse_number("quoteQty", &row.quote_qty)?,
+        asset: asset(&row.quote_asset),
+    };
+
+    let timestamp = timestamp_millis("time", row.time)?;
+    // Pairs without a cash side, e.g. ETHBTC, are unvalued until a price of either is known
+    let usd_amount = prices
+        .trade_usd_value(&quote, &base, timestamp.date_naive())
+        .unwrap_or(0.0);
+
+    let commission = parse_number("commission", &row.commission)?;
+    let comission = if commission == 0.0 {
+        None
+    } else {
+        // Commissions in BNB have no known usd value
+        let usd_commission = if row.commission_asset == row.quote_asset {
+            commission * usd_amount / quote.amount
+        } else if row.commission_asset == row.base_asset {
+            commission * parse_number("price", &row.price)? * usd_amount / quote.amount
+        } else {
+            0.0
+        };
+
+        Some(Comission {
+            amount: Amount {
+                amount: commission,
+                asset: asset(&row.commission_asset),
+            },
+            usd_amount: usd_commission,
+        })
+    };
+
+    let (source, destination) = if row.is_buyer != 1 {
+        // sold the base asset
+        (base, quote)
+    } else {
+        // bought the base asset
+        (quote, base)
+    };
+
+    Ok(Transaction::exchange(
+        Application("Binance".to_string()),
+        format!("{}-{}", row.symbol, row.id),
+        source,
+        destination,
+        comission,
+        usd_amount,
+        timestamp,
+    ))
+}
+
+struct DepositRow {
+    id: String,
+    amount: String,
+    coin: String,
+    network: String,
+    insert_time: i64,
+}
+
+fn map_deposit(row: DepositRow, prices: &PriceStore) -> Result<Transaction, InputError> {
+    let amount = Amount {
+        amount: parse_number("amount", &row.amount)?,
+        asset: asset(&row.coin),
+    };
+    let timestamp = timestamp_millis("insertTime", row.insert_time)?;
+
+    Ok(Transaction::Deposit(Transfer {
+        tx_id: row.id,
+        store: ValueStore::Cex("Binance".to_string()),
+        // Binance does not report the value of transfers
+        usd_amount: prices
+            .usd_value(&amount, timestamp.date_naive())
+            .unwrap_or(0.0),
+        amount,
+        comission: None,
+        timestamp,
+        note: format!("Binance deposit on {}", row.network),
+    }))
+}
+
+struct WithdrawalRow {
+    id: String,
+    amount: String,
+    transaction_fee: String,
+    coin: String,
+    network: String,
+    apply_time: String,
+}
+
+fn map_withdrawal(row: WithdrawalRow, prices: &PriceStore) -> Result<Transaction, InputError> {
+    let amount = Amount {
+        amount: parse_number("amount", &row.amount)?,
+        asset: asset(&row.coin),
+    };
+    let fee = Amount {
+        amount: parse_number("transactionFee", &row.transaction_fee)?,
+        asset: asset(&row.coin),
+    };
+    let timestamp = parse_apply_time(&row.apply_time)?;
+    let date = timestamp.date_naive();
+
+    Ok(Transaction::Withdrawal(Transfer {
+        tx_id: row.id,
+        store: ValueStore::Cex("Binance".to_string()),
+        usd_amount: prices.usd_value(&amount, date).unwrap_or(0.0),
+        amount,
+        comission: Some(Comission {
+            usd_amount: prices.usd_value(&fee, date).unwrap_or(0.0),
+            amount: fee,
+        }),
+        timestamp,
+        note: format!("Binance withdrawal on {}", row.network),
+    }))
+}
+
+struct ConvertRow {
+    order_id: i64,
+    from_asset: String,
+    from_amount: String,
+    to_asset: String,
+    to_amount: String,
+    create_time: i64,
+}
+
+fn map_convert(row: ConvertRow, prices: &PriceStore) -> Result<Transaction, InputError> {
+    let source = Amount {
+        amount: parse_number("fromAmount", &row.from_amount)?,
+        asset: asset(&row.from_asset),
+    };
+    let destination = Amount {
+        amount: parse_number("toAmount", &row.to_amount)?,
+        asset: asset(&row.to_asset),
+    };
+
+    let timestamp = timestamp_millis("createTime", row.create_time)?;
+    let usd_amount = prices
+        .trade_usd_value(&source, &destination, timestamp.date_naive())
+        .unwrap_or(0.0);
+
+    Ok(Transaction::exchange(
+        Application("Binance Convert".to_string()),
+        row.order_id.to_string(),
+        source,
+        destination,
+        None,
+        usd_amount,
+        timestamp,
+    ))
+}
+
+struct DustRow {
+    trans_id: i64,
+    from_asset: String,
+    amount: String,
+    transfered_amount: String,
+    service_charge_amount: String,
+    operate_time: i64,
+}
+
+fn map_dust(row: DustRow, prices: &PriceStore) -> Result<Transaction, InputError> {
+    let source = Amount {
+        amount: parse_number("amount", &row.amount)?,
+        asset: asset(&row.from_asset),
+    };
+    let destination = Amount {
+        amount: parse_number("transferedAmount", &row.transfered_amount)?,
+        asset: asset("BNB"),
+    };
+    let fee = Amount {
+        amount: parse_number("serviceChargeAmount", &row.service_charge_amount)?,
+        asset: asset("BNB"),
+    };
+    let timestamp = timestamp_millis("operateTime", row.operate_time)?;
+    let date = timestamp.date_naive();
+    // Dust without a price of either side stays unvalued
+    let usd_amount = prices
+        .trade_usd_value(&source, &destination, date)
+        .unwrap_or(0.0);
+    let comission = Comission {
+        usd_amount: prices.fee_usd_value(&fee, &source, &destination, usd_amount, date),
+        amount: fee,
+    };
+
+    Ok(Transaction::exchange(
+        Application("Binance Dust".to_string()),
+        format!("{}-{}", row.trans_id, row.from_asset),
+        source,
+        destination,
+        Some(comission),
+        usd_amount,
+        timestamp,
+    ))
+}
+
+pub async fn get_all_trades(
+    db: &Pool<Sqlite>,
+    errors: &ErrorCollector,
+) -> Result<Vec<MappedTransaction>, InputError> {
+    let mut transactions = vec![];
+    let prices = PriceStore::load(db).await?;
+
+    let trades = query_as!(
+        MyTradeRow,
+        "SELECT symbol, base_asset, quote_asset, id, price, qty, quote_qty,
+            commission, commission_asset, time, is_buyer
+        FROM binance_my_trades"
+    )
+    .fetch_all(db)
+    .await?;
+    for row in trades {
+        let id = format!("{}-{}", row.symbol, row.id);
+        let transaction = map_trade(row, &prices).map_err(|e| e.for_record(Source::Binance, &id));
+        transactions.extend(
+            errors
+                .collect(transaction)?
+                .map(|t| MappedTransaction::new("binance_my_trades", &id, "Binance", t)),
+        );
+    }
+
+    let deposits = query_as!(
+        DepositRow,
+        "SELECT id, amount, coin, network, insert_time FROM binance_deposits WHERE status = $1",
+        DEPOSIT_SUCCESS
+    )
+    .fetch_all(db)
+    .await?;
+    for row in deposits {
+        let id = row.id.clone();
+        let transaction = map_deposit(row, &prices).map_err(|e| e.for_record(Source::Binance, &id));
+        transactions.extend(
+            errors
+                .collect(transaction)?
+                .map(|t| MappedTransaction::new("binance_deposits", &id, "Binance", t)),
+        );
+    }
+
+    let withdrawals = query_as!(
+        WithdrawalRow,
+        "SELECT id, amount, transaction_fee, coin, network, apply_time
+        FROM binance_withdrawals WHERE status = $1",
+        WITHDRAWAL_COMPLETED
+    )
+    .fetch_all(db)
+    .await?;
+    for row in withdrawals {
+        let id = row.id.clone();
+        let transaction =
+            map_withdrawal(row, &prices).map_err(|e| e.for_record(Source::Binance, &id));
+        transactions.extend(
+            errors
+                .collect(transaction)?
+                .map(|t| MappedTransaction::new("binance_withdrawals", &id, "Binance", t)),
+        );
+    }
+
+    let converts = query_as!(
+        ConvertRow,
+        "SELECT order_id, from_asset, from_amount, to_asset, to_amount, create_time
+        FROM binance_converts WHERE order_status = 'SUCCESS'"
+    )
+    .fetch_all(db)
+    .await?;
+    for row in converts {
+        let id = row.order_id.to_string();
+        let transaction = map_convert(row, &prices).map_err(|e| e.for_record(Source::Binance, &id));
+        transactions.extend(
+            errors
+                .collect(transaction)?
+                .map(|t| MappedTransaction::new("binance_converts", &id, "Binance", t)),
+        );
+    }
+
+    let dust = query_as!(
+        DustRow,
+        "SELECT trans_id, from_asset, amount, transfered_amount, service_charge_amount, operate_time
+        FROM binance_dust"
+    )
+    .fetch_all(db)
+    .await?;
+    for row in dust {
+        let id = format!("{}-{}", row.trans_id, row.from_asset);
+        let transaction = map_dust(row, &prices).map_err(|e| e.for_record(Source::Binance, &id));
+        transactions.extend(
+            errors
+                .collect(transaction)?
+                .map(|t| MappedTransaction::new("binance_dust", &id, "Binance", t)),
+        );
+    }
+
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn eth_btc_trade() -> MyTradeRow {
+        MyTradeRow {
+            symbol: "ETHBTC".to_string(),
+            base_asset: "ETH".to_string(),
+            quote_asset: "BTC".to_string(),
+            id: 1,
+            price: "0.05".to_string(),
+            qty: "2".to_string(),
+            quote_qty: "0.1".to_string(),
+            commission: "0.0001".to_string(),
+            commission_asset: "BTC".to_string(),
+            // 2024-03-01
+            time: 1_709_251_200_000,
+            is_buyer: 1,
+        }
+    }
+
+    #[test]
+    fn crypto_trades_are_valued_from_the_prices() {
+        let mut prices = PriceStore::default();
+        let date = NaiveDate::from_ymd_opt(2024, 2, 28).unwrap();
+        prices.insert(&asset("BTC"), date, 60000.0);
+
+        let Transaction::Trade(trade) = map_trade(eth_btc_trade(), &prices).unwrap() else {
+            panic!("not a trade");
+        };
+
+        assert_eq!(trade.source.asset.name, "BTC");
+        assert_eq!(trade.destination.asset.name, "ETH");
+        assert!((trade.usd_amount - 6000.0).abs() < 1e-6);
+        assert!((trade.comission.unwrap().usd_amount - 6.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn crypto_trades_without_prices_are_unvalued() {
+        let Transaction::Trade(trade) = map_trade(eth_btc_trade(), &PriceStore::default()).unwrap()
+        else {
+            panic!("not a trade");
+        };
+
+        assert_eq!(trade.usd_amount, 0.0);
+    }
+
+    #[test]
+    fn converts_are_valued_by_the_cash_side_first() {
+        let mut prices = PriceStore::default();
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        prices.insert(&asset("ETH"), date, 4000.0);
+
+        let row = ConvertRow {
+            order_id: 7,
+            from_asset: "USDT".to_string(),
+            from_amount: "3000".to_string(),
+            to_asset: "ETH".to_string(),
+            to_amount: "1".to_string(),
+            create_time: 1_709_251_200_000,
+        };
+        let Transaction::Trade(trade) = map_convert(row, &prices).unwrap() else {
+            panic!("not a trade");
+        };
+
+        assert_eq!(trade.usd_amount, 3000.0);
+    }
+}
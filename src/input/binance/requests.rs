@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use super::InputError;
+use crate::input::{
+    env_var,
+    http::{HttpClient, HttpOptions, HttpResponse, RateLimit},
+    mexc::requests::sign,
+};
+
+/// Binance allows a request weight of 6,000 per minute and IP
+const RATE_LIMIT: RateLimit = RateLimit {
+    weight: 6_000,
+    per: Duration::from_secs(60),
+};
+
+/// The documented request weight of the endpoints
+fn weight(path: &str) -> u32 {
+    match path {
+        "/api/v3/myTrades" | "/api/v3/account" | "/api/v3/exchangeInfo" => 20,
+        "/sapi/v1/capital/withdraw/history" => 10,
+        _ => 1,
+    }
+}
+
+/// The client for the Binance API, `BINANCE_BASE_URL` overrides the base url
+pub fn client(options: &HttpOptions) -> HttpClient {
+    HttpClient::from_env(
+        "binance",
+        "BINANCE_BASE_URL",
+        "https://api.binance.com",
+        RATE_LIMIT,
+    )
+    .with_options(options)
+}
+
+pub async fn request(client: &HttpClient, path: &str) -> Result<HttpResponse, InputError> {
+    client.send(weight(path), || client.get(path)).await
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TimeResult {
+    server_time: i64,
+}
+
+/// Binance rejects timestamps outside of the receive window with code -1021
+fn is_timestamp_error(error: &InputError) -> bool {
+    matches!(error, InputError::StatusError(400, body) if body.contains("-1021"))
+}
+
+/// Sends a signed request, the signature is computed the same way as for MEXC
+pub async fn request_signed(
+    client: &HttpClient,
+    path: &str,
+    parameters: &str,
+) -> Result<HttpResponse, InputError> {
+    let api_key = env_var("BINANCE_API_KEY")?;
+    let secret = env_var("BINANCE_SECRET_KEY")?;
+    let clock = client.clock();
+    let mut retried = false;
+
+    loop {
+        clock
+            .sync(|| async {
+                Ok(request(client, "/api/v3/time")
+                    .await?
+                    .json::<TimeResult>()?
+                    .server_time)
+            })
+            .await?;
+
+        let result = client
+            .send(weight(path), || {
+                let now = clock.now_millis();
+                let parameters = if parameters.is_empty() {
+                    format!("timestamp={now}")
+                } else {
+                    format!("{parameters}&timestamp={now}")
+                };
+                let signature = sign(&secret, &parameters);
+
+                client
+                    .get(&format!("{path}?{parameters}&signature={signature}"))
+                    .header("X-MBX-APIKEY", &api_key)
+            })
+            .await;
+
+        match result {
+            Err(error) if !retried && is_timestamp_error(&error) => {
+                clock.invalidate();
+                retried = true;
+            }
+            result => return result,
+        }
+    }
+}
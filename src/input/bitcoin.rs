@@ -0,0 +1,481 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use bitcoin::{
+    block::Header, consensus::encode::deserialize_hex, hex::DisplayHex, Script,
+    Transaction as RawTransaction,
+};
+use chrono::{TimeZone, Utc};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as, Pool, Sqlite};
+
+use crate::{
+    data::{Amount, Asset, Comission, Transaction, Transfer, ValueStore},
+    prices::PriceStore,
+};
+
+use self::{
+    descriptor::{Chain, WalletKey},
+    electrum::ElectrumClient,
+};
+
+use super::{env_var, http::HttpOptions, ErrorCollector, InputError, MappedTransaction, Source};
+
+pub mod descriptor;
+pub mod electrum;
+
+/// How many unused addresses in a row end the scan of a chain, `BITCOIN_GAP_LIMIT`
+const DEFAULT_GAP_LIMIT: u32 = 20;
+
+const SATOSHIS_PER_BITCOIN: f64 = 1e8;
+
+/// A wallet configured by `BITCOIN_WALLETS` as comma separated `name=descriptor` pairs,
+/// where the descriptor is an account xpub, ypub or zpub or a single key descriptor
+struct BitcoinWallet {
+    name: String,
+    descriptor: String,
+    key: WalletKey,
+}
+
+fn wallets() -> Result<Vec<BitcoinWallet>, InputError> {
+    // Commas inside a descriptor, e.g. of a multisig, belong to the wallet before
+    let mut configured: Vec<String> = vec![];
+    for part in env_var("BITCOIN_WALLETS")?.split(',') {
+        match configured.last_mut() {
+            Some(wallet) if !part.contains('=') => *wallet = format!("{wallet},{part}"),
+            _ => configured.push(part.to_string()),
+        }
+    }
+
+    configured
+        .iter()
+        .filter(|wallet| !wallet.trim().is_empty())
+        .map(|wallet| match wallet.split_once('=') {
+            Some((name, descriptor)) => Ok(BitcoinWallet {
+                name: name.trim().to_string(),
+                descriptor: descriptor.trim().to_string(),
+                key: WalletKey::parse(descriptor.trim())?,
+            }),
+            None => Err(InputError::ConfigError(format!(
+                "bitcoin wallet '{wallet}' is not of the form name=descriptor"
+            ))),
+        })
+        .collect()
+}
+
+fn gap_limit() -> Result<u32, InputError> {
+    match dotenv::var("BITCOIN_GAP_LIMIT") {
+        Ok(limit) => limit
+            .parse()
+            .map_err(|_| InputError::ConfigError("BITCOIN_GAP_LIMIT is not a number".to_string())),
+        Err(_) => Ok(DEFAULT_GAP_LIMIT),
+    }
+}
+
+/// The key Electrum servers index scripts by, the reversed SHA-256 of the script
+fn script_hash(script: &Script) -> String {
+    let mut hash = Sha256::digest(script.as_bytes()).to_vec();
+    hash.reverse();
+    hash.to_lower_hex_string()
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct HistoryItem {
+    tx_hash: String,
+    /// 0 or less for transactions still in the mempool
+    height: i64,
+}
+
+/// The raw transaction, from the database or else from the server
+async fn raw_transaction(
+    db: &Pool<Sqlite>,
+    client: &ElectrumClient,
+    txid: &str,
+) -> Result<String, InputError> {
+    let stored = query!("SELECT raw FROM bitcoin_transactions WHERE txid = $1", txid)
+        .fetch_optional(db)
+        .await?;
+    if let Some(stored) = stored {
+        return Ok(stored.raw);
+    }
+
+    let raw = client
+        .call::<String>("blockchain.transaction.get", json!([txid]))
+        .await?;
+    query!(
+        "INSERT INTO bitcoin_transactions (txid, raw, created_at)
+        VALUES ($1, $2, CURRENT_TIMESTAMP)
+        ON CONFLICT (txid) DO NOTHING",
+        txid,
+        raw
+    )
+    .execute(db)
+    .await?;
+
+    Ok(raw)
+}
+
+fn decode(txid: &str, raw: &str) -> Result<RawTransaction, InputError> {
+    deserialize_hex(raw).map_err(|_| InputError::ParseError {
+        field: "raw transaction",
+        value: txid.to_string(),
+    })
+}
+
+/// Derives the addresses of every chain until `gap_limit` of them in a row are unused
+/// and returns the confirmed transactions of the used ones with their height
+async fn scan_addresses(
+    db: &Pool<Sqlite>,
+    client: &ElectrumClient,
+    wallet: &BitcoinWallet,
+    gap_limit: u32,
+) -> Result<BTreeMap<String, i64>, InputError> {
+    let mut history = BTreeMap::new();
+
+    for chain in wallet.key.chains() {
+        let is_change = chain == Chain::Change;
+        let mut unused = 0;
+        let mut index = 0;
+
+        while unused < gap_limit {
+            let address = wallet.key.address(chain, index)?;
+            let script = address.script_pubkey();
+            let address = address.to_string();
+            let script_pubkey = script.as_bytes().to_lower_hex_string();
+            let derivation_index = index as i64;
+            query!(
+                "INSERT INTO bitcoin_addresses
+                (wallet, address, script_pubkey, is_change, derivation_index, created_at)
+                VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP)
+                ON CONFLICT (wallet, address) DO NOTHING",
+                wallet.name,
+                address,
+                script_pubkey,
+                is_change,
+                derivation_index
+            )
+            .execute(db)
+            .await?;
+
+            let items = client
+                .call::<Vec<HistoryItem>>(
+                    "blockchain.scripthash.get_history",
+                    json!([script_hash(&script)]),
+                )
+                .await?;
+            if items.is_empty() {
+                unused += 1;
+            } else {
+                unused = 0;
+            }
+            history.extend(
+                items
+                    .into_iter()
+                    .filter(|item| item.height > 0)
+                    .map(|item| (item.tx_hash, item.height)),
+            );
+
+            index += 1;
+        }
+    }
+
+    Ok(history)
+}
+
+/// Imports the confirmed transactions of the wallet that are not known yet,
+/// together with the transactions their inputs spend, which carry the spent amounts
+async fn retrieve_and_save_wallet(
+    db: &Pool<Sqlite>,
+    client: &ElectrumClient,
+    wallet: &BitcoinWallet,
+    gap_limit: u32,
+) -> Result<(), InputError> {
+    let network = wallet.key.network().to_string();
+    query!(
+        "INSERT INTO bitcoin_wallets (name, descriptor, network, created_at)
+        VALUES ($1, $2, $3, CURRENT_TIMESTAMP)
+        ON CONFLICT (name) DO UPDATE
+        SET descriptor = excluded.descriptor, network = excluded.network",
+        wallet.name,
+        wallet.descriptor,
+        network
+    )
+    .execute(db)
+    .await?;
+
+    let history = scan_addresses(db, client, wallet, gap_limit).await?;
+
+    let known = query!(
+        "SELECT txid FROM bitcoin_wallet_transactions WHERE wallet = $1",
+        wallet.name
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|row| row.txid)
+    .collect::<HashSet<_>>();
+
+    let mut timestamps = HashMap::new();
+    for (txid, height) in history.iter().filter(|(txid, _)| !known.contains(*txid)) {
+        let transaction = decode(txid, &raw_transaction(db, client, txid).await?)?;
+        for input in &transaction.input {
+            // Newly mined coins spend nothing
+            if !input.previous_output.is_null() {
+                raw_transaction(db, client, &input.previous_output.txid.to_string()).await?;
+            }
+        }
+
+        let timestamp = match timestamps.get(height) {
+            Some(timestamp) => *timestamp,
+            None => {
+                let header = client
+                    .call::<String>("blockchain.block.header", json!([height]))
+                    .await?;
+                let header =
+                    deserialize_hex::<Header>(&header).map_err(|_| InputError::ParseError {
+                        field: "block header",
+                        value: height.to_string(),
+                    })?;
+                let timestamp = header.time as i64;
+                timestamps.insert(*height, timestamp);
+                timestamp
+            }
+        };
+
+        query!(
+            "INSERT INTO bitcoin_wallet_transactions (wallet, txid, height, timestamp, created_at)
+            VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)",
+            wallet.name,
+            txid,
+            height,
+            timestamp
+        )
+        .execute(db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn gather_data(
+    db: &Pool<Sqlite>,
+    options: &HttpOptions,
+    errors: &ErrorCollector,
+) -> Result<(), InputError> {
+    let client = ElectrumClient::new(&env_var("ELECTRUM_URL")?, options)?;
+    let gap_limit = gap_limit()?;
+
+    for wallet in wallets()? {
+        errors.collect(
+            retrieve_and_save_wallet(db, &client, &wallet, gap_limit)
+                .await
+                .map_err(|e| e.for_record(Source::Bitcoin, &wallet.name)),
+        )?;
+    }
+
+    Ok(())
+}
+
+struct WalletRow {
+    name: String,
+    descriptor: String,
+    network: String,
+}
+
+struct WalletTransactionRow {
+    wallet: String,
+    txid: String,
+    timestamp: i64,
+}
+
+fn bitcoin(satoshis: u64) -> Amount {
+    Amount {
+        amount: satoshis as f64 / SATOSHIS_PER_BITCOIN,
+        asset: Asset {
+            name: "BTC".to_string(),
+            contract_address: None,
+        },
+    }
+}
+
+/// Maps what the transaction moved from and to the wallet. Outputs to addresses of the
+/// wallet itself, e.g. change, are not sent, and the miner fee is only known and only
+/// paid by the wallet when it funded all inputs.
+fn map_transaction(
+    row: &WalletTransactionRow,
+    wallet: &WalletRow,
+    transactions: &HashMap<String, RawTransaction>,
+    scripts: &HashSet<String>,
+    prices: &PriceStore,
+) -> Result<Option<Transaction>, InputError> {
+    let transaction = transactions
+        .get(&row.txid)
+        .ok_or(InputError::UnsupportedError(format!(
+            "transaction {} without raw data",
+            row.txid
+        )))?;
+    let is_own = |script: &Script| scripts.contains(&script.as_bytes().to_lower_hex_string());
+
+    let mut spent = 0;
+    let mut total_in = 0;
+    let mut is_funded_by_wallet = true;
+    for input in &transaction.input {
+        if input.previous_output.is_null() {
+            is_funded_by_wallet = false;
+            continue;
+        }
+
+        let previous = transactions
+            .get(&input.previous_output.txid.to_string())
+            .and_then(|previous| previous.output.get(input.previous_output.vout as usize))
+            .ok_or(InputError::UnsupportedError(format!(
+                "input {} of {} without its previous transaction",
+                input.previous_output, row.txid
+            )))?;
+
+        let value = previous.value.to_sat();
+        total_in += value;
+        if is_own(&previous.script_pubkey) {
+            spent += value;
+        } else {
+            is_funded_by_wallet = false;
+        }
+    }
+
+    let (received, sent) = transaction
+        .output
+        .iter()
+        .fold((0, 0), |(received, sent), output| {
+            match is_own(&output.script_pubkey) {
+                true => (received + output.value.to_sat(), sent),
+                false => (received, sent + output.value.to_sat()),
+            }
+        });
+
+    let timestamp = Utc
+        .timestamp_opt(row.timestamp, 0)
+        .single()
+        .ok_or(InputError::ParseError {
+            field: "timestamp",
+            value: row.timestamp.to_string(),
+        })?;
+    let transfer = |amount: Amount, comission: Option<Comission>, note: &str| Transfer {
+        tx_id: row.txid.clone(),
+        store: ValueStore::Wallet {
+            name: wallet.name.clone(),
+            network: wallet.network.clone(),
+            address: wallet.descriptor.clone(),
+        },
+        usd_amount: prices
+            .usd_value(&amount, timestamp.date_naive())
+            .unwrap_or(0.0),
+        amount,
+        comission,
+        timestamp,
+        note: note.to_string(),
+    };
+
+    if spent == 0 {
+        return Ok((received > 0)
+            .then(|| Transaction::Deposit(transfer(bitcoin(received), None, "bitcoin transfer"))));
+    }
+
+    if is_funded_by_wallet {
+        let fee = bitcoin(total_in.saturating_sub(received + sent));
+        let fee = Comission {
+            usd_amount: prices
+                .usd_value(&fee, timestamp.date_naive())
+                .unwrap_or(0.0),
+            amount: fee,
+        };
+        let note = match sent {
+            0 => "bitcoin consolidation",
+            _ => "bitcoin transfer",
+        };
+
+        return Ok(Some(Transaction::Withdrawal(transfer(
+            bitcoin(sent),
+            Some(fee),
+            note,
+        ))));
+    }
+
+    // Inputs of others, e.g. a coinjoin, leave only the net change of the wallet
+    Ok(Some(match spent.cmp(&received) {
+        std::cmp::Ordering::Greater => Transaction::Withdrawal(transfer(
+            bitcoin(spent - received),
+            None,
+            "bitcoin transfer with foreign inputs",
+        )),
+        _ => Transaction::Deposit(transfer(
+            bitcoin(received - spent),
+            None,
+            "bitcoin transfer with foreign inputs",
+        )),
+    }))
+}
+
+pub async fn get_all_trades(
+    db: &Pool<Sqlite>,
+    errors: &ErrorCollector,
+) -> Result<Vec<MappedTransaction>, InputError> {
+    let wallets = query_as!(
+        WalletRow,
+        "SELECT name, descriptor, network FROM bitcoin_wallets"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut scripts = HashMap::<String, HashSet<String>>::new();
+    for row in query!("SELECT wallet, script_pubkey FROM bitcoin_addresses")
+        .fetch_all(db)
+        .await?
+    {
+        scripts
+            .entry(row.wallet)
+            .or_default()
+            .insert(row.script_pubkey);
+    }
+
+    let mut transactions = HashMap::new();
+    for row in query!("SELECT txid, raw FROM bitcoin_transactions")
+        .fetch_all(db)
+        .await?
+    {
+        let transaction = errors.collect(
+            decode(&row.txid, &row.raw).map_err(|e| e.for_record(Source::Bitcoin, &row.txid)),
+        )?;
+        if let Some(transaction) = transaction {
+            transactions.insert(row.txid, transaction);
+        }
+    }
+
+    let rows = query_as!(
+        WalletTransactionRow,
+        "SELECT wallet, txid, timestamp FROM bitcoin_wallet_transactions ORDER BY height"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let prices = PriceStore::load(db).await?;
+    let no_scripts = HashSet::new();
+    let mut result = vec![];
+    for row in &rows {
+        let Some(wallet) = wallets.iter().find(|w| w.name == row.wallet) else {
+            continue;
+        };
+        let scripts = scripts.get(&wallet.name).unwrap_or(&no_scripts);
+
+        let transaction = errors.collect(
+            map_transaction(row, wallet, &transactions, scripts, &prices)
+                .map_err(|e| e.for_record(Source::Bitcoin, &row.txid)),
+        )?;
+        result.extend(
+            transaction.flatten().map(|t| {
+                MappedTransaction::new("bitcoin_transactions", &row.txid, &wallet.name, t)
+            }),
+        );
+    }
+
+    Ok(result)
+}
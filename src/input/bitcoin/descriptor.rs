@@ -0,0 +1,242 @@
+use std::str::FromStr;
+
+use bitcoin::{
+    base58,
+    bip32::{ChildNumber, Xpub},
+    key::Secp256k1,
+    secp256k1::VerifyOnly,
+    Address, CompressedPublicKey, Network, NetworkKind,
+};
+
+use crate::input::InputError;
+
+/// The version bytes of extended public keys, SLIP-132 tells the script type by them
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+const VERSIONS: [(&str, ScriptKind, [u8; 4]); 6] = [
+    ("xpub", ScriptKind::P2pkh, XPUB_VERSION),
+    ("ypub", ScriptKind::P2shP2wpkh, XPUB_VERSION),
+    ("zpub", ScriptKind::P2wpkh, XPUB_VERSION),
+    ("tpub", ScriptKind::P2pkh, TPUB_VERSION),
+    ("upub", ScriptKind::P2shP2wpkh, TPUB_VERSION),
+    ("vpub", ScriptKind::P2wpkh, TPUB_VERSION),
+];
+
+/// The descriptor functions of single key wallets and the script they derive
+const FUNCTIONS: [(&str, ScriptKind); 4] = [
+    ("sh(wpkh(", ScriptKind::P2shP2wpkh),
+    ("wpkh(", ScriptKind::P2wpkh),
+    ("pkh(", ScriptKind::P2pkh),
+    ("tr(", ScriptKind::P2tr),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKind {
+    P2pkh,
+    P2shP2wpkh,
+    P2wpkh,
+    P2tr,
+}
+
+/// The receiving or the change addresses of a wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chain {
+    Receive,
+    Change,
+}
+
+/// A single key wallet, given by an extended public key or a descriptor of one
+#[derive(Debug, Clone)]
+pub struct WalletKey {
+    kind: ScriptKind,
+    xpub: Xpub,
+    /// The derivation below the key up to the address index, per chain
+    receive: Vec<ChildNumber>,
+    change: Option<Vec<ChildNumber>>,
+    secp: Secp256k1<VerifyOnly>,
+}
+
+fn config_error(descriptor: &str, reason: &str) -> InputError {
+    InputError::ConfigError(format!("bitcoin wallet '{descriptor}': {reason}"))
+}
+
+fn parse_xpub(descriptor: &str, key: &str) -> Result<(Xpub, Option<ScriptKind>), InputError> {
+    let (kind, version) = VERSIONS
+        .iter()
+        .find(|(prefix, _, _)| key.starts_with(prefix))
+        .map(|(_, kind, version)| (*kind, *version))
+        .ok_or_else(|| config_error(descriptor, "unknown extended public key"))?;
+
+    let mut data =
+        base58::decode_check(key).map_err(|_| config_error(descriptor, "invalid key checksum"))?;
+    if data.len() < 4 {
+        return Err(config_error(descriptor, "extended public key too short"));
+    }
+    // The library only knows the versions of xpub and tpub
+    data[..4].copy_from_slice(&version);
+    let xpub = Xpub::decode(&data).map_err(|e| config_error(descriptor, &e.to_string()))?;
+
+    let is_plain = key.starts_with("xpub") || key.starts_with("tpub");
+    Ok((xpub, (!is_plain).then_some(kind)))
+}
+
+/// Parses steps like `0`, `1h` or `<0;1>` of a derivation below the key
+fn parse_steps(
+    descriptor: &str,
+    path: &str,
+) -> Result<(Vec<ChildNumber>, Option<Vec<ChildNumber>>), InputError> {
+    let steps = path
+        .trim_start_matches('/')
+        .strip_suffix('*')
+        .ok_or_else(|| config_error(descriptor, "the derivation must end with /*"))?;
+
+    let mut receive = vec![];
+    let mut change = vec![];
+    let mut is_multipath = false;
+    for step in steps.split('/').filter(|step| !step.is_empty()) {
+        let (receive_step, change_step) = match step
+            .strip_prefix('<')
+            .and_then(|step| step.strip_suffix('>'))
+        {
+            Some(multipath) => {
+                is_multipath = true;
+                multipath
+                    .split_once(';')
+                    .ok_or_else(|| config_error(descriptor, "only <receive;change> is supported"))?
+            }
+            None => (step, step),
+        };
+
+        let parse = |step: &str| {
+            ChildNumber::from_str(step).map_err(|e| config_error(descriptor, &e.to_string()))
+        };
+        receive.push(parse(receive_step)?);
+        change.push(parse(change_step)?);
+    }
+
+    // Without a multipath, a receiving chain 0 has its change on chain 1 next to it
+    let change = if is_multipath {
+        Some(change)
+    } else if receive.last() == Some(&ChildNumber::Normal { index: 0 }) {
+        let mut change = receive.clone();
+        change.pop();
+        change.push(ChildNumber::Normal { index: 1 });
+        Some(change)
+    } else {
+        None
+    };
+
+    Ok((receive, change))
+}
+
+impl WalletKey {
+    /// Parses an xpub, ypub or zpub of an account or a descriptor like
+    /// `wpkh([d34db33f/84h/0h/0h]xpub.../<0;1>/*)`. Multisig descriptors are not supported.
+    pub fn parse(descriptor: &str) -> Result<WalletKey, InputError> {
+        // The checksum is optional and only guards against typos
+        let trimmed = descriptor.split('#').next().unwrap_or(descriptor).trim();
+
+        let (kind, inner) = match FUNCTIONS
+            .iter()
+            .find(|(function, _)| trimmed.starts_with(function))
+        {
+            Some((function, kind)) => {
+                let closing = function.matches('(').count();
+                let inner = trimmed[function.len()..]
+                    .strip_suffix(&")".repeat(closing))
+                    .ok_or_else(|| config_error(descriptor, "unbalanced parentheses"))?;
+                (Some(*kind), inner)
+            }
+            None => (None, trimmed),
+        };
+
+        // The origin of the key is informational only
+        let inner = match inner.strip_prefix('[') {
+            Some(origin) => origin
+                .split_once(']')
+                .map(|(_, key)| key)
+                .ok_or_else(|| config_error(descriptor, "unbalanced key origin"))?,
+            None => inner,
+        };
+        if inner.contains(',') {
+            return Err(config_error(descriptor, "multisig is not supported"));
+        }
+
+        let (key, path) = inner.split_once('/').unwrap_or((inner, ""));
+        let (xpub, key_kind) = parse_xpub(descriptor, key)?;
+        let (receive, change) = match (kind, path) {
+            // A bare account key has the usual receiving and change chains
+            (None, "") => (
+                vec![ChildNumber::Normal { index: 0 }],
+                Some(vec![ChildNumber::Normal { index: 1 }]),
+            ),
+            (None, _) => {
+                return Err(config_error(
+                    descriptor,
+                    "a derivation needs a descriptor function",
+                ))
+            }
+            (Some(_), path) => parse_steps(descriptor, path)?,
+        };
+
+        let kind = match (kind, key_kind) {
+            (Some(kind), _) => kind,
+            (None, Some(kind)) => kind,
+            (None, None) => ScriptKind::P2pkh,
+        };
+
+        Ok(WalletKey {
+            kind,
+            xpub,
+            receive,
+            change,
+            secp: Secp256k1::verification_only(),
+        })
+    }
+
+    pub fn network(&self) -> Network {
+        match self.xpub.network {
+            NetworkKind::Main => Network::Bitcoin,
+            NetworkKind::Test => Network::Testnet,
+        }
+    }
+
+    /// The chains the wallet derives addresses on
+    pub fn chains(&self) -> Vec<Chain> {
+        match self.change {
+            Some(_) => vec![Chain::Receive, Chain::Change],
+            None => vec![Chain::Receive],
+        }
+    }
+
+    /// The address at the index of the chain
+    pub fn address(&self, chain: Chain, index: u32) -> Result<Address, InputError> {
+        let mut path = match (chain, &self.change) {
+            (Chain::Change, Some(change)) => change.clone(),
+            _ => self.receive.clone(),
+        };
+        path.push(
+            ChildNumber::from_normal_idx(index).map_err(|e| InputError::ParseError {
+                field: "derivation index",
+                value: e.to_string(),
+            })?,
+        );
+
+        let child =
+            self.xpub
+                .derive_pub(&self.secp, &path)
+                .map_err(|e| InputError::ParseError {
+                    field: "derivation",
+                    value: e.to_string(),
+                })?;
+        let key = CompressedPublicKey(child.public_key);
+        let network = self.network();
+
+        Ok(match self.kind {
+            ScriptKind::P2pkh => Address::p2pkh(key.pubkey_hash(), network),
+            ScriptKind::P2shP2wpkh => Address::p2shwpkh(&key, network),
+            ScriptKind::P2wpkh => Address::p2wpkh(&key, network),
+            ScriptKind::P2tr => Address::p2tr(&self.secp, child.to_x_only_pub(), None, network),
+        })
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::Mutex,
+};
+
+use crate::input::{
+    http::{self, HttpMode, HttpOptions, HttpResponse},
+    InputError,
+};
+
+/// The directory of the recordings, next to those of the exchanges
+const NAME: &str = "electrum";
+
+/// How long the server may take to answer a single call
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A client of the Electrum protocol, newline separated JSON-RPC over a plain TCP connection
+/// as spoken by electrs, Fulcrum and ElectrumX. Calls are sent one after the other.
+pub struct ElectrumClient {
+    address: String,
+    mode: HttpMode,
+    connection: Mutex<Option<BufReader<TcpStream>>>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+impl ElectrumClient {
+    /// The client of a server given as `host:port` or `tcp://host:port`
+    pub fn new(url: &str, options: &HttpOptions) -> Result<ElectrumClient, InputError> {
+        if url.starts_with("ssl://") {
+            return Err(InputError::ConfigError(
+                "only plain tcp:// Electrum servers are supported, e.g. a local electrs"
+                    .to_string(),
+            ));
+        }
+
+        Ok(ElectrumClient {
+            address: url.trim_start_matches("tcp://").to_string(),
+            mode: options.mode.clone(),
+            connection: Mutex::new(None),
+        })
+    }
+
+    async fn send(&self, key: String, request: &Value) -> Result<HttpResponse, InputError> {
+        let mut connection = self.connection.lock().await;
+        let stream = match connection.as_mut() {
+            Some(stream) => stream,
+            None => connection.insert(BufReader::new(TcpStream::connect(&self.address).await?)),
+        };
+
+        let exchange = async {
+            stream
+                .get_mut()
+                .write_all(format!("{request}\n").as_bytes())
+                .await?;
+
+            let mut line = String::new();
+            match stream.read_line(&mut line).await? {
+                0 => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)),
+                _ => Ok(line),
+            }
+        };
+
+        let body = match tokio::time::timeout(TIMEOUT, exchange).await {
+            Ok(Ok(body)) => body,
+            Ok(Err(error)) => {
+                // The next call connects anew
+                *connection = None;
+                return Err(error.into());
+            }
+            Err(_) => {
+                *connection = None;
+                return Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into());
+            }
+        };
+
+        Ok(HttpResponse {
+            url: key,
+            status: 200,
+            body,
+            retry_after: None,
+        })
+    }
+
+    /// Calls the method, errors of the server become status errors with their code
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, InputError> {
+        // The id is constant so that recordings of the same call match
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+        let key = format!("{method}{params}");
+
+        let response = match &self.mode {
+            HttpMode::Live => self.send(key, &request).await?,
+            HttpMode::Record(directory) => {
+                let response = self.send(key.clone(), &request).await?;
+                http::record(&directory.join(NAME), &key, &response)?;
+                response
+            }
+            HttpMode::Replay(directory) => http::replay(&directory.join(NAME), &key)?,
+        };
+        let response = response.json::<RpcResponse<T>>()?;
+
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(InputError::StatusError(
+                200,
+                format!("{method}: {} ({})", error.message, error.code),
+            )),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(InputError::StatusError(
+                200,
+                format!("{method}: empty result"),
+            )),
+        }
+    }
+}
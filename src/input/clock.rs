@@ -0,0 +1,134 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+
+use super::InputError;
+
+/// How long a measured offset is trusted before it is measured again
+const REFRESH_AFTER: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy)]
+struct Offset {
+    /// Server time minus local time in milliseconds
+    millis: i64,
+    measured_at: Instant,
+}
+
+/// The skew between the local clock and the clock of an exchange.
+/// It is measured once and shared by all requests, instead of asking the exchange for its
+/// time before every signed request.
+#[derive(Debug, Clone, Default)]
+pub struct ServerClock {
+    offset: Arc<Mutex<Option<Offset>>>,
+    /// Makes concurrent requests wait for a single measurement
+    measuring: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl ServerClock {
+    fn fresh_offset(&self) -> Option<i64> {
+        self.offset
+            .lock()
+            .expect("clock poisoned")
+            .filter(|offset| offset.measured_at.elapsed() < REFRESH_AFTER)
+            .map(|offset| offset.millis)
+    }
+
+    /// Measures the offset with `server_time_millis` unless a fresh one is known
+    pub async fn sync<F, Fut>(&self, server_time_millis: F) -> Result<(), InputError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<i64, InputError>>,
+    {
+        if self.fresh_offset().is_some() {
+            return Ok(());
+        }
+
+        let _measuring = self.measuring.lock().await;
+        if self.fresh_offset().is_some() {
+            return Ok(());
+        }
+
+        let before = Utc::now().timestamp_millis();
+        let server_time = server_time_millis().await?;
+        let after = Utc::now().timestamp_millis();
+
+        *self.offset.lock().expect("clock poisoned") = Some(Offset {
+            millis: server_time - (before + after) / 2,
+            measured_at: Instant::now(),
+        });
+
+        Ok(())
+    }
+
+    /// Forgets the offset, e.g. after the exchange rejected a timestamp
+    pub fn invalidate(&self) {
+        *self.offset.lock().expect("clock poisoned") = None;
+    }
+
+    /// The current time of the exchange in milliseconds, the local time until synced
+    pub fn now_millis(&self) -> i64 {
+        let offset = self
+            .offset
+            .lock()
+            .expect("clock poisoned")
+            .map(|offset| offset.millis)
+            .unwrap_or(0);
+
+        Utc::now().timestamp_millis() + offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    const HOUR: i64 = 60 * 60 * 1000;
+
+    /// Syncs with a server an hour ahead and counts the measurements
+    async fn sync(clock: &ServerClock, measured: &AtomicUsize) {
+        clock
+            .sync(|| async {
+                measured.fetch_add(1, Ordering::SeqCst);
+                Ok(Utc::now().timestamp_millis() + HOUR)
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_one_measurement() {
+        let clock = ServerClock::default();
+        let measured = AtomicUsize::new(0);
+
+        futures::future::join_all((0..4).map(|_| sync(&clock, &measured))).await;
+
+        assert_eq!(measured.load(Ordering::SeqCst), 1);
+        let skew = clock.now_millis() - Utc::now().timestamp_millis();
+        assert!((skew - HOUR).abs() < 1000, "skew {skew}");
+    }
+
+    #[tokio::test]
+    async fn invalidated_and_outdated_offsets_are_measured_again() {
+        let clock = ServerClock::default();
+        let measured = AtomicUsize::new(0);
+
+        sync(&clock, &measured).await;
+        clock.invalidate();
+        assert!((clock.now_millis() - Utc::now().timestamp_millis()).abs() < 1000);
+        sync(&clock, &measured).await;
+        assert_eq!(measured.load(Ordering::SeqCst), 2);
+
+        if let Some(offset) = clock.offset.lock().unwrap().as_mut() {
+            offset.measured_at = Instant::now() - REFRESH_AFTER;
+        }
+        sync(&clock, &measured).await;
+        sync(&clock, &measured).await;
+        assert_eq!(measured.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,10 +1,20 @@
-use reqwest::Response;
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+
 use serde::de::DeserializeOwned;
-use sqlx::{query, Pool, Sqlite};
+use sqlx::{query, query_as, Pool, Sqlite};
 
-use crate::data::{Airdrop, Amount, Application, Asset, Comission, Trade, Transaction};
+use crate::{
+    data::{Airdrop, Amount, Application, Asset, Comission, Transaction, Transfer, ValueStore},
+    prices::PriceStore,
+};
 
-use super::{HmacSha256, InputError};
+use super::{
+    http::{HttpClient, HttpOptions, HttpResponse, RateLimit},
+    parse_number, parse_timestamp, ErrorCollector, HmacSha256, InputError, MappedTransaction,
+    ReportedBalance, Source,
+};
 use hmac::Mac;
 
 #[derive(Debug, serde::Deserialize)]
@@ -23,59 +33,85 @@ pub struct TimeResult {
     epoch: u64,
 }
 
-pub async fn request(path: &str) -> Result<Response, InputError> {
-    let client = reqwest::Client::new();
-    let resp = client
-        .get(format!("https://api.coinbase.com{path}"))
-        .send()
-        .await?;
+/// Coinbase allows 10,000 requests per hour and API key
+const RATE_LIMIT: RateLimit = RateLimit {
+    weight: 10_000,
+    per: Duration::from_secs(60 * 60),
+};
 
-    Ok(resp)
+/// The client for the Coinbase API, `COINBASE_BASE_URL` overrides the base url
+pub fn client(options: &HttpOptions) -> HttpClient {
+    HttpClient::from_env(
+        "coinbase",
+        "COINBASE_BASE_URL",
+        "https://api.coinbase.com",
+        RATE_LIMIT,
+    )
+    .with_options(options)
 }
 
-pub async fn get_server_time() -> Result<u64, InputError> {
-    Ok(request("/v2/time")
-        .await?
-        .json::<CoinbaseResult<TimeResult>>()
+pub async fn request(client: &HttpClient, path: &str) -> Result<HttpResponse, InputError> {
+    client.send(1, || client.get(path)).await
+}
+
+pub async fn get_server_time(client: &HttpClient) -> Result<u64, InputError> {
+    Ok(request(client, "/v2/time")
         .await?
+        .json::<CoinbaseResult<TimeResult>>()?
         .data
         .epoch)
 }
 
-pub async fn request_signed(path: &str) -> Result<Response, InputError> {
-    let client = reqwest::Client::new();
-    let parameters = "";
-
-    let time = get_server_time().await?;
-    let parameters = if parameters.is_empty() {
-        "".to_string()
-    } else {
-        format!("?{parameters}")
-    };
-    let body = "";
-    let method = "GET";
+/// Coinbase rejects timestamps that are more than 30 seconds off with a 401
+fn is_timestamp_error(error: &InputError) -> bool {
+    matches!(error, InputError::StatusError(401, body) if body.contains("timestamp"))
+}
 
+/// Signs the request as documented, the path includes the query parameters
+pub fn sign(secret: &str, time: u64, method: &str, path: &str, body: &str) -> String {
     let signed = format!("{time}{method}{path}{body}");
-    let mut mac = HmacSha256::new_from_slice(dotenv!("COINBASE_API_SECRET").as_bytes()).unwrap();
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
     mac.update(signed.as_bytes());
     let signed = mac.finalize().into_bytes();
 
-    let resp = client
-        .get(format!("https://api.coinbase.com{path}{parameters}"))
-        .header("CB-ACCESS-KEY", dotenv!("COINBASE_API_KEY"))
-        .header("CB-ACCESS-SIGN", format!("{:#01x}", signed))
-        .header("CB-ACCESS-TIMESTAMP", time.to_string())
-        .send()
-        .await?;
+    format!("{:#01x}", signed)
+}
 
-    if resp.status() != 200 {
-        return Err(InputError::StatusError(resp.status().as_u16()));
-    }
+pub async fn request_signed(client: &HttpClient, path: &str) -> Result<HttpResponse, InputError> {
+    let clock = client.clock();
+    let mut retried = false;
+
+    loop {
+        clock
+            .sync(|| async { Ok(get_server_time(client).await? as i64 * 1000) })
+            .await?;
 
-    Ok(resp)
+        let result = client
+            .send(1, || {
+                let time = (clock.now_millis() / 1000) as u64;
+                let signature = sign(dotenv!("COINBASE_API_SECRET"), time, "GET", path, "");
+
+                client
+                    .get(path)
+                    .header("CB-ACCESS-KEY", dotenv!("COINBASE_API_KEY"))
+                    .header("CB-ACCESS-SIGN", signature)
+                    .header("CB-ACCESS-TIMESTAMP", time.to_string())
+            })
+            .await;
+
+        match result {
+            Err(error) if !retried && is_timestamp_error(&error) => {
+                clock.invalidate();
+                retried = true;
+            }
+            result => return result,
+        }
+    }
 }
 
 pub async fn request_all_pages<T: DeserializeOwned>(
+    client: &HttpClient,
     signed: bool,
     path: &str,
 ) -> Result<Vec<T>, InputError> {
@@ -84,11 +120,11 @@ pub async fn request_all_pages<T: DeserializeOwned>(
 
     loop {
         let response = if signed {
-            request_signed(&path).await?
+            request_signed(client, &path).await?
         } else {
-            request(&path).await?
+            request(client, &path).await?
         };
-        let mut response = response.json::<CoinbaseResult<Vec<T>>>().await?;
+        let mut response = response.json::<CoinbaseResult<Vec<T>>>()?;
         result.append(&mut response.data);
 
         if let Some(pagination) = response.pagination {
@@ -108,14 +144,25 @@ pub async fn request_all_pages<T: DeserializeOwned>(
 #[derive(Debug, serde::Deserialize)]
 pub struct AccountResult {
     id: String,
+    balance: AmountResult,
 }
 
-pub async fn get_account_ids() -> Result<Vec<String>, InputError> {
-    let accounts = request_all_pages::<AccountResult>(true, "/v2/accounts").await?;
+pub async fn get_accounts(client: &HttpClient) -> Result<Vec<AccountResult>, InputError> {
+    request_all_pages::<AccountResult>(client, true, "/v2/accounts").await
+}
 
-    let accounts = accounts.into_iter().map(|x| x.id).collect();
+/// Snapshots the balances of the accounts, to reconcile them with the transactions
+async fn save_balances(db: &Pool<Sqlite>, accounts: &[AccountResult]) -> Result<(), InputError> {
+    let mut balances = vec![];
+    for account in accounts {
+        balances.push(ReportedBalance {
+            account: account.id.clone(),
+            asset: account.balance.currency.clone(),
+            amount: parse_number("balance", &account.balance.amount)?,
+        });
+    }
 
-    Ok(accounts)
+    super::save_balances(db, Source::Coinbase, &balances).await
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -134,6 +181,21 @@ pub struct ToResult {
     resource: String,
     resource_path: Option<String>,
 }
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ResourceResult {
+    id: String,
+    resource: String,
+    resource_path: String,
+}
+/// The order or trade a leg of a conversion belongs to, both legs link to the same one
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct LinkResult {
+    id: String,
+}
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct AdvancedTradeFillResult {
+    order_id: String,
+}
 #[derive(Debug, serde::Deserialize)]
 pub struct DetailsResult {
     title: String,
@@ -154,19 +216,116 @@ pub struct TransactionResult {
     network: Option<NetworkResult>,
     to: Option<ToResult>,
     details: DetailsResult,
+    buy: Option<ResourceResult>,
+    sell: Option<ResourceResult>,
+    trade: Option<LinkResult>,
+    advanced_trade_fill: Option<AdvancedTradeFillResult>,
+}
+
+impl TransactionResult {
+    /// The id both legs of a conversion share
+    fn trade_id(&self) -> Option<String> {
+        self.trade
+            .as_ref()
+            .map(|trade| trade.id.clone())
+            .or(self
+                .advanced_trade_fill
+                .as_ref()
+                .map(|fill| fill.order_id.clone()))
+            .or(self.buy.as_ref().map(|buy| buy.id.clone()))
+            .or(self.sell.as_ref().map(|sell| sell.id.clone()))
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct BuySellResult {
+    id: String,
+    status: String,
+    fee: Option<AmountResult>,
+    subtotal: Option<AmountResult>,
+    total: Option<AmountResult>,
+}
+
+/// Buys and sells only report their fee on the linked buy or sell resource
+async fn retrieve_and_save_buy_sell(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+    transaction_id: &str,
+    resource: &ResourceResult,
+) -> Result<(), InputError> {
+    let exists = query!(
+        "SELECT id FROM coinbase_buys_sells WHERE id = $1",
+        resource.id
+    )
+    .fetch_optional(db)
+    .await?
+    .is_some();
+
+    if exists {
+        return Ok(());
+    }
+
+    let buy_sell = request_signed(client, &resource.resource_path)
+        .await?
+        .json::<CoinbaseResult<BuySellResult>>()?
+        .data;
+
+    let (fee_amount, fee_currency) = buy_sell
+        .fee
+        .map(|fee| (fee.amount, fee.currency))
+        .unwrap_or(("0".to_string(), "".to_string()));
+    let (subtotal_amount, subtotal_currency) = buy_sell
+        .subtotal
+        .map(|subtotal| (subtotal.amount, subtotal.currency))
+        .unzip();
+    let (total_amount, total_currency) = buy_sell
+        .total
+        .map(|total| (total.amount, total.currency))
+        .unzip();
+
+    query!(
+        "INSERT INTO coinbase_buys_sells (
+            id, transaction_id, resource, status,
+            fee_amount, fee_currency,
+            subtotal_amount, subtotal_currency,
+            total_amount, total_currency
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        buy_sell.id,
+        transaction_id,
+        resource.resource,
+        buy_sell.status,
+        fee_amount,
+        fee_currency,
+        subtotal_amount,
+        subtotal_currency,
+        total_amount,
+        total_currency
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
 }
 
 pub async fn retrieve_and_save_transactions(
     db: &Pool<Sqlite>,
+    client: &HttpClient,
     account: &str,
 ) -> Result<Vec<TransactionResult>, InputError> {
     let transactions = request_all_pages::<TransactionResult>(
+        client,
         true,
         &format!("/v2/accounts/{account}/transactions"),
     )
     .await?;
 
     for transaction in transactions {
+        if let Some(resource) = transaction.buy.as_ref().or(transaction.sell.as_ref()) {
+            retrieve_and_save_buy_sell(db, client, &transaction.id, resource).await?;
+        }
+
+        let trade_id = transaction.trade_id();
         let exists = query!(
             "SELECT id FROM coinbase_transactions WHERE id = $1",
             transaction.id
@@ -176,6 +335,14 @@ pub async fn retrieve_and_save_transactions(
         .is_some();
 
         if exists {
+            // Rows fetched before the trade id was kept get it now
+            query!(
+                "UPDATE coinbase_transactions SET trade_id = $2 WHERE id = $1 AND trade_id IS NULL",
+                transaction.id,
+                trade_id
+            )
+            .execute(db)
+            .await?;
             continue;
         }
 
@@ -193,9 +360,9 @@ pub async fn retrieve_and_save_transactions(
                 resource, resource_path,
                 network_status, network_name,
                 to_id, to_resource, to_resource_path,
-                details_title, details_subtitle
+                details_title, details_subtitle, trade_id
             ) 
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)",
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20)",
             transaction.id,
             transaction.r#type,
             transaction.status,
@@ -214,189 +381,732 @@ pub async fn retrieve_and_save_transactions(
             to_resource,
             to_resource_path,
             transaction.details.title,
-            transaction.details.subtitle
+            transaction.details.subtitle,
+            trade_id
         ).execute(db).await?;
     }
     Ok(vec![])
 }
 
-pub async fn gather_data(db: &Pool<Sqlite>) -> Result<(), InputError> {
-    let account_ids = get_account_ids().await?;
+pub async fn gather_data(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+    errors: &ErrorCollector,
+) -> Result<(), InputError> {
+    let accounts = get_accounts(client).await?;
+    errors.collect(save_balances(db, &accounts).await)?;
+
+    for account in accounts.iter().map(|account| &account.id) {
+        let result = retrieve_and_save_transactions(db, client, account)
+            .await
+            .map_err(|e| e.for_record(Source::Coinbase, account));
 
-    let mut transactions = vec![];
-    for account in account_ids {
-        transactions.append(&mut retrieve_and_save_transactions(db, &account).await?);
+        errors.collect(result)?;
     }
 
     Ok(())
 }
 
-fn convert_to_usd(currency: &str, amount: &f64) -> f64 {
-    match currency {
-        "EUR" => amount / 0.92,
-        _ => unimplemented!("Currency {} is not implemented", currency),
+/// How a Coinbase transaction type is mapped onto a `Transaction`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CoinbaseKind {
+    /// A buy of crypto with the payment method of the account
+    Buy,
+    /// A sell of crypto to the payment method of the account
+    Sell,
+    /// One side of a conversion, the other side is booked on another account
+    TradeLeg,
+    /// An asset given to the account for free, e.g. staking rewards or interest
+    Reward,
+    /// An asset moved in or out of the account, the sign of the amount gives the direction
+    Transfer,
+}
+
+/// The mapping table of all documented Coinbase transaction types
+fn classify(r#type: &str) -> Option<CoinbaseKind> {
+    let kind = match r#type {
+        "buy" | "card_buy_back" => CoinbaseKind::Buy,
+        "sell" | "cardspend" => CoinbaseKind::Sell,
+
+        "trade" | "advanced_trade_fill" | "retail_simple_dust" | "wrap_asset" | "unwrap_asset" => {
+            CoinbaseKind::TradeLeg
+        }
+
+        "earn_payout"
+        | "staking_reward"
+        | "inflation_reward"
+        | "interest"
+        | "incentives_rewards_payout"
+        | "retail_eth2_deprecation" => CoinbaseKind::Reward,
+
+        "send"
+        | "request"
+        | "tx"
+        | "transfer"
+        | "fiat_deposit"
+        | "fiat_withdrawal"
+        | "exchange_deposit"
+        | "exchange_withdrawal"
+        | "pro_deposit"
+        | "pro_withdrawal"
+        | "intx_deposit"
+        | "intx_withdrawal"
+        | "vault_withdrawal"
+        | "staking_transfer"
+        | "unstaking_transfer"
+        | "subscription"
+        | "clawback"
+        | "derivatives_settlement" => CoinbaseKind::Transfer,
+
+        _ => return None,
+    };
+
+    Some(kind)
+}
+
+/// A Coinbase transaction that could not be mapped and is left out of the data
+#[derive(Debug, Clone)]
+pub struct QuarantinedTransaction {
+    pub id: String,
+    pub r#type: String,
+    pub created_at: String,
+}
+
+fn print_quarantine_report(quarantined: &[QuarantinedTransaction], unpaired: &[String]) {
+    if !quarantined.is_empty() {
+        eprintln!(
+            "Quarantined {} Coinbase transactions with unknown types:",
+            quarantined.len()
+        );
+        for transaction in quarantined {
+            eprintln!(
+                "  {} {} '{}'",
+                transaction.created_at, transaction.id, transaction.r#type
+            );
+        }
+    }
+
+    if !unpaired.is_empty() {
+        eprintln!(
+            "Quarantined {} Coinbase conversions whose legs do not pair up:",
+            unpaired.len()
+        );
+        for trade_id in unpaired {
+            eprintln!("  {trade_id}");
+        }
     }
 }
 
-pub async fn get_all_trades(db: &Pool<Sqlite>) -> Result<Vec<Transaction>, InputError> {
-    let mut transactions_except_trades =
-        query!("SELECT * FROM coinbase_transactions WHERE type != 'trade'")
-            .fetch_all(db)
-            .await?
-            .into_iter()
-            .filter(|row| match row.r#type.as_ref() {
-                "buy" => row.amount_currency != "EUR",
-                "sell" => row.amount_currency != "EUR",
+fn coinbase_asset(name: String) -> Asset {
+    Asset {
+        name,
+        contract_address: None,
+    }
+}
 
-                "earn_payout" => true,
+struct CoinbaseTransactionRow {
+    id: String,
+    r#type: String,
+    amount_amount: String,
+    amount_currency: String,
+    native_amount_amount: String,
+    native_amount_currency: String,
+    description: Option<String>,
+    created_at: String,
+    fee_amount: Option<String>,
+    fee_currency: Option<String>,
+    trade_id: Option<String>,
+}
 
-                "send" => false,
-                "fiat_deposit" => false,
+/// A side of a conversion with its usd value
+type Leg = (Amount, f64);
 
-                _ => unimplemented!("Coinbase type '{}' is not implemented", row.r#type),
-            })
-            .map(|row| {
-                let usd_amount = convert_to_usd(
-                    &row.native_amount_currency,
-                    &row.native_amount_amount.parse().unwrap(),
-                );
-
-                match row.r#type.as_ref() {
-                    "buy" => Transaction::Trade(Trade {
-                        application: Application("Coinbase".to_string()),
-                        tx_id: row.id,
-                        source: Amount {
-                            amount: usd_amount,
-                            asset: Asset {
-                                name: "USD".to_string(),
-                                contract_address: None,
-                            },
-                        },
-                        usd_amount,
-                        destination: Amount {
-                            amount: row.amount_amount.parse().unwrap(),
-                            asset: Asset {
-                                name: row.amount_currency,
-                                contract_address: None,
-                            },
-                        },
-                        comission: None,
-                        timestamp: row.created_at.parse().unwrap(),
-                    }),
-                    "sell" => Transaction::Trade(Trade {
-                        application: Application("Coinbase".to_string()),
-                        tx_id: row.id,
-                        source: Amount {
-                            amount: row.amount_amount.parse::<f64>().unwrap() * -1.0,
-                            asset: Asset {
-                                name: row.amount_currency,
-                                contract_address: None,
-                            },
-                        },
-                        destination: Amount {
-                            amount: usd_amount * -1.0,
-                            asset: Asset {
-                                name: "USD".to_string(),
-                                contract_address: None,
-                            },
-                        },
-                        usd_amount: usd_amount * -1.0,
-                        comission: None,
-                        timestamp: row.created_at.parse().unwrap(),
-                    }),
-                    "earn_payout" => Transaction::Airdrop(Airdrop {
-                        tx_id: row.id,
-                        amount: Amount {
-                            amount: row.amount_amount.parse().unwrap(),
-                            asset: Asset {
-                                name: row.amount_currency,
-                                contract_address: None,
-                            },
-                        },
-                        usd_amount,
-                        timestamp: row.created_at.parse().unwrap(),
-                        note: row
-                            .description
-                            .and_then(|d| if d.is_empty() { None } else { Some(d) })
-                            .unwrap_or("Coinbase 'earn_payout'".to_string()),
-                    }),
-                    _ => {
-                        unimplemented!()
-                    }
-                }
-            })
-            .collect::<Vec<_>>();
+/// The legs of a conversion, which are booked on two accounts and share the id of their
+/// trade or order. The fills of one order add up per side.
+#[derive(Default)]
+struct ConversionLegs {
+    /// The row of the earliest leg, ties broken by the row id, the conversion is mapped from
+    /// it so that its id stays the same when more fills or legs in another order come in
+    row_id: String,
+    timestamp: DateTime<Utc>,
+    source: Option<Leg>,
+    destination: Option<Leg>,
+    /// A side got legs of different assets, so the legs cannot be paired
+    conflicting: bool,
+}
 
-    let mut trades = vec![];
-    trades.append(&mut transactions_except_trades);
+impl ConversionLegs {
+    fn add(&mut self, row_id: String, timestamp: DateTime<Utc>, leg: Leg, is_source: bool) {
+        let side = if is_source {
+            &mut self.source
+        } else {
+            &mut self.destination
+        };
+        match side {
+            Some((amount, usd_amount)) if amount.asset.name == leg.0.asset.name => {
+                amount.amount += leg.0.amount;
+                *usd_amount += leg.1;
+            }
+            Some(_) => self.conflicting = true,
+            None => *side = Some(leg),
+        }
 
-    let transactions_trades =
-        query!("SELECT * FROM coinbase_transactions WHERE type = 'trade' ORDER BY created_at")
-            .fetch_all(db)
-            .await?;
-    let mut source = None;
-    let mut destination = None;
+        if self.row_id.is_empty() || (timestamp, &row_id) < (self.timestamp, &self.row_id) {
+            self.row_id = row_id;
+            self.timestamp = timestamp;
+        }
+    }
 
-    for trade in transactions_trades {
-        if trade.amount_amount.contains('-') {
-            source = Some((
-                Amount {
-                    amount: trade.amount_amount.parse::<f64>().unwrap() * -1.0,
-                    asset: Asset {
-                        name: trade.amount_currency,
-                        contract_address: None,
+    /// The conversion, if both sides are known and fit together
+    fn into_exchange(self) -> Option<Transaction> {
+        let ((source, source_usd), (destination, destination_usd)) =
+            match (self.source, self.destination) {
+                (Some(source), Some(destination)) if !self.conflicting => (source, destination),
+                _ => return None,
+            };
+
+        let comission = Some(Comission {
+            amount: Amount {
+                amount: source_usd - destination_usd,
+                asset: coinbase_asset("USD".to_string()),
+            },
+            usd_amount: source_usd - destination_usd,
+        });
+
+        Some(Transaction::exchange(
+            Application("Coinbase".to_string()),
+            self.row_id,
+            source,
+            destination,
+            comission,
+            source_usd,
+            self.timestamp,
+        ))
+    }
+}
+
+/// Maps a row, the legs of conversions are added to them by their trade id instead
+fn map_row(
+    row: CoinbaseTransactionRow,
+    kind: CoinbaseKind,
+    prices: &PriceStore,
+    conversions: &mut BTreeMap<String, ConversionLegs>,
+) -> Result<Option<Transaction>, InputError> {
+    let amount = parse_number("amount", &row.amount_amount)?;
+    let native_amount = parse_number("native_amount", &row.native_amount_amount)?;
+    let timestamp = parse_timestamp("created_at", &row.created_at)?;
+    let date = timestamp.date_naive();
+    let usd_amount = prices.convert_to_usd(&row.native_amount_currency, native_amount, date)?;
+    let comission = match (row.fee_amount, row.fee_currency) {
+        (Some(fee_amount), Some(fee_currency)) => {
+            let fee_amount = parse_number("fee", &fee_amount)?;
+
+            if fee_amount == 0.0 {
+                None
+            } else {
+                Some(Comission {
+                    usd_amount: prices.convert_to_usd(&fee_currency, fee_amount, date)?,
+                    amount: Amount {
+                        amount: fee_amount,
+                        asset: coinbase_asset(fee_currency),
                     },
+                })
+            }
+        }
+        _ => None,
+    };
+
+    // A buy or sell of fiat is one side of a conversion between two fiat accounts
+    let kind = match kind {
+        CoinbaseKind::Buy | CoinbaseKind::Sell
+            if coinbase_asset(row.amount_currency.clone()).is_fiat() =>
+        {
+            CoinbaseKind::TradeLeg
+        }
+        kind => kind,
+    };
+
+    let transaction = match kind {
+        CoinbaseKind::Buy => Transaction::exchange(
+            Application("Coinbase".to_string()),
+            row.id,
+            Amount {
+                amount: native_amount,
+                asset: coinbase_asset(row.native_amount_currency),
+            },
+            Amount {
+                amount,
+                asset: coinbase_asset(row.amount_currency),
+            },
+            comission,
+            usd_amount,
+            timestamp,
+        ),
+        CoinbaseKind::Sell => Transaction::exchange(
+            Application("Coinbase".to_string()),
+            row.id,
+            Amount {
+                amount: -amount,
+                asset: coinbase_asset(row.amount_currency),
+            },
+            Amount {
+                amount: -native_amount,
+                asset: coinbase_asset(row.native_amount_currency),
+            },
+            comission,
+            -usd_amount,
+            timestamp,
+        ),
+        CoinbaseKind::Reward => Transaction::Airdrop(Airdrop {
+            tx_id: row.id,
+            amount: Amount {
+                amount,
+                asset: coinbase_asset(row.amount_currency),
+            },
+            usd_amount,
+            timestamp,
+            note: row
+                .description
+                .and_then(|d| if d.is_empty() { None } else { Some(d) })
+                .unwrap_or(format!("Coinbase '{}'", row.r#type)),
+        }),
+        CoinbaseKind::Transfer => {
+            let transfer = Transfer {
+                tx_id: row.id,
+                store: ValueStore::Cex("Coinbase".to_string()),
+                amount: Amount {
+                    amount: amount.abs(),
+                    asset: coinbase_asset(row.amount_currency),
                 },
-                convert_to_usd(
-                    &trade.native_amount_currency,
-                    &trade.native_amount_amount.parse::<f64>().unwrap(),
-                ) * -1.0,
-            ));
-        } else {
-            destination = Some((
+                comission: None,
+                usd_amount: usd_amount.abs(),
+                timestamp,
+                note: format!("Coinbase '{}'", row.r#type),
+            };
+
+            if amount < 0.0 {
+                Transaction::Withdrawal(transfer)
+            } else {
+                Transaction::Deposit(transfer)
+            }
+        }
+        CoinbaseKind::TradeLeg => {
+            // Rows fetched before the trade id was kept only share their time
+            let trade_id = row.trade_id.unwrap_or(row.created_at);
+            let leg = (
                 Amount {
-                    amount: trade.amount_amount.parse().unwrap(),
-                    asset: Asset {
-                        name: trade.amount_currency,
-                        contract_address: None,
-                    },
+                    amount: amount.abs(),
+                    asset: coinbase_asset(row.amount_currency),
                 },
-                convert_to_usd(
-                    &trade.native_amount_currency,
-                    &trade.native_amount_amount.parse::<f64>().unwrap(),
-                ),
-            ));
+                usd_amount.abs(),
+            );
+            conversions
+                .entry(trade_id)
+                .or_default()
+                .add(row.id, timestamp, leg, amount < 0.0);
+
+            return Ok(None);
         }
+    };
 
-        if source.is_some() && destination.is_some() {
-            let comission = Some(Comission {
-                amount: Amount {
-                    amount: source.clone().unwrap().1 - destination.clone().unwrap().1,
-                    asset: Asset {
-                        name: "USD".to_string(),
-                        contract_address: None,
-                    },
-                },
-                usd_amount: source.clone().unwrap().1 - destination.clone().unwrap().1,
+    Ok(Some(transaction))
+}
+
+pub async fn get_all_trades(
+    db: &Pool<Sqlite>,
+    errors: &ErrorCollector,
+) -> Result<Vec<MappedTransaction>, InputError> {
+    let rows = query_as!(
+        CoinbaseTransactionRow,
+        r#"SELECT t.id, t.type, t.amount_amount, t.amount_currency,
+            t.native_amount_amount, t.native_amount_currency,
+            t.description, t.created_at,
+            f.fee_amount as "fee_amount?", f.fee_currency as "fee_currency?", t.trade_id
+        FROM coinbase_transactions t
+        LEFT JOIN coinbase_buys_sells f ON f.transaction_id = t.id
+        ORDER BY t.created_at"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    let prices = PriceStore::load(db).await?;
+    let mut trades = vec![];
+    let mut quarantined = vec![];
+    let mut conversions = BTreeMap::<String, ConversionLegs>::new();
+
+    for row in rows {
+        let Some(kind) = classify(&row.r#type) else {
+            quarantined.push(QuarantinedTransaction {
+                id: row.id,
+                r#type: row.r#type,
+                created_at: row.created_at,
             });
+            continue;
+        };
+
+        let id = row.id.clone();
+        let transaction = map_row(row, kind, &prices, &mut conversions)
+            .map_err(|e| e.for_record(Source::Coinbase, &id));
 
-            trades.push(Transaction::Trade(Trade {
-                application: Application("Coinbase".to_string()),
-                tx_id: trade.id,
-                source: source.expect("").0,
-                destination: destination.expect("").0,
-                usd_amount: convert_to_usd(
-                    &trade.native_amount_currency,
-                    &trade.native_amount_amount.parse::<f64>().unwrap(),
-                ) * -1.0,
-                comission,
-                timestamp: trade.created_at.parse().unwrap(),
-            }));
-
-            source = None;
-            destination = None;
+        if let Some(Some(transaction)) = errors.collect(transaction)? {
+            trades.push(MappedTransaction::new(
+                "coinbase_transactions",
+                &id,
+                "Coinbase",
+                transaction,
+            ));
         }
     }
 
+    let mut unpaired = vec![];
+    for (trade_id, legs) in conversions {
+        let row_id = legs.row_id.clone();
+        match legs.into_exchange() {
+            Some(transaction) => trades.push(MappedTransaction::new(
+                "coinbase_transactions",
+                &row_id,
+                "Coinbase",
+                transaction,
+            )),
+            None => unpaired.push(trade_id),
+        }
+    }
+
+    print_quarantine_report(&quarantined, &unpaired);
+
     Ok(trades)
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path, query_param, query_param_is_missing},
+        Match, Mock, MockServer, Request, ResponseTemplate,
+    };
+
+    use super::*;
+
+    /// Matches requests whose signature fits their timestamp, method and path
+    struct Signed;
+
+    impl Match for Signed {
+        fn matches(&self, request: &Request) -> bool {
+            let header = |name| {
+                request
+                    .headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default()
+            };
+            let Ok(time) = header("CB-ACCESS-TIMESTAMP").parse() else {
+                return false;
+            };
+            let path = match request.url.query() {
+                Some(query) => format!("{}?{query}", request.url.path()),
+                None => request.url.path().to_string(),
+            };
+
+            header("CB-ACCESS-KEY") == dotenv!("COINBASE_API_KEY")
+                && header("CB-ACCESS-SIGN")
+                    == sign(dotenv!("COINBASE_API_SECRET"), time, "GET", &path, "")
+        }
+    }
+
+    async fn server() -> (MockServer, HttpClient) {
+        let server = MockServer::start().await;
+        let client = HttpClient::new("coinbase", &server.uri(), RATE_LIMIT);
+        (server, client)
+    }
+
+    async fn mount_time(server: &MockServer, calls: u64) {
+        Mock::given(method("GET"))
+            .and(path("/v2/time"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "data": { "epoch": chrono::Utc::now().timestamp() } })),
+            )
+            .expect(calls)
+            .mount(server)
+            .await;
+    }
+
+    fn account(id: &str) -> serde_json::Value {
+        json!({ "id": id, "balance": { "amount": "1.5", "currency": "BTC" } })
+    }
+
+    #[tokio::test]
+    async fn follows_the_next_uri_of_every_page_signed() {
+        let (server, client) = server().await;
+        mount_time(&server, 1).await;
+        Mock::given(method("GET"))
+            .and(path("/v2/accounts"))
+            .and(query_param_is_missing("starting_after"))
+            .and(Signed)
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": [account("a1"), account("a2")],
+                "pagination": { "next_uri": "/v2/accounts?starting_after=a2" }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v2/accounts"))
+            .and(query_param("starting_after", "a2"))
+            .and(Signed)
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "data": [account("a3")],
+                "pagination": { "next_uri": null }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let accounts = get_accounts(&client).await.unwrap();
+
+        let ids = accounts.iter().map(|a| a.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, ["a1", "a2", "a3"]);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_returned_without_retry() {
+        let (server, client) = server().await;
+        mount_time(&server, 1).await;
+        Mock::given(method("GET"))
+            .and(path("/v2/accounts"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("not found"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let error = get_accounts(&client).await.unwrap_err();
+
+        assert!(matches!(error, InputError::StatusError(404, body) if body == "not found"));
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let (server, client) = server().await;
+        mount_time(&server, 1).await;
+        Mock::given(method("GET"))
+            .and(path("/v2/accounts"))
+            .respond_with(ResponseTemplate::new(503).insert_header("Retry-After", "0"))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v2/accounts"))
+            .and(Signed)
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": [] })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert!(get_accounts(&client).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejected_timestamps_measure_the_clock_again() {
+        let (server, client) = server().await;
+        mount_time(&server, 2).await;
+        Mock::given(method("GET"))
+            .and(path("/v2/accounts"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("request timestamp expired"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v2/accounts"))
+            .and(Signed)
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": [] })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        get_accounts(&client).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_unauthorized_answers_are_not_retried() {
+        let (server, client) = server().await;
+        mount_time(&server, 1).await;
+        Mock::given(method("GET"))
+            .and(path("/v2/accounts"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("invalid api key"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let error = get_accounts(&client).await.unwrap_err();
+
+        assert!(matches!(error, InputError::StatusError(401, _)));
+    }
+
+    fn leg(
+        id: &str,
+        trade_id: &str,
+        amount: &str,
+        currency: &str,
+        usd: &str,
+    ) -> CoinbaseTransactionRow {
+        CoinbaseTransactionRow {
+            id: id.to_string(),
+            r#type: "trade".to_string(),
+            amount_amount: amount.to_string(),
+            amount_currency: currency.to_string(),
+            native_amount_amount: usd.to_string(),
+            native_amount_currency: "USD".to_string(),
+            description: None,
+            created_at: format!("2024-01-01T00:00:0{}Z", &id[1..]),
+            fee_amount: None,
+            fee_currency: None,
+            trade_id: Some(trade_id.to_string()),
+        }
+    }
+
+    fn pair(rows: Vec<CoinbaseTransactionRow>) -> Vec<Option<Transaction>> {
+        let mut conversions = BTreeMap::new();
+        for row in rows {
+            let transaction = map_row(
+                row,
+                CoinbaseKind::TradeLeg,
+                &PriceStore::default(),
+                &mut conversions,
+            )
+            .unwrap();
+            assert!(transaction.is_none());
+        }
+        conversions
+            .into_values()
+            .map(ConversionLegs::into_exchange)
+            .collect()
+    }
+
+    #[test]
+    fn interleaved_legs_pair_by_trade_id() {
+        let conversions = pair(vec![
+            leg("t1", "a", "-0.1", "BTC", "-4000"),
+            leg("t2", "b", "-1", "ETH", "-2000"),
+            leg("t3", "b", "1990", "USDC", "1990"),
+            leg("t4", "a", "3980", "USDC", "3980"),
+        ]);
+
+        let Some(Transaction::Trade(a)) = &conversions[0] else {
+            panic!("conversion a is no trade: {:?}", conversions[0]);
+        };
+        assert_eq!(a.source.asset.name, "BTC");
+        assert_eq!(a.destination.amount, 3980.0);
+        assert_eq!(a.tx_id, "t1");
+
+        let Some(Transaction::Trade(b)) = &conversions[1] else {
+            panic!("conversion b is no trade: {:?}", conversions[1]);
+        };
+        assert_eq!(b.source.asset.name, "ETH");
+        assert_eq!(b.destination.amount, 1990.0);
+        assert_eq!(b.comission.as_ref().unwrap().usd_amount, 10.0);
+    }
+
+    #[test]
+    fn fills_of_one_order_add_up() {
+        let conversions = pair(vec![
+            leg("t1", "a", "-0.1", "BTC", "-4000"),
+            leg("t2", "a", "-0.1", "BTC", "-4000"),
+            leg("t3", "a", "7900", "USDC", "7900"),
+        ]);
+
+        let Some(Transaction::Trade(trade)) = &conversions[0] else {
+            panic!("no trade: {:?}", conversions[0]);
+        };
+        assert_eq!(trade.source.amount, 0.2);
+        assert_eq!(trade.usd_amount, 8000.0);
+    }
+
+    #[test]
+    fn the_id_stays_the_same_with_more_fills_in_another_order() {
+        let at_once = |mut row: CoinbaseTransactionRow| {
+            row.created_at = "2024-01-01T00:00:00Z".to_string();
+            row
+        };
+        let tx_id = |conversions: Vec<Option<Transaction>>| match &conversions[0] {
+            Some(Transaction::Trade(trade)) => (trade.tx_id.clone(), trade.timestamp),
+            conversion => panic!("no trade: {conversion:?}"),
+        };
+
+        let first = pair(vec![
+            at_once(leg("t2", "a", "-0.1", "BTC", "-4000")),
+            at_once(leg("t3", "a", "3980", "USDC", "3980")),
+        ]);
+        let later = pair(vec![
+            leg("t5", "a", "3980", "USDC", "3980"),
+            at_once(leg("t3", "a", "3980", "USDC", "3980")),
+            leg("t4", "a", "-0.1", "BTC", "-4000"),
+            at_once(leg("t2", "a", "-0.1", "BTC", "-4000")),
+        ]);
+
+        assert_eq!(tx_id(first), tx_id(later));
+    }
+
+    async fn insert_transaction(db: &Pool<Sqlite>, id: &str, r#type: &str, amount: &str) {
+        sqlx::query(
+            "INSERT INTO coinbase_transactions (id, type, status, amount_amount, amount_currency,
+                native_amount_amount, native_amount_currency, created_at, updated_at,
+                resource, resource_path, details_title, details_subtitle)
+            VALUES ($1, $2, 'completed', $3, 'BTC', '100.00', 'USD', '2024-01-01T00:00:00Z',
+                '2024-01-01T00:00:00Z', 'transaction', '', '', '')",
+        )
+        .bind(id)
+        .bind(r#type)
+        .bind(amount)
+        .execute(db)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn buys_get_the_fee_of_their_buy_resource() {
+        let db = crate::memory_db().await;
+        insert_transaction(&db, "t1", "buy", "0.0025").await;
+        insert_transaction(&db, "t2", "buy", "0.0025").await;
+        insert_transaction(&db, "t3", "buy", "0.0025").await;
+        for (id, transaction_id, fee) in [("b1", "t1", "1.49"), ("b2", "t2", "0")] {
+            sqlx::query(
+                "INSERT INTO coinbase_buys_sells (id, transaction_id, resource, status,
+                    fee_amount, fee_currency)
+                VALUES ($1, $2, 'buy', 'completed', $3, 'USD')",
+            )
+            .bind(id)
+            .bind(transaction_id)
+            .bind(fee)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        let trades = get_all_trades(&db, &ErrorCollector::new(false))
+            .await
+            .unwrap();
+
+        let fees = trades
+            .iter()
+            .map(|mapped| match &mapped.transaction {
+                Transaction::Trade(trade) => (
+                    trade.tx_id.as_str(),
+                    trade.comission.as_ref().map(|c| c.amount.amount),
+                ),
+                transaction => panic!("no trade: {transaction:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(fees, [("t1", Some(1.49)), ("t2", None), ("t3", None)]);
+    }
+
+    #[test]
+    fn legs_without_their_other_side_are_not_paired() {
+        let conversions = pair(vec![
+            leg("t1", "a", "-0.1", "BTC", "-4000"),
+            leg("t2", "b", "1990", "USDC", "1990"),
+        ]);
+
+        assert!(conversions.iter().all(Option::is_none));
+    }
+}
@@ -0,0 +1,960 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use chrono::{TimeZone, Utc};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde_json::json;
+use sqlx::{query, query_as, Pool, Sqlite};
+
+use crate::{
+    data::{Amount, Application, Asset, Bridge, Comission, Transaction, Transfer, ValueStore},
+    prices::PriceStore,
+};
+
+use self::rpc::{
+    address_topic, call, decode_string, parse_big_quantity, parse_quantity, to_quantity,
+    topic_address,
+};
+use self::swap::SwapLog;
+
+use super::{
+    env_var,
+    http::{HttpClient, HttpOptions},
+    ErrorCollector, InputError, MappedTransaction, Source,
+};
+
+pub mod bridge;
+pub mod rpc;
+pub mod swap;
+
+/// The topic of the ERC-20 `Transfer(address,address,uint256)` event
+pub const TRANSFER_TOPIC: &str =
+    "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+/// The selectors of the ERC-20 `symbol()` and `decimals()` calls
+const SYMBOL_SELECTOR: &str = "0x95d89b41";
+const DECIMALS_SELECTOR: &str = "0x313ce567";
+
+/// The number of blocks asked for in one `eth_getLogs` call, nodes limit the range.
+/// The blocks are imported in chunks of it as well, the progress is saved after each.
+const LOG_CHUNK: u64 = 2_000;
+
+/// A network with its JSON-RPC endpoint, configured by `EVM_NETWORKS` and `EVM_RPC_URL_<NETWORK>`
+struct EvmNetwork {
+    name: String,
+    client: HttpClient,
+    /// The block the first import starts at, `EVM_START_BLOCK_<NETWORK>`
+    start_block: Option<u64>,
+}
+
+/// A wallet configured by `EVM_WALLETS` as comma separated `name=address` pairs
+#[derive(Debug, Clone)]
+struct EvmWallet {
+    name: String,
+    address: String,
+}
+
+fn network_variable(prefix: &str, network: &str) -> String {
+    format!("{prefix}_{}", network.to_uppercase().replace('-', "_"))
+}
+
+fn networks(options: &HttpOptions) -> Result<Vec<EvmNetwork>, InputError> {
+    let names = dotenv::var("EVM_NETWORKS").unwrap_or("ethereum".to_string());
+
+    names
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let url = env_var(&network_variable("EVM_RPC_URL", &name))?;
+            let start_block = match dotenv::var(network_variable("EVM_START_BLOCK", &name)) {
+                Ok(block) => Some(block.parse().map_err(|_| {
+                    InputError::ConfigError(format!("start block of {name} is not a number"))
+                })?),
+                Err(_) => None,
+            };
+
+            Ok(EvmNetwork {
+                client: rpc::client(&name, &url, options),
+                name,
+                start_block,
+            })
+        })
+        .collect()
+}
+
+fn wallets() -> Result<Vec<EvmWallet>, InputError> {
+    env_var("EVM_WALLETS")?
+        .split(',')
+        .filter(|wallet| !wallet.trim().is_empty())
+        .map(|wallet| match wallet.split_once('=') {
+            Some((name, address)) if address.trim().starts_with("0x") => Ok(EvmWallet {
+                name: name.trim().to_string(),
+                address: address.trim().to_lowercase(),
+            }),
+            _ => Err(InputError::ConfigError(format!(
+                "EVM wallet '{wallet}' is not of the form name=0xaddress"
+            ))),
+        })
+        .collect()
+}
+
+/// The asset gas is paid in, `EVM_NATIVE_ASSET_<NETWORK>` overrides it
+pub fn native_asset(network: &str) -> Asset {
+    let name = dotenv::var(network_variable("EVM_NATIVE_ASSET", network)).unwrap_or(
+        match network {
+            "bsc" | "binance" => "BNB",
+            "polygon" => "POL",
+            "avalanche" => "AVAX",
+            "gnosis" => "XDAI",
+            "fantom" => "FTM",
+            _ => "ETH",
+        }
+        .to_string(),
+    );
+
+    Asset {
+        name,
+        contract_address: None,
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogResult {
+    address: String,
+    topics: Vec<String>,
+    data: String,
+    log_index: String,
+    transaction_hash: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionResult {
+    hash: String,
+    from: String,
+    to: Option<String>,
+    value: String,
+    input: String,
+    gas_price: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BlockResult {
+    number: String,
+    timestamp: String,
+    transactions: Vec<TransactionResult>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReceiptResult {
+    status: Option<String>,
+    gas_used: String,
+    effective_gas_price: Option<String>,
+    logs: Vec<LogResult>,
+}
+
+/// The hashes of the transactions that moved tokens from or to the wallets
+async fn token_transfer_hashes(
+    client: &HttpClient,
+    wallets: &[EvmWallet],
+    from: u64,
+    to: u64,
+) -> Result<HashSet<String>, InputError> {
+    let mut hashes = HashSet::new();
+
+    for chunk_start in (from..=to).step_by(LOG_CHUNK as usize) {
+        let chunk_end = (chunk_start + LOG_CHUNK - 1).min(to);
+
+        for wallet in wallets {
+            let topic = address_topic(&wallet.address);
+            for topics in [
+                json!([TRANSFER_TOPIC, null, topic]),
+                json!([TRANSFER_TOPIC, topic]),
+            ] {
+                let logs = call::<Vec<LogResult>>(
+                    client,
+                    "eth_getLogs",
+                    json!([{
+                        "fromBlock": to_quantity(chunk_start),
+                        "toBlock": to_quantity(chunk_end),
+                        "topics": topics,
+                    }]),
+                )
+                .await?;
+
+                hashes.extend(
+                    logs.into_iter()
+                        .map(|log| log.transaction_hash.to_lowercase()),
+                );
+            }
+        }
+    }
+
+    Ok(hashes)
+}
+
+/// Stores the transaction with its receipt and all of its logs
+async fn save_transaction(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+    network: &str,
+    block_number: i64,
+    timestamp: i64,
+    transaction: &TransactionResult,
+) -> Result<(), InputError> {
+    let receipt = call::<ReceiptResult>(
+        client,
+        "eth_getTransactionReceipt",
+        json!([transaction.hash]),
+    )
+    .await?;
+
+    let hash = transaction.hash.to_lowercase();
+    let from = transaction.from.to_lowercase();
+    let to = transaction.to.as_ref().map(|to| to.to_lowercase());
+    // Receipts before London have no effective gas price
+    let gas_price = receipt
+        .effective_gas_price
+        .or(transaction.gas_price.clone())
+        .unwrap_or("0x0".to_string());
+    let status = match receipt.status.as_deref() {
+        Some(status) => parse_quantity("status", status)? as i64,
+        // Receipts before Byzantium have no status
+        None => 1,
+    };
+
+    query!(
+        "INSERT OR REPLACE INTO evm_transactions (
+            network, hash, block_number, timestamp, from_address, to_address,
+            value, input, gas_used, gas_price, status, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, CURRENT_TIMESTAMP)",
+        network,
+        hash,
+        block_number,
+        timestamp,
+        from,
+        to,
+        transaction.value,
+        transaction.input,
+        receipt.gas_used,
+        gas_price,
+        status
+    )
+    .execute(db)
+    .await?;
+
+    for log in receipt.logs {
+        let log_index = parse_quantity("logIndex", &log.log_index)? as i64;
+        let address = log.address.to_lowercase();
+        let topics = serde_json::to_string(&log.topics)?;
+
+        query!(
+            "INSERT OR REPLACE INTO evm_logs (
+                network, tx_hash, log_index, address, topics, data, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP)",
+            network,
+            hash,
+            log_index,
+            address,
+            topics,
+            log.data
+        )
+        .execute(db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// The symbol and decimals of the token. Contracts that are no ERC-20 token, e.g. NFTs,
+/// revert the calls or answer without a symbol.
+async fn token_details(client: &HttpClient, address: &str) -> Option<(String, i64)> {
+    let symbol = call::<String>(
+        client,
+        "eth_call",
+        json!([{ "to": address, "data": SYMBOL_SELECTOR }, "latest"]),
+    )
+    .await
+    .ok()?;
+    let decimals = call::<String>(
+        client,
+        "eth_call",
+        json!([{ "to": address, "data": DECIMALS_SELECTOR }, "latest"]),
+    )
+    .await
+    .ok()?;
+
+    let symbol = decode_string(&symbol)?;
+    let decimals = parse_quantity("decimals", &decimals).ok()?;
+
+    Some((symbol, decimals as i64))
+}
+
+/// Looks up symbol and decimals of the tokens the wallets received or sent for the first time
+async fn save_tokens(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+    network: &str,
+) -> Result<(), InputError> {
+    let transfer_topics = format!("[\"{TRANSFER_TOPIC}\"%");
+    let unknown = query!(
+        "SELECT DISTINCT l.address FROM evm_logs l
+        LEFT JOIN evm_tokens t ON t.network = l.network AND t.contract_address = l.address
+        WHERE l.network = $1 AND t.symbol IS NULL AND l.topics LIKE $2",
+        network,
+        transfer_topics
+    )
+    .fetch_all(db)
+    .await?;
+
+    for token in unknown {
+        let Some((symbol, decimals)) = token_details(client, &token.address).await else {
+            continue;
+        };
+
+        query!(
+            "INSERT OR REPLACE INTO evm_tokens (network, contract_address, symbol, decimals, created_at)
+            VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)",
+            network,
+            token.address,
+            symbol,
+            decimals
+        )
+        .execute(db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Imports what the wallets did in the blocks and saves them as imported for the wallets
+/// that were not further yet
+async fn retrieve_and_save_blocks(
+    db: &Pool<Sqlite>,
+    network: &EvmNetwork,
+    wallets: &[EvmWallet],
+    from: u64,
+    to: u64,
+) -> Result<(), InputError> {
+    let client = &network.client;
+    let token_hashes = token_transfer_hashes(client, wallets, from, to).await?;
+    let addresses = wallets
+        .iter()
+        .map(|w| w.address.as_str())
+        .collect::<HashSet<_>>();
+
+    let mut blocks = stream::iter(from..=to)
+        .map(|number| async move {
+            call::<BlockResult>(
+                client,
+                "eth_getBlockByNumber",
+                json!([to_quantity(number), true]),
+            )
+            .await
+        })
+        .buffer_unordered(client.concurrency());
+
+    let mut transactions = vec![];
+    while let Some(block) = blocks.next().await {
+        let block = block?;
+        let block_number = parse_quantity("number", &block.number)? as i64;
+        let timestamp = parse_quantity("timestamp", &block.timestamp)? as i64;
+
+        transactions.extend(
+            block
+                .transactions
+                .into_iter()
+                .filter(|tx| {
+                    addresses.contains(tx.from.to_lowercase().as_str())
+                        || tx
+                            .to
+                            .as_ref()
+                            .is_some_and(|to| addresses.contains(to.to_lowercase().as_str()))
+                        || token_hashes.contains(&tx.hash.to_lowercase())
+                })
+                .map(|tx| (block_number, timestamp, tx)),
+        );
+    }
+
+    stream::iter(transactions.iter())
+        .map(|(block_number, timestamp, transaction)| {
+            save_transaction(
+                db,
+                client,
+                &network.name,
+                *block_number,
+                *timestamp,
+                transaction,
+            )
+        })
+        .buffer_unordered(client.concurrency())
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let to = to as i64;
+    for wallet in wallets {
+        query!(
+            "UPDATE evm_wallets SET last_block = $1
+            WHERE network = $2 AND address = $3 AND (last_block IS NULL OR last_block < $1)",
+            to,
+            network.name,
+            wallet.address
+        )
+        .execute(db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Imports everything the wallets did since the last import of the network, a chunk of
+/// blocks at a time. Native transfers are only visible in the blocks, so every block since
+/// then is scanned, and the first import starts at the configured start block.
+async fn retrieve_and_save_network(
+    db: &Pool<Sqlite>,
+    network: &EvmNetwork,
+    wallets: &[EvmWallet],
+) -> Result<(), InputError> {
+    let client = &network.client;
+    let latest = parse_quantity(
+        "blockNumber",
+        &call::<String>(client, "eth_blockNumber", json!([])).await?,
+    )?;
+
+    let mut from = latest + 1;
+    for wallet in wallets {
+        query!(
+            "INSERT INTO evm_wallets (network, address, name, last_block, created_at)
+            VALUES ($1, $2, $3, NULL, CURRENT_TIMESTAMP)
+            ON CONFLICT (network, address) DO UPDATE SET name = excluded.name",
+            network.name,
+            wallet.address,
+            wallet.name
+        )
+        .execute(db)
+        .await?;
+
+        let last_block = query!(
+            "SELECT last_block FROM evm_wallets WHERE network = $1 AND address = $2",
+            network.name,
+            wallet.address
+        )
+        .fetch_one(db)
+        .await?
+        .last_block;
+
+        let wallet_from = match (last_block, network.start_block) {
+            (Some(last_block), _) => last_block as u64 + 1,
+            (None, Some(start_block)) => start_block,
+            // Scanning every block since genesis would take days on a public endpoint
+            (None, None) => {
+                return Err(InputError::ConfigError(format!(
+                    "{} is not set, it is needed for the first import of {}",
+                    network_variable("EVM_START_BLOCK", &network.name),
+                    wallet.name
+                )))
+            }
+        };
+        from = from.min(wallet_from);
+    }
+
+    for chunk_start in (from..=latest).step_by(LOG_CHUNK as usize) {
+        let chunk_end = (chunk_start + LOG_CHUNK - 1).min(latest);
+        retrieve_and_save_blocks(db, network, wallets, chunk_start, chunk_end).await?;
+    }
+
+    save_tokens(db, client, &network.name).await
+}
+
+pub async fn gather_data(
+    db: &Pool<Sqlite>,
+    options: &HttpOptions,
+    errors: &ErrorCollector,
+) -> Result<(), InputError> {
+    let wallets = wallets()?;
+
+    for network in networks(options)? {
+        errors.collect(
+            retrieve_and_save_network(db, &network, &wallets)
+                .await
+                .map_err(|e| e.for_record(Source::Evm, &network.name)),
+        )?;
+    }
+
+    Ok(())
+}
+
+struct WalletRow {
+    network: String,
+    address: String,
+    name: String,
+}
+
+/// The account of a wallet, as the same address may be used on several networks
+fn account(wallet: &WalletRow) -> String {
+    format!("{}:{}", wallet.network, wallet.address)
+}
+
+fn store_account(store: &ValueStore) -> String {
+    match store {
+        ValueStore::Wallet {
+            network, address, ..
+        } => format!("{network}:{address}"),
+        ValueStore::Cex(name) => name.clone(),
+    }
+}
+
+struct TransactionRow {
+    network: String,
+    hash: String,
+    timestamp: i64,
+    from_address: String,
+    to_address: Option<String>,
+    value: String,
+    gas_used: String,
+    gas_price: String,
+    status: i64,
+}
+
+struct LogRow {
+    network: String,
+    tx_hash: String,
+    log_index: i64,
+    address: String,
+    topics: String,
+    data: String,
+    symbol: Option<String>,
+    decimals: Option<i64>,
+}
+
+/// A token moved by a `Transfer` event
+struct TokenTransfer {
+    log_index: i64,
+    from: String,
+    to: String,
+    amount: Amount,
+}
+
+fn token_transfer(log: &LogRow) -> Result<Option<TokenTransfer>, InputError> {
+    let topics = serde_json::from_str::<Vec<String>>(&log.topics)?;
+
+    // ERC-721 transfers have the token id as third topic, they move no amount
+    let [topic, from, to] = &topics[..] else {
+        return Ok(None);
+    };
+    if topic != TRANSFER_TOPIC {
+        return Ok(None);
+    }
+
+    let (Some(symbol), Some(decimals)) = (&log.symbol, log.decimals) else {
+        return Err(InputError::UnsupportedError(format!(
+            "token {} without symbol or decimals",
+            log.address
+        )));
+    };
+
+    Ok(Some(TokenTransfer {
+        log_index: log.log_index,
+        from: topic_address(from),
+        to: topic_address(to),
+        amount: Amount {
+            amount: parse_big_quantity("data", &log.data)? / 10f64.powi(decimals as i32),
+            asset: Asset {
+                name: symbol.clone(),
+                contract_address: Some(log.address.clone()),
+            },
+        },
+    }))
+}
+
+fn transfer(
+    tx_id: String,
+    wallet: &WalletRow,
+    prices: &PriceStore,
+    amount: Amount,
+    timestamp: chrono::DateTime<Utc>,
+    note: String,
+) -> Transfer {
+    Transfer {
+        tx_id,
+        store: ValueStore::Wallet {
+            name: wallet.name.clone(),
+            network: wallet.network.clone(),
+            address: wallet.address.clone(),
+        },
+        usd_amount: prices
+            .usd_value(&amount, timestamp.date_naive())
+            .unwrap_or(0.0),
+        amount,
+        comission: None,
+        timestamp,
+        note,
+    }
+}
+
+/// Maps the transaction to a trade if the wallet swapped exactly one asset for another
+fn map_swap(
+    row: &TransactionRow,
+    logs: &[LogRow],
+    withdrawals: &[Transfer],
+    deposits: &[Transfer],
+    gas: &Comission,
+    prices: &PriceStore,
+    timestamp: chrono::DateTime<Utc>,
+) -> Result<Option<Transaction>, InputError> {
+    let native = native_asset(&row.network);
+    let topics = logs
+        .iter()
+        .map(|log| serde_json::from_str::<Vec<String>>(&log.topics))
+        .collect::<Result<Vec<_>, _>>()?;
+    let swap_logs = logs
+        .iter()
+        .zip(&topics)
+        .map(|(log, topics)| SwapLog {
+            address: &log.address,
+            topics,
+            data: &log.data,
+        })
+        .collect::<Vec<_>>();
+
+    let Some(protocol) = swap::protocol(row.to_address.as_deref(), &swap_logs) else {
+        return Ok(None);
+    };
+
+    let spent = withdrawals
+        .iter()
+        .map(|w| w.amount.clone())
+        .collect::<Vec<_>>();
+    let mut received = deposits
+        .iter()
+        .map(|d| d.amount.clone())
+        .collect::<Vec<_>>();
+    if !spent.iter().any(|a| a.asset.name == native.name) {
+        received.extend(swap::unwrapped_native(
+            &swap_logs,
+            &row.network,
+            row.to_address.as_deref(),
+            &native,
+        ));
+    }
+
+    let Some((source, destination)) = swap::net_amounts(&spent, &received) else {
+        return Ok(None);
+    };
+
+    // Swaps without a stablecoin side are unvalued until a price of either token is known
+    let usd_amount = prices
+        .trade_usd_value(&source, &destination, timestamp.date_naive())
+        .unwrap_or(0.0);
+
+    Ok(Some(Transaction::exchange(
+        Application(protocol),
+        row.hash.clone(),
+        source,
+        destination,
+        Some(gas.clone()),
+        usd_amount,
+        timestamp,
+    )))
+}
+
+/// Maps what the transaction moved from and to the wallet, the gas is paid by the sender
+fn map_transaction(
+    row: &TransactionRow,
+    logs: &[LogRow],
+    wallet: &WalletRow,
+    prices: &PriceStore,
+) -> Result<Vec<Transaction>, InputError> {
+    let timestamp = Utc
+        .timestamp_opt(row.timestamp, 0)
+        .single()
+        .ok_or(InputError::ParseError {
+            field: "timestamp",
+            value: row.timestamp.to_string(),
+        })?;
+    let native = native_asset(&row.network);
+    let is_sender = row.from_address == wallet.address;
+
+    let mut deposits = vec![];
+    let mut withdrawals = vec![];
+
+    // Failed transactions move nothing but still cost gas
+    if row.status == 1 {
+        let value = parse_big_quantity("value", &row.value)? / 1e18;
+        if value > 0.0 {
+            let amount = Amount {
+                amount: value,
+                asset: native.clone(),
+            };
+            if is_sender {
+                withdrawals.push(transfer(
+                    row.hash.clone(),
+                    wallet,
+                    prices,
+                    amount.clone(),
+                    timestamp,
+                    format!("{} native transfer", row.network),
+                ));
+            }
+            if row.to_address.as_deref() == Some(wallet.address.as_str()) {
+                deposits.push(transfer(
+                    row.hash.clone(),
+                    wallet,
+                    prices,
+                    amount,
+                    timestamp,
+                    format!("{} native transfer", row.network),
+                ));
+            }
+        }
+
+        for log in logs {
+            let Some(token) = token_transfer(log)? else {
+                continue;
+            };
+            let tx_id = format!("{}-{}", row.hash, token.log_index);
+
+            if token.from == wallet.address {
+                withdrawals.push(transfer(
+                    tx_id.clone(),
+                    wallet,
+                    prices,
+                    token.amount.clone(),
+                    timestamp,
+                    format!("{} token transfer", row.network),
+                ));
+            }
+            if token.to == wallet.address {
+                deposits.push(transfer(
+                    tx_id,
+                    wallet,
+                    prices,
+                    token.amount,
+                    timestamp,
+                    format!("{} token transfer", row.network),
+                ));
+            }
+        }
+    }
+
+    if is_sender {
+        let gas = parse_big_quantity("gasUsed", &row.gas_used)?
+            * parse_big_quantity("gasPrice", &row.gas_price)?
+            / 1e18;
+        let gas = Amount {
+            amount: gas,
+            asset: native.clone(),
+        };
+        let comission = Comission {
+            usd_amount: prices
+                .usd_value(&gas, timestamp.date_naive())
+                .unwrap_or(0.0),
+            amount: gas,
+        };
+
+        if row.status == 1 {
+            let swap = map_swap(
+                row,
+                logs,
+                &withdrawals,
+                &deposits,
+                &comission,
+                prices,
+                timestamp,
+            )?;
+            if let Some(swap) = swap {
+                return Ok(vec![swap]);
+            }
+        }
+
+        match withdrawals.first_mut() {
+            Some(withdrawal) => withdrawal.comission = Some(comission),
+            // e.g. approvals or failed transactions only cost gas
+            None => withdrawals.push(Transfer {
+                comission: Some(comission),
+                ..transfer(
+                    row.hash.clone(),
+                    wallet,
+                    prices,
+                    Amount {
+                        amount: 0.0,
+                        asset: native,
+                    },
+                    timestamp,
+                    format!("{} contract call", row.network),
+                )
+            }),
+        }
+    }
+
+    Ok(withdrawals
+        .into_iter()
+        .map(Transaction::Withdrawal)
+        .chain(deposits.into_iter().map(Transaction::Deposit))
+        .collect())
+}
+
+pub async fn get_all_trades(
+    db: &Pool<Sqlite>,
+    errors: &ErrorCollector,
+) -> Result<Vec<MappedTransaction>, InputError> {
+    let wallets = query_as!(WalletRow, "SELECT network, address, name FROM evm_wallets")
+        .fetch_all(db)
+        .await?;
+
+    let transactions = query_as!(
+        TransactionRow,
+        "SELECT network, hash, timestamp, from_address, to_address,
+            value, gas_used, gas_price, status
+        FROM evm_transactions ORDER BY block_number"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let logs = query_as!(
+        LogRow,
+        "SELECT l.network, l.tx_hash, l.log_index, l.address, l.topics, l.data,
+            t.symbol AS \"symbol?\", t.decimals AS \"decimals?\"
+        FROM evm_logs l
+        LEFT JOIN evm_tokens t ON t.network = l.network AND t.contract_address = l.address
+        ORDER BY l.log_index"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut logs_by_transaction = HashMap::<(String, String), Vec<LogRow>>::new();
+    for log in logs {
+        logs_by_transaction
+            .entry((log.network.clone(), log.tx_hash.clone()))
+            .or_default()
+            .push(log);
+    }
+
+    let prices = PriceStore::load(db).await?;
+    let mut result = vec![];
+    let mut bridged = HashMap::new();
+    let mut accounts = HashMap::new();
+    for row in &transactions {
+        let logs = logs_by_transaction
+            .get(&(row.network.clone(), row.hash.clone()))
+            .map(|logs| &logs[..])
+            .unwrap_or_default();
+
+        // The addresses the transaction touched, a wallet may be on both sides
+        let mut touched = BTreeSet::from([row.from_address.as_str()]);
+        touched.extend(row.to_address.as_deref());
+        // Bridges are either called directly or receive the tokens
+        let mut bridge = row.to_address.as_deref().and_then(bridge::bridge_name);
+        for log in logs {
+            if let Ok(Some(token)) = token_transfer(log) {
+                touched.extend(
+                    wallets
+                        .iter()
+                        .filter(|w| w.address == token.from || w.address == token.to)
+                        .map(|w| w.address.as_str()),
+                );
+                bridge = bridge.or(bridge::bridge_name(&token.to));
+            }
+        }
+
+        for wallet in wallets
+            .iter()
+            .filter(|w| w.network == row.network && touched.contains(w.address.as_str()))
+        {
+            let mapped = map_transaction(row, logs, wallet, &prices)
+                .map_err(|e| e.for_record(Source::Evm, &row.hash));
+            let Some(mut mapped) = errors.collect(mapped)? else {
+                continue;
+            };
+            for transaction in &mapped {
+                accounts.insert(transaction.tx_id().to_string(), account(wallet));
+            }
+
+            if let Some(bridge) = bridge
+                .as_ref()
+                .filter(|_| row.from_address == wallet.address)
+            {
+                for transaction in &mapped {
+                    if let Transaction::Withdrawal(withdrawal) = transaction {
+                        bridged.insert(withdrawal.tx_id.clone(), bridge.clone());
+                    }
+                }
+            }
+            result.append(&mut mapped);
+        }
+    }
+
+    // The ids of token transfers and of gas add a suffix to the hash of the transaction
+    Ok(bridge::link_bridges(result, &bridged)
+        .into_iter()
+        .map(|t| {
+            let hash = t.tx_id().split('-').next().unwrap_or_default().to_string();
+            // Transfers between own wallets share their id, but carry the wallet
+            let account = match &t {
+                Transaction::Deposit(Transfer { store, .. })
+                | Transaction::Withdrawal(Transfer { store, .. })
+                | Transaction::Bridge(Bridge { source: store, .. }) => store_account(store),
+                _ => accounts.get(t.tx_id()).cloned().unwrap_or_default(),
+            };
+            MappedTransaction::new("evm_transactions", &hash, &account, t)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{matchers::method, Mock, MockServer, Request, ResponseTemplate};
+
+    use super::*;
+
+    /// A node that answers `symbol()` with the result or error and `decimals()` with 18
+    async fn node(symbol: serde_json::Value) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(move |request: &Request| {
+                let body = String::from_utf8_lossy(&request.body);
+                let answer = if body.contains(SYMBOL_SELECTOR) {
+                    symbol.clone()
+                } else {
+                    json!({ "result": to_quantity(18) })
+                };
+                ResponseTemplate::new(200).set_body_json(answer)
+            })
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn tokens_have_symbol_and_decimals() {
+        // `symbol()` returning the ABI encoded string "USDC"
+        let server = node(json!({
+            "result": format!(
+                "0x{:0>64}{:0>64}{:0<64}",
+                "20",
+                "4",
+                "55534443"
+            )
+        }))
+        .await;
+        let client = rpc::client("test", &server.uri(), &HttpOptions::default());
+
+        assert_eq!(
+            token_details(&client, "0xtoken").await,
+            Some(("USDC".to_string(), 18))
+        );
+    }
+
+    #[tokio::test]
+    async fn reverting_contracts_are_no_tokens() {
+        let server = node(json!({
+            "error": { "code": 3, "message": "execution reverted" }
+        }))
+        .await;
+        let client = rpc::client("test", &server.uri(), &HttpOptions::default());
+
+        assert_eq!(token_details(&client, "0xnft").await, None);
+    }
+}
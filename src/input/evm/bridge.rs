@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use chrono::Duration;
+
+use crate::data::{Amount, Application, Bridge, Transaction, Transfer, ValueStore};
+
+/// Well known bridge contracts, `EVM_BRIDGES` adds more as `name=address` pairs
+const BRIDGES: [(&str, &str); 9] = [
+    ("0x5c7bcd6e7de5423a257d81b442095a1a6ced35c5", "Across"),
+    ("0x8731d54e9d02c286767d56ac03e8037c07e01e98", "Stargate"),
+    ("0xb8901acb165ed027e32754e0ffe830802919727f", "Hop"),
+    (
+        "0x4dbd4fc535ac27206064b68ffcf827b0a60bab3f",
+        "Arbitrum Bridge",
+    ),
+    (
+        "0x99c9fc46f92e8a1c0dec1b1747d010903e884be1",
+        "Optimism Bridge",
+    ),
+    ("0x3154cf16ccdb4c6d922629664174b904d80f2c35", "Base Bridge"),
+    (
+        "0xa0c68c638235ee32657e8f720a23cec1bfc77c77",
+        "Polygon Bridge",
+    ),
+    (
+        "0x32400084c286cf3e17e7b677ea9583e60a000324",
+        "zkSync Bridge",
+    ),
+    ("0x80c67432656d59144ceff962e8faf8926599bcf8", "Orbiter"),
+];
+
+/// How long the arrival on the other network may take, `EVM_BRIDGE_WINDOW_MINUTES`
+const DEFAULT_WINDOW_MINUTES: i64 = 120;
+
+/// The largest share of the amount a bridge is expected to keep as its fee
+const MAX_FEE_SHARE: f64 = 0.05;
+
+/// The name of the bridge if the address is a known bridge contract
+pub fn bridge_name(address: &str) -> Option<String> {
+    let configured = dotenv::var("EVM_BRIDGES").unwrap_or_default();
+    let configured = configured
+        .split(',')
+        .filter_map(|bridge| bridge.split_once('='))
+        .map(|(name, bridge)| (bridge.trim().to_lowercase(), name.trim().to_string()));
+
+    BRIDGES
+        .iter()
+        .map(|(bridge, name)| (bridge.to_string(), name.to_string()))
+        .chain(configured)
+        .find(|(bridge, _)| bridge == address)
+        .map(|(_, name)| name)
+}
+
+fn window() -> Duration {
+    let minutes = dotenv::var("EVM_BRIDGE_WINDOW_MINUTES")
+        .ok()
+        .and_then(|minutes| minutes.parse().ok())
+        .unwrap_or(DEFAULT_WINDOW_MINUTES);
+
+    Duration::minutes(minutes)
+}
+
+fn network(store: &ValueStore) -> Option<&str> {
+    match store {
+        ValueStore::Wallet { network, .. } => Some(network),
+        ValueStore::Cex(_) => None,
+    }
+}
+
+/// Whether the deposit can be the arrival of the withdrawal sent into a bridge:
+/// the same asset on another network, shortly after and a little less
+fn is_arrival(withdrawal: &Transfer, deposit: &Transfer, window: Duration) -> bool {
+    let sent = withdrawal.amount.amount;
+    let received = deposit.amount.amount;
+
+    network(&withdrawal.store) != network(&deposit.store)
+        && network(&deposit.store).is_some()
+        && withdrawal.amount.asset.name == deposit.amount.asset.name
+        && deposit.timestamp >= withdrawal.timestamp
+        && deposit.timestamp - withdrawal.timestamp <= window
+        && received <= sent
+        && received >= sent * (1.0 - MAX_FEE_SHARE)
+}
+
+/// Replaces withdrawals into a bridge and their arrival on the other network by a bridge.
+/// `outgoing` maps the ids of the withdrawals into a bridge to the name of the bridge.
+/// Withdrawals without an arrival stay as they are. The gas of the withdrawal is kept
+/// as a withdrawal of nothing, as a bridge only has a fee in the bridged asset.
+pub fn link_bridges(
+    transactions: Vec<Transaction>,
+    outgoing: &HashMap<String, String>,
+) -> Vec<Transaction> {
+    let window = window();
+    let mut result = vec![];
+    let mut deposits = vec![];
+    let mut withdrawals = vec![];
+
+    for transaction in transactions {
+        match transaction {
+            Transaction::Deposit(deposit) => deposits.push(Some(deposit)),
+            Transaction::Withdrawal(withdrawal) if outgoing.contains_key(&withdrawal.tx_id) => {
+                withdrawals.push(withdrawal)
+            }
+            transaction => result.push(transaction),
+        }
+    }
+
+    withdrawals.sort_by_key(|w| w.timestamp);
+    for withdrawal in withdrawals {
+        // The closest arrival in time wins, so that repeated bridging is paired in order
+        let arrival = deposits
+            .iter()
+            .enumerate()
+            .filter_map(|(i, d)| d.as_ref().map(|d| (i, d)))
+            .filter(|(_, deposit)| is_arrival(&withdrawal, deposit, window))
+            .min_by_key(|(_, deposit)| deposit.timestamp)
+            .map(|(i, _)| i);
+
+        let Some(deposit) = arrival.and_then(|i| deposits[i].take()) else {
+            result.push(Transaction::Withdrawal(withdrawal));
+            continue;
+        };
+
+        if let Some(gas) = withdrawal.comission.clone() {
+            result.push(Transaction::Withdrawal(Transfer {
+                tx_id: format!("{}-gas", withdrawal.tx_id),
+                amount: Amount {
+                    amount: 0.0,
+                    asset: gas.amount.asset.clone(),
+                },
+                comission: Some(gas),
+                usd_amount: 0.0,
+                note: "gas of a bridge".to_string(),
+                ..withdrawal.clone()
+            }));
+        }
+
+        result.push(Transaction::Bridge(Bridge {
+            application: Application(outgoing[&withdrawal.tx_id].clone()),
+            comission: Amount {
+                amount: withdrawal.amount.amount - deposit.amount.amount,
+                asset: withdrawal.amount.asset.clone(),
+            },
+            tx_id: withdrawal.tx_id,
+            source: withdrawal.store,
+            destination: deposit.store,
+            amount: withdrawal.amount,
+            timestamp: withdrawal.timestamp,
+        }));
+    }
+
+    result.extend(deposits.into_iter().flatten().map(Transaction::Deposit));
+
+    result
+}
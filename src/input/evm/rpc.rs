@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::input::{
+    http::{HttpClient, HttpOptions, RateLimit},
+    InputError,
+};
+
+/// Public endpoints allow about 25 calls per second, local nodes are not limited
+const RATE_LIMIT: RateLimit = RateLimit {
+    weight: 25,
+    per: Duration::from_secs(1),
+};
+
+/// The client for the JSON-RPC endpoint of a network
+pub fn client(network: &str, url: &str, options: &HttpOptions) -> HttpClient {
+    HttpClient::new(&format!("evm_{network}"), url, RATE_LIMIT).with_options(options)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+/// Calls the method, errors of the node become status errors with the JSON-RPC error code
+pub async fn call<T: DeserializeOwned>(
+    client: &HttpClient,
+    method: &str,
+    params: Value,
+) -> Result<T, InputError> {
+    // The id is constant so that recordings of the same call match
+    let body = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+
+    let response = client.send(1, || client.post_json(&body)).await?;
+    let response = response.json::<RpcResponse<T>>()?;
+
+    match (response.result, response.error) {
+        (_, Some(error)) => Err(InputError::StatusError(
+            200,
+            format!("{method}: {} ({})", error.message, error.code),
+        )),
+        (Some(result), None) => Ok(result),
+        (None, None) => Err(InputError::StatusError(
+            200,
+            format!("{method}: empty result"),
+        )),
+    }
+}
+
+/// Parses quantities like `0x1a`, which fit into 64 bits
+pub fn parse_quantity(field: &'static str, value: &str) -> Result<u64, InputError> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| InputError::ParseError {
+        field,
+        value: value.to_string(),
+    })
+}
+
+/// Parses 256 bit integers like token amounts, precise enough for amounts
+pub fn parse_big_quantity(field: &'static str, value: &str) -> Result<f64, InputError> {
+    let digits = value.trim_start_matches("0x");
+
+    digits.chars().try_fold(0.0, |total, digit| {
+        digit
+            .to_digit(16)
+            .map(|digit| total * 16.0 + digit as f64)
+            .ok_or(InputError::ParseError {
+                field,
+                value: value.to_string(),
+            })
+    })
+}
+
+pub fn to_quantity(value: u64) -> String {
+    format!("{value:#x}")
+}
+
+/// The address in the last 20 bytes of a 32 byte topic or word
+pub fn topic_address(topic: &str) -> String {
+    let digits = topic.trim_start_matches("0x");
+    format!("0x{}", &digits[digits.len().saturating_sub(40)..]).to_lowercase()
+}
+
+/// The address padded to a 32 byte topic
+pub fn address_topic(address: &str) -> String {
+    format!("0x{:0>64}", address.trim_start_matches("0x").to_lowercase())
+}
+
+/// The bytes of hex data like `0x00ff`
+pub fn decode_hex(data: &str) -> Option<Vec<u8>> {
+    let hex = data.trim_start_matches("0x");
+
+    (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decodes a string returned by `eth_call`, ABI encoded or as bytes32 like older tokens do
+pub fn decode_string(data: &str) -> Option<String> {
+    let bytes = decode_hex(data)?;
+
+    let decoded = if bytes.len() >= 64 {
+        let length = bytes[32..64].iter().fold(0usize, |length, b| {
+            length.saturating_mul(256).saturating_add(*b as usize)
+        });
+        bytes.get(64..64usize.checked_add(length)?)?.to_vec()
+    } else {
+        bytes.into_iter().take_while(|b| *b != 0).collect()
+    };
+
+    String::from_utf8(decoded)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
@@ -0,0 +1,229 @@
+use crate::data::{Amount, Asset};
+
+use super::{
+    network_variable,
+    rpc::{parse_big_quantity, topic_address},
+};
+
+/// The topics of the `Swap` events of the pools and the protocol they identify
+const SWAP_EVENTS: [(&str, &str); 3] = [
+    (
+        "0xd78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822",
+        "Uniswap V2",
+    ),
+    (
+        "0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67",
+        "Uniswap V3",
+    ),
+    (
+        "0x19b47279256b2a23a1665c810c8d55a1758940ee09377d4f8d26497a3577dc83",
+        "PancakeSwap V3",
+    ),
+];
+
+/// The topic of the `Withdrawal(address,uint256)` event of wrapped native tokens like WETH
+const UNWRAP_TOPIC: &str = "0x7fcf532c15f0a6db0bd6d0e038bea71d30d808c7d98cb3bf7268a95bf5081b65";
+
+/// The wrapped native token of the networks, `EVM_WRAPPED_NATIVE_<NETWORK>` sets it for others
+const WRAPPED_NATIVE: [(&str, &str); 9] = [
+    ("ethereum", "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+    ("bsc", "0xbb4cdb9cbd36b01bd1cbaebf2de08d9173bc095c"),
+    ("binance", "0xbb4cdb9cbd36b01bd1cbaebf2de08d9173bc095c"),
+    ("polygon", "0x0d500b1d8e8ef31e21c99d1db9a6444d3adf1270"),
+    ("arbitrum", "0x82af49447d8a07e3bd95bd0d56f35241523fbab1"),
+    ("optimism", "0x4200000000000000000000000000000000000006"),
+    ("base", "0x4200000000000000000000000000000000000006"),
+    ("avalanche", "0xb31f66aa3c1e785363f0875a1b74e27b85fd66c7"),
+    ("gnosis", "0xe91d153e0b41518a2ce8dd3d7944fa863463a97d"),
+];
+
+/// Well known routers and aggregators, `EVM_DEX_ROUTERS` adds more as `name=address` pairs
+const ROUTERS: [(&str, &str); 10] = [
+    ("0x7a250d5630b4cf539739df2c5dacb4c659f2488d", "Uniswap V2"),
+    ("0xe592427a0aece92de3edee1f18e0157c05861564", "Uniswap V3"),
+    ("0x68b3465833fb72a70ecdf485e0e4c7bd8665fc45", "Uniswap V3"),
+    ("0x3fc91a3afd70395cd496c647d5a6cc9d4b2b7fad", "Uniswap"),
+    (
+        "0x10ed43c718714eb63d5aa57b78b54704e256024e",
+        "PancakeSwap V2",
+    ),
+    ("0x13f4ea83d0bd40e75c8222255bc855a974568dd4", "PancakeSwap"),
+    ("0xd9e1ce17f2641f24ae83637ab66a2cca9c378b9f", "SushiSwap"),
+    ("0x1111111254eeb25477b68fb85ed929f73a960582", "1inch"),
+    ("0x111111125421ca6dc452d289314280a0f8842a65", "1inch"),
+    ("0xdef1c0ded9bec7f1a1670819833240f027b25eff", "0x"),
+];
+
+/// A log of a transaction, as far as the swap decoding needs it
+pub struct SwapLog<'a> {
+    /// The contract that emitted the log
+    pub address: &'a str,
+    pub topics: &'a [String],
+    pub data: &'a str,
+}
+
+fn router(address: &str) -> Option<String> {
+    let configured = dotenv::var("EVM_DEX_ROUTERS").unwrap_or_default();
+    let configured = configured
+        .split(',')
+        .filter_map(|router| router.split_once('='))
+        .map(|(name, router)| (router.trim().to_lowercase(), name.trim().to_string()));
+
+    ROUTERS
+        .iter()
+        .map(|(router, name)| (router.to_string(), name.to_string()))
+        .chain(configured)
+        .find(|(router, _)| router == address)
+        .map(|(_, name)| name)
+}
+
+/// The protocol of a swap, from the called router or else from the `Swap` events of its pools
+pub fn protocol(to_address: Option<&str>, logs: &[SwapLog]) -> Option<String> {
+    if let Some(name) = to_address.and_then(router) {
+        return Some(name);
+    }
+
+    logs.iter().find_map(|log| {
+        let topic = log.topics.first()?;
+        SWAP_EVENTS
+            .iter()
+            .find(|(event, _)| event == topic)
+            .map(|(_, name)| name.to_string())
+    })
+}
+
+fn wrapped_native(network: &str) -> Option<String> {
+    match dotenv::var(network_variable("EVM_WRAPPED_NATIVE", network)) {
+        Ok(address) => Some(address.trim().to_lowercase()),
+        Err(_) => WRAPPED_NATIVE
+            .iter()
+            .find(|(name, _)| *name == network)
+            .map(|(_, address)| address.to_string()),
+    }
+}
+
+/// The native amount the called router unwrapped for the swap, which it then sends on to
+/// the wallet. Such payouts are internal transactions and leave no other trace in the
+/// receipt. Only unwraps of the network's wrapped native token by the router itself count.
+pub fn unwrapped_native(
+    logs: &[SwapLog],
+    network: &str,
+    router: Option<&str>,
+    native: &Asset,
+) -> Option<Amount> {
+    let wrapped = wrapped_native(network)?;
+    let router = router?;
+    let amount = logs
+        .iter()
+        .filter(|log| log.address == wrapped)
+        .filter(|log| log.topics.len() == 2 && log.topics[0] == UNWRAP_TOPIC)
+        .filter(|log| topic_address(&log.topics[1]) == router)
+        .filter_map(|log| parse_big_quantity("wad", log.data).ok())
+        .sum::<f64>()
+        / 1e18;
+
+    (amount > 0.0).then(|| Amount {
+        amount,
+        asset: native.clone(),
+    })
+}
+
+/// What the wallet spent and received in total, by asset and contract.
+/// A swap spends exactly one asset and receives exactly one other.
+pub fn net_amounts(spent: &[Amount], received: &[Amount]) -> Option<(Amount, Amount)> {
+    let mut net: Vec<Amount> = vec![];
+
+    let changes = spent
+        .iter()
+        .map(|a| (a, -a.amount))
+        .chain(received.iter().map(|a| (a, a.amount)));
+    for (amount, change) in changes {
+        match net.iter_mut().find(|n| {
+            n.asset.name == amount.asset.name
+                && n.asset.contract_address == amount.asset.contract_address
+        }) {
+            Some(n) => n.amount += change,
+            None => net.push(Amount {
+                amount: change,
+                asset: amount.asset.clone(),
+            }),
+        }
+    }
+
+    let sources = net.iter().filter(|n| n.amount < 0.0).collect::<Vec<_>>();
+    let destinations = net.iter().filter(|n| n.amount > 0.0).collect::<Vec<_>>();
+
+    match (&sources[..], &destinations[..]) {
+        ([source], [destination]) => Some((
+            Amount {
+                amount: -source.amount,
+                asset: source.asset.clone(),
+            },
+            (*destination).clone(),
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+    const ROUTER: &str = "0x7a250d5630b4cf539739df2c5dacb4c659f2488d";
+
+    fn unwrap_topics(by: &str) -> Vec<String> {
+        vec![
+            UNWRAP_TOPIC.to_string(),
+            format!("0x{:0>64}", by.trim_start_matches("0x")),
+        ]
+    }
+
+    fn eth() -> Asset {
+        Asset {
+            name: "ETH".to_string(),
+            contract_address: None,
+        }
+    }
+
+    // 0.5 ETH
+    const HALF: &str = "0x00000000000000000000000000000000000000000000000006f05b59d3b20000";
+
+    #[test]
+    fn unwraps_of_weth_by_the_router_are_received() {
+        let topics = unwrap_topics(ROUTER);
+        let logs = [SwapLog {
+            address: WETH,
+            topics: &topics,
+            data: HALF,
+        }];
+
+        let amount = unwrapped_native(&logs, "ethereum", Some(ROUTER), &eth()).unwrap();
+
+        assert_eq!(amount.amount, 0.5);
+        assert_eq!(amount.asset.name, "ETH");
+    }
+
+    #[test]
+    fn unwraps_of_other_contracts_or_by_others_are_ignored() {
+        let by_router = unwrap_topics(ROUTER);
+        let by_other = unwrap_topics("0x1111111111111111111111111111111111111111");
+        let logs = [
+            // a token with an event of the same signature
+            SwapLog {
+                address: "0x2222222222222222222222222222222222222222",
+                topics: &by_router,
+                data: HALF,
+            },
+            // weth unwrapped by another contract of the transaction
+            SwapLog {
+                address: WETH,
+                topics: &by_other,
+                data: HALF,
+            },
+        ];
+
+        assert!(unwrapped_native(&logs, "ethereum", Some(ROUTER), &eth()).is_none());
+        assert!(unwrapped_native(&logs[..0], "ethereum", Some(ROUTER), &eth()).is_none());
+    }
+}
@@ -0,0 +1,540 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Url};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use tokio::{
+    sync::{Mutex, Semaphore},
+    time::Instant,
+};
+
+use super::{clock::ServerClock, InputError};
+
+/// Query parameters that must never end up in a recording
+const SECRET_PARAMETERS: [&str; 4] = ["signature", "timestamp", "recvWindow", "nonce"];
+
+/// The longest file name of a recording, longer ones are shortened with a hash
+const MAX_FILE_NAME: usize = 120;
+
+/// How often a request is retried on rate limits, server errors and timeouts
+const MAX_RETRIES: u32 = 5;
+/// The wait before the first retry, it doubles with every further retry
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Where the responses of a client come from
+#[derive(Debug, Clone, Default)]
+pub enum HttpMode {
+    /// Requests are sent to the exchange
+    #[default]
+    Live,
+    /// Requests are sent to the exchange and the responses archived in the directory
+    Record(PathBuf),
+    /// Responses are read from a directory written by `Record`, nothing is sent
+    Replay(PathBuf),
+}
+
+/// Options shared by the clients of all exchanges
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    pub mode: HttpMode,
+    /// The maximum number of requests in flight per exchange
+    pub concurrency: usize,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        HttpOptions {
+            mode: HttpMode::Live,
+            concurrency: 4,
+        }
+    }
+}
+
+/// The documented limit of an exchange, in request weight per time window
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub weight: u32,
+    pub per: Duration,
+}
+
+/// A sliding window over the weight of the recently sent requests
+#[derive(Debug)]
+struct RateLimiter {
+    limit: RateLimit,
+    used: Mutex<VecDeque<(Instant, u32)>>,
+}
+
+impl RateLimiter {
+    fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            used: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Waits until the weight of the request fits into the current window
+    async fn acquire(&self, weight: u32) {
+        loop {
+            let wait = {
+                let mut used = self.used.lock().await;
+                let now = Instant::now();
+
+                while let Some((sent, _)) = used.front() {
+                    if now.duration_since(*sent) >= self.limit.per {
+                        used.pop_front();
+                    } else {
+                        break;
+                    }
+                }
+
+                let total = used.iter().map(|(_, weight)| weight).sum::<u32>();
+                match used.front() {
+                    Some((oldest, _)) if total + weight > self.limit.weight => {
+                        *oldest + self.limit.per - now
+                    }
+                    _ => {
+                        used.push_back((now, weight));
+                        return;
+                    }
+                }
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// A response of an exchange, either received or replayed
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct HttpResponse {
+    /// The requested url without secrets
+    pub url: String,
+    pub status: u16,
+    pub body: String,
+    /// The wait the exchange asked for before the next request
+    #[serde(skip)]
+    pub retry_after: Option<Duration>,
+}
+
+impl HttpResponse {
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, InputError> {
+        Ok(serde_json::from_str(&self.body)?)
+    }
+
+    /// Rate limits and server errors are worth another try
+    fn is_retryable(&self) -> bool {
+        matches!(self.status, 418 | 429 | 500..=599)
+    }
+}
+
+/// The HTTP client of a single exchange, shared by all its requests
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    name: String,
+    base_url: String,
+    mode: HttpMode,
+    concurrency: usize,
+    /// Bounds the requests in flight to the concurrency, shared by all clones of the client
+    in_flight: Arc<Semaphore>,
+    rate_limiter: Arc<RateLimiter>,
+    clock: ServerClock,
+}
+
+impl HttpClient {
+    pub fn new(name: &str, base_url: &str, rate_limit: RateLimit) -> Self {
+        HttpClient {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .expect("the client configuration is valid"),
+            name: name.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            mode: HttpMode::Live,
+            concurrency: HttpOptions::default().concurrency,
+            in_flight: Arc::new(Semaphore::new(HttpOptions::default().concurrency)),
+            rate_limiter: Arc::new(RateLimiter::new(rate_limit)),
+            clock: ServerClock::default(),
+        }
+    }
+
+    /// Uses the base url of the environment variable if set, e.g. to point to a mock server
+    pub fn from_env(
+        name: &str,
+        variable: &str,
+        default_base_url: &str,
+        rate_limit: RateLimit,
+    ) -> Self {
+        match std::env::var(variable) {
+            Ok(base_url) if !base_url.is_empty() => HttpClient::new(name, &base_url, rate_limit),
+            _ => HttpClient::new(name, default_base_url, rate_limit),
+        }
+    }
+
+    pub fn with_options(mut self, options: &HttpOptions) -> Self {
+        self.mode = options.mode.clone();
+        self.concurrency = options.concurrency.max(1);
+        self.in_flight = Arc::new(Semaphore::new(self.concurrency));
+        self
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    /// The clock of the exchange, shared by all clones of the client
+    pub fn clock(&self) -> &ServerClock {
+        &self.clock
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Starts a GET request for the path, which is appended to the base url
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.client.get(format!("{}{path}", self.base_url))
+    }
+
+    /// Starts a POST request with a JSON body, e.g. for JSON-RPC
+    pub fn post_json(&self, body: &serde_json::Value) -> RequestBuilder {
+        self.client
+            .post(&self.base_url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+    }
+
+    /// Starts a POST request for the path, which is appended to the base url
+    pub fn post(&self, path: &str) -> RequestBuilder {
+        self.client.post(format!("{}{path}", self.base_url))
+    }
+
+    /// Sends the request built by `build` with the given rate limit weight.
+    /// Rate limits, server errors and timeouts are retried with a fresh request,
+    /// any other non success status is returned as an error.
+    pub async fn send(
+        &self,
+        weight: u32,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<HttpResponse, InputError> {
+        let response = match &self.mode {
+            HttpMode::Live => self.execute_with_retries(weight, build).await?,
+            HttpMode::Record(directory) => {
+                let response = self.execute_with_retries(weight, build).await?;
+                self.record(directory, &response)?;
+                response
+            }
+            HttpMode::Replay(directory) => {
+                let request = build().build()?;
+                self.replay(directory, &redact(&request))?
+            }
+        };
+
+        if !(200..300).contains(&response.status) {
+            return Err(InputError::StatusError(response.status, response.body));
+        }
+
+        Ok(response)
+    }
+
+    async fn execute_with_retries(
+        &self,
+        weight: u32,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<HttpResponse, InputError> {
+        let mut attempt = 0;
+
+        loop {
+            let result = {
+                let _permit = self
+                    .in_flight
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed");
+                self.rate_limiter.acquire(weight).await;
+                self.execute(build().build()?).await
+            };
+            let retry_after = match &result {
+                Ok(response) if response.is_retryable() => Some(response.retry_after),
+                Err(InputError::RequestError(error))
+                    if error.is_timeout() || error.is_connect() =>
+                {
+                    Some(None)
+                }
+                _ => None,
+            };
+
+            match retry_after {
+                Some(retry_after) if attempt < MAX_RETRIES => {
+                    let backoff = retry_after.unwrap_or(INITIAL_BACKOFF * 2u32.pow(attempt));
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    async fn execute(&self, request: reqwest::Request) -> Result<HttpResponse, InputError> {
+        let url = redact(&request);
+        let resp = self.client.execute(request).await?;
+
+        let retry_after = resp
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+
+        Ok(HttpResponse {
+            url,
+            status: resp.status().as_u16(),
+            retry_after,
+            body: resp.text().await?,
+        })
+    }
+
+    fn record(&self, directory: &Path, response: &HttpResponse) -> Result<(), InputError> {
+        let key = response
+            .url
+            .strip_prefix(&self.base_url)
+            .unwrap_or(&response.url);
+        record(&directory.join(&self.name), key, response)
+    }
+
+    fn replay(&self, directory: &Path, url: &str) -> Result<HttpResponse, InputError> {
+        let key = url.strip_prefix(&self.base_url).unwrap_or(url);
+        replay(&directory.join(&self.name), key)
+    }
+}
+
+/// The file of the recording of a request, named after its key in the directory
+fn recording_path(directory: &Path, key: &str) -> PathBuf {
+    let file_name = key
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+
+    // Long requests, e.g. JSON-RPC bodies, are shortened and told apart by their hash
+    let file_name = if file_name.len() > MAX_FILE_NAME {
+        let hash = format!("{:x}", Sha256::digest(key));
+        format!("{}_{}", &file_name[..MAX_FILE_NAME / 2], &hash[..16])
+    } else {
+        file_name
+    };
+
+    directory.join(format!("{file_name}.json"))
+}
+
+/// Archives the response to the request with the key, also for clients not speaking HTTP
+pub fn record(directory: &Path, key: &str, response: &HttpResponse) -> Result<(), InputError> {
+    let path = recording_path(directory, key);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::write(path, serde_json::to_string_pretty(response)?)?;
+
+    Ok(())
+}
+
+/// Reads the response to the request with the key written by `record`
+pub fn replay(directory: &Path, key: &str) -> Result<HttpResponse, InputError> {
+    let path = recording_path(directory, key);
+    let recording = match std::fs::read_to_string(&path) {
+        Ok(recording) => recording,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Err(InputError::MissingRecordingError(path));
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    Ok(serde_json::from_str(&recording)?)
+}
+
+/// The url without secret query parameters, which also keeps recordings stable between runs.
+/// Form parameters of the body are added to the query, so that POST requests differ as well.
+fn redact(request: &reqwest::Request) -> String {
+    let mut url = request.url().clone();
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(|body| Url::parse(&format!("form:?{}", String::from_utf8_lossy(body))))
+        .and_then(Result::ok);
+    let parameters = url
+        .query_pairs()
+        .chain(body.iter().flat_map(|body| body.query_pairs()))
+        .filter(|(key, _)| !SECRET_PARAMETERS.contains(&key.as_ref()))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+
+    if parameters.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(parameters);
+    }
+
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        weight: 100,
+        per: Duration::from_secs(1),
+    };
+
+    fn options(mode: HttpMode) -> HttpOptions {
+        HttpOptions {
+            mode,
+            ..HttpOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_recordings_without_secrets_and_without_sending() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/trades"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("[1]"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let directory = std::env::temp_dir().join(format!("recordings-{}", std::process::id()));
+
+        let recorder = HttpClient::new("test", &server.uri(), LIMIT)
+            .with_options(&options(HttpMode::Record(directory.clone())));
+        recorder
+            .send(1, || {
+                recorder.get("/trades?symbol=A&timestamp=1&signature=s")
+            })
+            .await
+            .unwrap();
+
+        // A later run signs with another timestamp, the recording still fits
+        let replayer = HttpClient::new("test", &server.uri(), LIMIT)
+            .with_options(&options(HttpMode::Replay(directory.clone())));
+        let response = replayer
+            .send(1, || {
+                replayer.get("/trades?symbol=A&timestamp=2&signature=t")
+            })
+            .await
+            .unwrap();
+        let missing = replayer
+            .send(1, || replayer.get("/trades?symbol=B"))
+            .await
+            .unwrap_err();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(response.body, "[1]");
+        assert!(!response.url.contains("signature") && !response.url.contains("timestamp"));
+        assert!(matches!(missing, InputError::MissingRecordingError(_)));
+    }
+
+    #[test]
+    fn secrets_are_left_out_of_the_query_and_the_form_body() {
+        let client = reqwest::Client::new();
+        let get = client
+            .get("https://api.example.com/trades?symbol=A&recvWindow=5000&signature=s")
+            .build()
+            .unwrap();
+        let post = client
+            .post("https://api.example.com/0/private/Ledgers")
+            .body("nonce=1700000000&ofs=50")
+            .build()
+            .unwrap();
+
+        assert_eq!(redact(&get), "https://api.example.com/trades?symbol=A");
+        assert_eq!(
+            redact(&post),
+            "https://api.example.com/0/private/Ledgers?ofs=50"
+        );
+    }
+
+    #[tokio::test]
+    async fn recorded_form_requests_replay_with_another_nonce() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/ledgers"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let directory = std::env::temp_dir().join(format!("form-{}", std::process::id()));
+
+        let recorder = HttpClient::new("test", &server.uri(), LIMIT)
+            .with_options(&options(HttpMode::Record(directory.clone())));
+        recorder
+            .send(1, || recorder.post("/ledgers").body("nonce=17&ofs=0"))
+            .await
+            .unwrap();
+        let recordings = std::fs::read_dir(directory.join("test"))
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect::<Vec<_>>();
+
+        let replayer = HttpClient::new("test", &server.uri(), LIMIT)
+            .with_options(&options(HttpMode::Replay(directory.clone())));
+        let replayed = replayer
+            .send(1, || replayer.post("/ledgers").body("nonce=18&ofs=0"))
+            .await;
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let [recording] = &recordings[..] else {
+            panic!("expected one recording, got {recordings:?}");
+        };
+        assert!(!recording.contains("nonce") && recording.contains("ofs=0"));
+        assert_eq!(replayed.unwrap().body, "{}");
+    }
+
+    #[tokio::test]
+    async fn bounds_the_requests_in_flight() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+            .expect(6)
+            .mount(&server)
+            .await;
+        let client = HttpClient::new("test", &server.uri(), LIMIT).with_options(&HttpOptions {
+            concurrency: 2,
+            ..HttpOptions::default()
+        });
+
+        let started = Instant::now();
+        let requests = (0..6).map(|_| client.send(1, || client.get("/")));
+        for result in futures::future::join_all(requests).await {
+            result.unwrap();
+        }
+
+        // Three rounds of two requests each
+        assert!(started.elapsed() >= Duration::from_millis(600));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_retry() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500).insert_header("Retry-After", "0"))
+            .expect(u64::from(MAX_RETRIES) + 1)
+            .mount(&server)
+            .await;
+        let client = HttpClient::new("test", &server.uri(), LIMIT);
+
+        let error = client.send(1, || client.get("/")).await.unwrap_err();
+
+        assert!(matches!(error, InputError::StatusError(500, _)));
+    }
+}
@@ -0,0 +1,473 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use sqlx::{query, query_as, Pool, Sqlite};
+
+use crate::{
+    data::{Airdrop, Amount, Application, Asset, Comission, Transaction, Transfer, ValueStore},
+    prices::PriceStore,
+};
+
+use self::requests::{request_private, result};
+
+use super::{
+    http::HttpClient, parse_number, ErrorCollector, InputError, MappedTransaction, Source,
+};
+
+pub mod requests;
+
+/// Suffixes of the variants of an asset held in staking, earn or futures
+const VARIANT_SUFFIXES: [&str; 6] = [".S", ".M", ".P", ".F", ".B", ".HOLD"];
+
+/// Maps Kraken's asset codes to the common tickers, e.g. `XXBT` to `BTC` and `DOT.S` to `DOT`
+pub fn kraken_asset(code: &str) -> Asset {
+    let code = VARIANT_SUFFIXES
+        .iter()
+        .find_map(|suffix| code.strip_suffix(suffix))
+        .unwrap_or(code);
+
+    // Legacy codes carry an X for crypto and a Z for fiat in front
+    let code = match code {
+        "XETC" | "XETH" | "XLTC" | "XMLN" | "XREP" | "XXBT" | "XXDG" | "XXLM" | "XXMR" | "XXRP"
+        | "XZEC" | "ZAUD" | "ZCAD" | "ZEUR" | "ZGBP" | "ZJPY" | "ZUSD" => &code[1..],
+        code => code,
+    };
+
+    let name = match code {
+        "XBT" => "BTC",
+        "XDG" => "DOGE",
+        "ETH2" => "ETH",
+        code => code,
+    };
+
+    Asset {
+        name: name.to_string(),
+        contract_address: None,
+    }
+}
+
+/// A single balance change of the ledger, from the API or the `ledgers.csv` export
+#[derive(Debug, Clone)]
+pub struct LedgerEntry {
+    pub id: String,
+    /// The id of the trade, deposit, etc. the entry belongs to, shared by all its entries
+    pub refid: String,
+    pub timestamp: DateTime<Utc>,
+    pub r#type: String,
+    pub subtype: String,
+    pub asset: Asset,
+    pub amount: f64,
+    pub fee: f64,
+}
+
+fn positive(amount: f64, asset: &Asset) -> Amount {
+    Amount {
+        amount: amount.abs(),
+        asset: asset.clone(),
+    }
+}
+
+/// Sums the amounts per asset, the order of the assets is kept
+fn sum_by_asset(amounts: impl Iterator<Item = Amount>) -> Vec<Amount> {
+    let mut sums: Vec<Amount> = vec![];
+
+    for amount in amounts {
+        match sums
+            .iter_mut()
+            .find(|sum| sum.asset.name == amount.asset.name)
+        {
+            Some(sum) => sum.amount += amount.amount,
+            None => sums.push(amount),
+        }
+    }
+
+    sums
+}
+
+fn map_exchange(
+    refid: &str,
+    entries: &[LedgerEntry],
+    prices: &PriceStore,
+) -> Result<Transaction, InputError> {
+    let spent = sum_by_asset(
+        entries
+            .iter()
+            .filter(|e| e.amount < 0.0)
+            .map(|e| positive(e.amount, &e.asset)),
+    );
+    let received = sum_by_asset(
+        entries
+            .iter()
+            .filter(|e| e.amount > 0.0)
+            .map(|e| positive(e.amount, &e.asset)),
+    );
+    let fees = sum_by_asset(
+        entries
+            .iter()
+            .filter(|e| e.fee != 0.0)
+            .map(|e| positive(e.fee, &e.asset)),
+    );
+
+    let ([source], [destination]) = (&spent[..], &received[..]) else {
+        return Err(InputError::UnsupportedError(format!(
+            "trade of {} spent and {} received assets",
+            spent.len(),
+            received.len()
+        )));
+    };
+
+    // Trades without a cash side are unvalued until a price of either asset is known
+    let timestamp = entries[0].timestamp;
+    let usd_amount = prices
+        .trade_usd_value(source, destination, timestamp.date_naive())
+        .unwrap_or(0.0);
+
+    let comission = match &fees[..] {
+        [] => None,
+        [fee] => Some(Comission {
+            usd_amount: prices.fee_usd_value(
+                fee,
+                source,
+                destination,
+                usd_amount,
+                timestamp.date_naive(),
+            ),
+            amount: fee.clone(),
+        }),
+        _ => {
+            return Err(InputError::UnsupportedError(
+                "trade with fees in several assets".to_string(),
+            ))
+        }
+    };
+
+    Ok(Transaction::exchange(
+        Application("Kraken".to_string()),
+        refid.to_string(),
+        source.clone(),
+        destination.clone(),
+        comission,
+        usd_amount,
+        timestamp,
+    ))
+}
+
+fn map_transfer(entry: &LedgerEntry, prices: &PriceStore) -> Transaction {
+    let date = entry.timestamp.date_naive();
+    let amount = positive(entry.amount, &entry.asset);
+    let comission = (entry.fee != 0.0).then(|| {
+        let fee = positive(entry.fee, &entry.asset);
+        Comission {
+            usd_amount: prices.usd_value(&fee, date).unwrap_or(0.0),
+            amount: fee,
+        }
+    });
+
+    let transfer = Transfer {
+        tx_id: entry.id.clone(),
+        store: ValueStore::Cex("Kraken".to_string()),
+        // The ledger has no prices, they come from the price store
+        usd_amount: prices.usd_value(&amount, date).unwrap_or(0.0),
+        amount,
+        comission,
+        timestamp: entry.timestamp,
+        note: format!("Kraken {}", entry.r#type),
+    };
+
+    if entry.amount < 0.0 {
+        Transaction::Withdrawal(transfer)
+    } else {
+        Transaction::Deposit(transfer)
+    }
+}
+
+fn map_reward(entry: &LedgerEntry, prices: &PriceStore) -> Transaction {
+    let amount = positive(entry.amount - entry.fee, &entry.asset);
+
+    Transaction::Airdrop(Airdrop {
+        tx_id: entry.id.clone(),
+        usd_amount: prices
+            .usd_value(&amount, entry.timestamp.date_naive())
+            .unwrap_or(0.0),
+        amount,
+        timestamp: entry.timestamp,
+        note: if entry.subtype.is_empty() {
+            format!("Kraken {}", entry.r#type)
+        } else {
+            format!("Kraken {} {}", entry.r#type, entry.subtype)
+        },
+    })
+}
+
+/// Maps the entries sharing a refid
+fn map_group(
+    refid: &str,
+    entries: &[LedgerEntry],
+    prices: &PriceStore,
+) -> Result<Vec<Transaction>, InputError> {
+    let Some(first) = entries.first() else {
+        return Ok(vec![]);
+    };
+
+    let transactions = match (first.r#type.as_str(), first.subtype.as_str()) {
+        ("trade" | "spend" | "receive" | "conversion", _) => {
+            vec![map_exchange(refid, entries, prices)?]
+        }
+        ("deposit" | "withdrawal", _) => entries
+            .iter()
+            .map(|entry| map_transfer(entry, prices))
+            .collect(),
+        ("staking" | "dividend" | "reward", _) | ("earn", "reward") => entries
+            .iter()
+            .filter(|e| e.amount > 0.0)
+            .map(|entry| map_reward(entry, prices))
+            .collect(),
+        // Airdrops and forks are credited as transfers without subtype
+        ("transfer", "") if entries.iter().all(|e| e.amount > 0.0) => entries
+            .iter()
+            .map(|entry| map_reward(entry, prices))
+            .collect(),
+        // Moving assets between spot, staking, earn and futures stays within Kraken
+        (
+            "transfer",
+            "spottostaking" | "stakingfromspot" | "stakingtospot" | "spotfromstaking"
+            | "spottofutures" | "spotfromfutures",
+        )
+        | ("earn", "migration" | "allocation" | "deallocation" | "autoallocation") => vec![],
+        (r#type, subtype) => {
+            return Err(InputError::UnsupportedError(format!(
+                "Kraken ledger type '{type}' with subtype '{subtype}'"
+            )))
+        }
+    };
+
+    Ok(transactions)
+}
+
+/// Groups the ledger entries by refid and maps every group to its transactions
+pub fn map_ledger(
+    entries: Vec<LedgerEntry>,
+    prices: &PriceStore,
+    errors: &ErrorCollector,
+) -> Result<Vec<Transaction>, InputError> {
+    let mut groups = BTreeMap::<String, Vec<LedgerEntry>>::new();
+    for entry in entries {
+        groups.entry(entry.refid.clone()).or_default().push(entry);
+    }
+
+    let mut transactions = vec![];
+    for (refid, entries) in groups {
+        let mapped =
+            map_group(&refid, &entries, prices).map_err(|e| e.for_record(Source::Kraken, &refid));
+        if let Some(mut mapped) = errors.collect(mapped)? {
+            transactions.append(&mut mapped);
+        }
+    }
+
+    Ok(transactions)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct LedgerEntryResult {
+    refid: String,
+    time: f64,
+    r#type: String,
+    subtype: String,
+    aclass: String,
+    asset: String,
+    amount: String,
+    fee: String,
+    balance: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct LedgersResult {
+    ledger: HashMap<String, LedgerEntryResult>,
+    count: i64,
+}
+
+/// Stores the ledger, starting one day before the newest stored entry
+async fn retrieve_and_save_ledger(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+) -> Result<(), InputError> {
+    let newest = query!("SELECT MAX(time) AS time FROM kraken_ledger")
+        .fetch_one(db)
+        .await?
+        .time;
+    let start = newest
+        .map(|time| format!("&start={}", time as i64 - Duration::days(1).num_seconds()))
+        .unwrap_or_default();
+
+    let mut offset = 0;
+    loop {
+        let response = request_private(
+            client,
+            "/0/private/Ledgers",
+            &format!("ofs={offset}{start}"),
+        )
+        .await?;
+        let ledgers = result::<LedgersResult>(&response)?;
+
+        let received = ledgers.ledger.len() as i64;
+        for (id, entry) in ledgers.ledger {
+            query!(
+                "INSERT OR REPLACE INTO kraken_ledger (
+                    id, refid, time, type, subtype, aclass,
+                    asset, amount, fee, balance, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, CURRENT_TIMESTAMP)",
+                id,
+                entry.refid,
+                entry.time,
+                entry.r#type,
+                entry.subtype,
+                entry.aclass,
+                entry.asset,
+                entry.amount,
+                entry.fee,
+                entry.balance
+            )
+            .execute(db)
+            .await?;
+        }
+
+        offset += received;
+        if received == 0 || offset >= ledgers.count {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn gather_data(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+    errors: &ErrorCollector,
+) -> Result<(), InputError> {
+    errors.collect(
+        retrieve_and_save_ledger(db, client)
+            .await
+            .map_err(|e| e.for_record(Source::Kraken, "ledger")),
+    )?;
+
+    Ok(())
+}
+
+struct LedgerRow {
+    id: String,
+    refid: String,
+    time: f64,
+    r#type: String,
+    subtype: String,
+    asset: String,
+    amount: String,
+    fee: String,
+}
+
+fn map_row(row: LedgerRow) -> Result<LedgerEntry, InputError> {
+    Ok(LedgerEntry {
+        timestamp: Utc
+            .timestamp_millis_opt((row.time * 1000.0).round() as i64)
+            .single()
+            .ok_or(InputError::ParseError {
+                field: "time",
+                value: row.time.to_string(),
+            })?,
+        asset: kraken_asset(&row.asset),
+        amount: parse_number("amount", &row.amount)?,
+        fee: parse_number("fee", &row.fee)?,
+        id: row.id,
+        refid: row.refid,
+        r#type: row.r#type,
+        subtype: row.subtype,
+    })
+}
+
+pub async fn get_all_trades(
+    db: &Pool<Sqlite>,
+    errors: &ErrorCollector,
+) -> Result<Vec<MappedTransaction>, InputError> {
+    let rows = query_as!(
+        LedgerRow,
+        r#"SELECT id, refid, time, type AS "type", subtype, asset, amount, fee
+        FROM kraken_ledger ORDER BY time"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    let prices = PriceStore::load(db).await?;
+    let mut entries = vec![];
+    for row in rows {
+        let id = row.id.clone();
+        let entry = map_row(row).map_err(|e| e.for_record(Source::Kraken, &id));
+        entries.extend(errors.collect(entry)?);
+    }
+
+    // Entries of the same refid become one transaction, which is found by its refid or id
+    Ok(map_ledger(entries, &prices, errors)?
+        .into_iter()
+        .map(|t| {
+            let raw_id = t.tx_id().to_string();
+            MappedTransaction::new("kraken_ledger", &raw_id, "Kraken", t)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn entry(id: &str, code: &str, amount: f64, fee: f64) -> LedgerEntry {
+        LedgerEntry {
+            id: id.to_string(),
+            refid: "TRADE".to_string(),
+            timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+            r#type: "trade".to_string(),
+            subtype: "".to_string(),
+            asset: kraken_asset(code),
+            amount,
+            fee,
+        }
+    }
+
+    fn eth_for_btc() -> Vec<LedgerEntry> {
+        vec![
+            entry("L1", "XXBT", -0.1, 0.0),
+            entry("L2", "XETH", 2.0, 0.002),
+        ]
+    }
+
+    #[test]
+    fn crypto_trades_are_valued_from_the_prices() {
+        let mut prices = PriceStore::default();
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        prices.insert(&kraken_asset("XETH"), date, 3000.0);
+
+        let transactions = map_ledger(eth_for_btc(), &prices, &ErrorCollector::new(false)).unwrap();
+        let [Transaction::Trade(trade)] = &transactions[..] else {
+            panic!("not one trade");
+        };
+
+        assert_eq!(trade.source.asset.name, "BTC");
+        assert_eq!(trade.destination.asset.name, "ETH");
+        assert!((trade.usd_amount - 6000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn crypto_trades_without_prices_are_unvalued() {
+        let transactions = map_ledger(
+            eth_for_btc(),
+            &PriceStore::default(),
+            &ErrorCollector::new(false),
+        )
+        .unwrap();
+        let [Transaction::Trade(trade)] = &transactions[..] else {
+            panic!("not one trade");
+        };
+
+        assert_eq!(trade.usd_amount, 0.0);
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::input::{
+    env_var,
+    http::{HttpClient, HttpOptions, HttpResponse, RateLimit},
+    InputError,
+};
+
+/// Kraken's call counter of the starter tier holds 15 points and decays by 0.33 per second
+const RATE_LIMIT: RateLimit = RateLimit {
+    weight: 15,
+    per: Duration::from_secs(45),
+};
+
+/// The ledger and trade history endpoints add two points to the call counter
+fn weight(path: &str) -> u32 {
+    match path {
+        "/0/private/Ledgers" | "/0/private/TradesHistory" => 2,
+        _ => 1,
+    }
+}
+
+/// The client for the Kraken API, `KRAKEN_BASE_URL` overrides the base url
+pub fn client(options: &HttpOptions) -> HttpClient {
+    HttpClient::from_env(
+        "kraken",
+        "KRAKEN_BASE_URL",
+        "https://api.kraken.com",
+        RATE_LIMIT,
+    )
+    .with_options(options)
+}
+
+/// Kraken answers with status 200 and reports errors in the body
+#[derive(Debug, serde::Deserialize)]
+struct KrakenResponse<T> {
+    error: Vec<String>,
+    result: Option<T>,
+}
+
+/// The result of the response, errors in the body become status errors
+pub fn result<T: DeserializeOwned>(response: &HttpResponse) -> Result<T, InputError> {
+    let response = response.json::<KrakenResponse<T>>()?;
+
+    match response.result {
+        Some(result) if response.error.is_empty() => Ok(result),
+        _ => Err(InputError::StatusError(200, response.error.join(", "))),
+    }
+}
+
+/// Signs the request as documented: HMAC-SHA512 of the path and the SHA256 of nonce and body,
+/// keyed with the base64 decoded secret
+pub fn sign(secret: &str, path: &str, nonce: u64, body: &str) -> Result<String, InputError> {
+    let secret = STANDARD
+        .decode(secret)
+        .map_err(|_| InputError::ConfigError("KRAKEN_SECRET_KEY is not base64".to_string()))?;
+
+    let hashed = Sha256::digest(format!("{nonce}{body}"));
+    let mut mac = Hmac::<Sha512>::new_from_slice(&secret).expect("HMAC can take a key of any size");
+    mac.update(path.as_bytes());
+    mac.update(&hashed);
+
+    Ok(STANDARD.encode(mac.finalize().into_bytes()))
+}
+
+/// Sends a private request, the nonce is the current time so that it always increases
+pub async fn request_private(
+    client: &HttpClient,
+    path: &str,
+    parameters: &str,
+) -> Result<HttpResponse, InputError> {
+    let api_key = env_var("KRAKEN_API_KEY")?;
+    let secret = env_var("KRAKEN_SECRET_KEY")?;
+    // Fails early on a malformed secret instead of on every attempt
+    sign(&secret, path, 0, "")?;
+
+    client
+        .send(weight(path), || {
+            let nonce = Utc::now().timestamp_millis() as u64;
+            let body = if parameters.is_empty() {
+                format!("nonce={nonce}")
+            } else {
+                format!("nonce={nonce}&{parameters}")
+            };
+            let signature = sign(&secret, path, nonce, &body).unwrap_or_default();
+
+            client
+                .post(path)
+                .header("API-Key", &api_key)
+                .header("API-Sign", signature)
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(body)
+        })
+        .await
+}
@@ -0,0 +1,393 @@
+use std::collections::HashSet;
+
+use sqlx::{query, Pool, Sqlite};
+
+use crate::data::{Amount, Transaction, ValueStore};
+
+use super::{ErrorCollector, InputError, MappedSource, MappedTransaction, Source};
+
+/// The exchanges the mappers store transfers with, known before any of them is fetched
+const EXCHANGES: [&str; 6] = ["Binance", "Bison", "Bitpanda", "Coinbase", "Kraken", "MEXC"];
+
+type AssetKey = (String, Option<String>);
+type StoreKey = (String, Option<String>, Option<String>);
+
+fn asset_key(amount: &Amount) -> AssetKey {
+    (
+        amount.asset.name.clone(),
+        amount.asset.contract_address.clone(),
+    )
+}
+
+fn store_key(store: &ValueStore) -> StoreKey {
+    match store {
+        ValueStore::Cex(name) => (name.clone(), None, None),
+        ValueStore::Wallet {
+            name,
+            network,
+            address,
+        } => (name.clone(), Some(network.clone()), Some(address.clone())),
+    }
+}
+
+/// The assets and stores of the mapped transactions of all sources
+struct Known {
+    assets: HashSet<AssetKey>,
+    stores: HashSet<StoreKey>,
+}
+
+async fn known(db: &Pool<Sqlite>) -> Result<Known, InputError> {
+    let mut assets = HashSet::new();
+    for row in query!("SELECT DISTINCT asset, contract_address FROM transaction_legs")
+        .fetch_all(db)
+        .await?
+    {
+        assets.insert((row.asset, row.contract_address));
+    }
+    for row in query!("SELECT DISTINCT asset, contract_address FROM transaction_fees")
+        .fetch_all(db)
+        .await?
+    {
+        assets.insert((row.asset, row.contract_address));
+    }
+
+    let mut stores = EXCHANGES
+        .iter()
+        .map(|name| (name.to_string(), None, None))
+        .collect::<HashSet<_>>();
+    for row in query!(
+        r#"SELECT DISTINCT store_name AS "store_name!", store_network, store_address
+        FROM transaction_legs WHERE store_name IS NOT NULL"#
+    )
+    .fetch_all(db)
+    .await?
+    {
+        stores.insert((row.store_name, row.store_network, row.store_address));
+    }
+
+    Ok(Known { assets, stores })
+}
+
+fn check_amount(known: &Known, what: &str, amount: &Amount) -> Result<(), InputError> {
+    if !amount.amount.is_finite() || amount.amount < 0.0 {
+        return Err(InputError::InvalidError(format!(
+            "{what} amount {} is negative or not a number",
+            amount.amount
+        )));
+    }
+    if !amount.asset.is_fiat() && !known.assets.contains(&asset_key(amount)) {
+        return Err(InputError::InvalidError(format!(
+            "{what} asset '{}' is not known from any source",
+            amount.asset.name
+        )));
+    }
+
+    Ok(())
+}
+
+fn check_store(known: &Known, what: &str, store: &ValueStore) -> Result<(), InputError> {
+    if known.stores.contains(&store_key(store)) {
+        Ok(())
+    } else {
+        Err(InputError::InvalidError(format!(
+            "{what} store {store:?} is not known from any source"
+        )))
+    }
+}
+
+/// Checks that the transaction only uses assets and stores that are known and adds up
+fn validate(known: &Known, transaction: &Transaction) -> Result<(), InputError> {
+    if transaction.tx_id().trim().is_empty() {
+        return Err(InputError::InvalidError("the tx_id is empty".to_string()));
+    }
+
+    let usd_amount = match transaction {
+        Transaction::Trade(trade) => {
+            check_amount(known, "source", &trade.source)?;
+            check_amount(known, "destination", &trade.destination)?;
+            if let Some(comission) = &trade.comission {
+                check_amount(known, "comission", &comission.amount)?;
+            }
+            if trade.source.asset.name == trade.destination.asset.name {
+                return Err(InputError::InvalidError(
+                    "a trade needs two different assets".to_string(),
+                ));
+            }
+            trade.usd_amount
+        }
+        Transaction::CurrencyExchange(exchange) => {
+            check_amount(known, "source", &exchange.source)?;
+            check_amount(known, "destination", &exchange.destination)?;
+            if let Some(comission) = &exchange.comission {
+                check_amount(known, "comission", &comission.amount)?;
+            }
+            if exchange.source.asset.name == exchange.destination.asset.name {
+                return Err(InputError::InvalidError(
+                    "a currency exchange needs two different currencies".to_string(),
+                ));
+            }
+            exchange.usd_amount
+        }
+        Transaction::Airdrop(airdrop) => {
+            check_amount(known, "airdropped", &airdrop.amount)?;
+            airdrop.usd_amount
+        }
+        Transaction::Bridge(bridge) => {
+            check_amount(known, "bridged", &bridge.amount)?;
+            check_amount(known, "comission", &bridge.comission)?;
+            check_store(known, "source", &bridge.source)?;
+            check_store(known, "destination", &bridge.destination)?;
+            if bridge.comission.asset.name != bridge.amount.asset.name
+                || bridge.comission.amount > bridge.amount.amount
+            {
+                return Err(InputError::InvalidError(
+                    "the comission of a bridge is a part of the bridged amount".to_string(),
+                ));
+            }
+            0.0
+        }
+        Transaction::Deposit(transfer) | Transaction::Withdrawal(transfer) => {
+            check_amount(known, "transferred", &transfer.amount)?;
+            if let Some(comission) = &transfer.comission {
+                check_amount(known, "comission", &comission.amount)?;
+            }
+            check_store(known, "transfer", &transfer.store)?;
+            transfer.usd_amount
+        }
+    };
+
+    if !usd_amount.is_finite() || usd_amount < 0.0 {
+        return Err(InputError::InvalidError(format!(
+            "usd amount {usd_amount} is negative or not a number"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Checks that no transaction of any source, except the one being edited, has the tx_id
+async fn check_unique(
+    db: &Pool<Sqlite>,
+    tx_id: &str,
+    editing: Option<&str>,
+) -> Result<(), InputError> {
+    let manual = MappedSource::Manual.name();
+    let taken = query!(
+        "SELECT tx_id FROM transactions WHERE tx_id = $1 AND source != $2
+        UNION SELECT tx_id FROM manual_transactions WHERE tx_id = $1",
+        tx_id,
+        manual
+    )
+    .fetch_optional(db)
+    .await?
+    .is_some();
+
+    if taken && editing != Some(tx_id) {
+        return Err(InputError::InvalidError(format!(
+            "tx_id '{tx_id}' is already taken"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Parses and validates a transaction in the shape `display` prints it as JSON
+async fn parse(db: &Pool<Sqlite>, json: &str) -> Result<Transaction, InputError> {
+    let transaction = serde_json::from_str(json)?;
+    validate(&known(db).await?, &transaction)?;
+
+    Ok(transaction)
+}
+
+pub async fn add_transaction(db: &Pool<Sqlite>, json: &str) -> Result<(), InputError> {
+    let transaction = parse(db, json).await?;
+    let tx_id = transaction.tx_id();
+    check_unique(db, tx_id, None).await?;
+
+    let data = serde_json::to_string(&transaction)?;
+    query!(
+        "INSERT INTO manual_transactions (tx_id, transaction_data, created_at, updated_at)
+        VALUES ($1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)",
+        tx_id,
+        data
+    )
+    .execute(db)
+    .await?;
+
+    println!("Added manual transaction {tx_id}");
+
+    Ok(())
+}
+
+/// Replaces the manual transaction, which may get another tx_id by that
+pub async fn edit_transaction(
+    db: &Pool<Sqlite>,
+    tx_id: &str,
+    json: &str,
+) -> Result<(), InputError> {
+    let transaction = parse(db, json).await?;
+    let new_tx_id = transaction.tx_id();
+    check_unique(db, new_tx_id, Some(tx_id)).await?;
+
+    let data = serde_json::to_string(&transaction)?;
+    let result = query!(
+        "UPDATE manual_transactions
+        SET tx_id = $1, transaction_data = $2, updated_at = CURRENT_TIMESTAMP
+        WHERE tx_id = $3",
+        new_tx_id,
+        data,
+        tx_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(InputError::InvalidError(format!(
+            "there is no manual transaction '{tx_id}'"
+        )));
+    }
+
+    println!("Edited manual transaction {new_tx_id}");
+
+    Ok(())
+}
+
+pub async fn delete_transaction(db: &Pool<Sqlite>, tx_id: &str) -> Result<(), InputError> {
+    let result = query!("DELETE FROM manual_transactions WHERE tx_id = $1", tx_id)
+        .execute(db)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(InputError::InvalidError(format!(
+            "there is no manual transaction '{tx_id}'"
+        )));
+    }
+
+    println!("Deleted manual transaction {tx_id}");
+
+    Ok(())
+}
+
+pub async fn get_all_trades(
+    db: &Pool<Sqlite>,
+    errors: &ErrorCollector,
+) -> Result<Vec<MappedTransaction>, InputError> {
+    let rows =
+        query!("SELECT tx_id, transaction_data FROM manual_transactions ORDER BY created_at")
+            .fetch_all(db)
+            .await?;
+
+    let mut transactions = vec![];
+    for row in rows {
+        let transaction = serde_json::from_str::<Transaction>(&row.transaction_data)
+            .map_err(|e| InputError::from(e).for_record(Source::Manual, &row.tx_id));
+
+        if let Some(transaction) = errors.collect(transaction)? {
+            transactions.push(MappedTransaction::new(
+                "manual_transactions",
+                &row.tx_id,
+                "manual",
+                transaction,
+            ));
+        }
+    }
+
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::data::{Application, Asset, Trade, Transfer};
+
+    fn amount(amount: f64, name: &str) -> Amount {
+        Amount {
+            amount,
+            asset: Asset {
+                name: name.to_string(),
+                contract_address: None,
+            },
+        }
+    }
+
+    fn deposit(tx_id: &str, store: &str, amount: Amount) -> Transaction {
+        Transaction::Deposit(Transfer {
+            tx_id: tx_id.to_string(),
+            store: ValueStore::Cex(store.to_string()),
+            usd_amount: amount.amount,
+            amount,
+            comission: None,
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 2, 12, 0, 0).unwrap(),
+            note: "".to_string(),
+        })
+    }
+
+    fn trade(source: Amount, destination: Amount) -> Transaction {
+        Transaction::Trade(Trade {
+            application: Application("Kraken".to_string()),
+            tx_id: "m1".to_string(),
+            source,
+            destination,
+            comission: None,
+            usd_amount: 100.0,
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 2, 12, 0, 0).unwrap(),
+        })
+    }
+
+    fn known() -> Known {
+        Known {
+            assets: HashSet::from([("BTC".to_string(), None)]),
+            stores: HashSet::from([("Kraken".to_string(), None, None)]),
+        }
+    }
+
+    #[test]
+    fn only_known_assets_and_stores_and_sound_amounts_are_valid() {
+        let known = known();
+
+        assert!(validate(&known, &trade(amount(100.0, "USD"), amount(0.01, "BTC"))).is_ok());
+        assert!(validate(&known, &trade(amount(100.0, "USD"), amount(1.0, "FOO"))).is_err());
+        assert!(validate(&known, &trade(amount(-1.0, "USD"), amount(0.01, "BTC"))).is_err());
+        assert!(validate(&known, &trade(amount(0.01, "BTC"), amount(0.01, "BTC"))).is_err());
+        assert!(validate(&known, &deposit("m2", "Kraken", amount(5.0, "EUR"))).is_ok());
+        assert!(validate(&known, &deposit("m2", "Krakn", amount(5.0, "EUR"))).is_err());
+        assert!(validate(&known, &deposit(" ", "Kraken", amount(5.0, "EUR"))).is_err());
+    }
+
+    #[tokio::test]
+    async fn added_and_edited_transactions_keep_their_tx_id_unique() {
+        let db = crate::memory_db().await;
+        let json = |transaction: Transaction| serde_json::to_string(&transaction).unwrap();
+
+        add_transaction(&db, &json(deposit("m1", "Kraken", amount(5.0, "EUR"))))
+            .await
+            .unwrap();
+        add_transaction(&db, &json(deposit("m2", "Kraken", amount(7.0, "EUR"))))
+            .await
+            .unwrap();
+
+        let again = json(deposit("m1", "Kraken", amount(1.0, "EUR")));
+        assert!(add_transaction(&db, &again).await.is_err());
+        assert!(edit_transaction(&db, "m2", &again).await.is_err());
+        let missing = json(deposit("m9", "Kraken", amount(1.0, "EUR")));
+        assert!(edit_transaction(&db, "m9", &missing).await.is_err());
+        assert!(edit_transaction(&db, "m2", "{}").await.is_err());
+
+        edit_transaction(
+            &db,
+            "m2",
+            &json(deposit("m3", "Kraken", amount(8.0, "EUR"))),
+        )
+        .await
+        .unwrap();
+        let stored = get_all_trades(&db, &ErrorCollector::new(false))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|mapped| mapped.transaction.tx_id().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(stored, ["m1", "m3"]);
+    }
+}
@@ -1,12 +1,18 @@
 use chrono::{TimeZone, Utc};
-use futures::future::join_all;
-use sqlx::{query, Pool, Sqlite};
+use futures::{stream, StreamExt};
+use sqlx::{query, query_as, Pool, Sqlite};
 
-use crate::data::{Amount, Application, Asset, Comission, Trade, Transaction};
+use crate::{
+    data::{Amount, Application, Asset, Comission, Trade, Transaction},
+    prices::PriceStore,
+};
 
-use self::requests::{get_symbols, request_signed};
+use self::requests::{get_symbols, request_all_pages, request_signed};
 
-use super::InputError;
+use super::{
+    http::HttpClient, parse_number, save_balances, ErrorCollector, InputError, MappedTransaction,
+    ReportedBalance, Source,
+};
 
 pub mod requests;
 pub mod trades;
@@ -32,14 +38,52 @@ struct MyTradesResult {
     client_order_id: Option<String>,
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct AccountResult {
+    balances: Vec<BalanceResult>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BalanceResult {
+    asset: String,
+    free: String,
+    locked: String,
+}
+
+/// Snapshots the balances of the spot account, to reconcile them with the trades
+async fn retrieve_and_save_balances(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+) -> Result<(), InputError> {
+    let account = request_signed(client, "account", "")
+        .await?
+        .json::<AccountResult>()?;
+
+    let mut balances = vec![];
+    for balance in account.balances {
+        balances.push(ReportedBalance {
+            account: "spot".to_string(),
+            amount: parse_number("free", &balance.free)? + parse_number("locked", &balance.locked)?,
+            asset: balance.asset,
+        });
+    }
+
+    save_balances(db, Source::Mexc, &balances).await
+}
+
 async fn retrieve_and_save_trades_for_symbol(
     db: &Pool<Sqlite>,
+    client: &HttpClient,
     symbol: String,
 ) -> Result<(), InputError> {
-    let trade_result = request_signed("myTrades", &format!("symbol={symbol}"))
-        .await?
-        .json::<Vec<MyTradesResult>>()
-        .await?;
+    let trade_result = request_all_pages::<MyTradesResult>(
+        client,
+        "myTrades",
+        &format!("symbol={symbol}"),
+        |trade| trade.id.clone(),
+        |trade| trade.time,
+    )
+    .await?;
 
     for trade in trade_result {
         let exists = query!("SELECT id FROM mexc_my_trades WHERE id = $1", trade.id)
@@ -83,82 +127,142 @@ async fn retrieve_and_save_trades_for_symbol(
     Ok(())
 }
 
-pub async fn get_all_trades(db: &Pool<Sqlite>) -> Result<Vec<Transaction>, InputError> {
-    let trades = query!("SELECT * FROM mexc_my_trades")
-        .fetch_all(db)
-        .await?
-        .into_iter()
-        .map(|row| {
-            if !row.symbol.ends_with("USDT") {
-                panic!("Unsupported symbol {}", row.symbol);
-            }
-
-            let crypto = Amount {
-                amount: row.qty.parse().unwrap(),
-                asset: Asset {
-                    name: row.symbol.split("USDT").next().unwrap().to_string(),
-                    contract_address: None,
-                },
-            };
-
-            let usdt = Amount {
-                amount: row.quote_qty.parse().unwrap(),
-                asset: Asset {
-                    name: "USDT".to_string(),
-                    contract_address: None,
-                },
-            };
-
-            let comission = if row.commission == "0" {
-                None
-            } else {
-                Some(Comission {
-                    amount: Amount {
-                        amount: row.commission.parse().unwrap(),
-                        asset: Asset {
-                            name: row.commission_asset,
-                            contract_address: None,
-                        },
-                    },
-                    usd_amount: row.commission.parse().unwrap(),
-                })
-            };
-
-            let (source, destination) = if row.is_buyer != 1 {
-                // sold crypto
-                (crypto, usdt)
-            } else {
-                // bought crypto
-                (usdt, crypto)
-            };
-
-            Transaction::Trade(Trade {
-                application: Application("MEXC".to_string()),
-                tx_id: row.id,
-                source,
-                destination,
-                comission,
-                usd_amount: row.quote_qty.parse().unwrap(),
-                timestamp: Utc.timestamp_millis_opt(row.time).unwrap(),
-            })
+struct MyTradeRow {
+    symbol: String,
+    id: String,
+    qty: String,
+    quote_qty: String,
+    commission: String,
+    commission_asset: String,
+    time: i64,
+    is_buyer: i64,
+}
+
+fn map_trade(row: MyTradeRow, prices: &PriceStore) -> Result<Transaction, InputError> {
+    let Some(crypto_name) = row.symbol.strip_suffix("USDT") else {
+        return Err(InputError::UnsupportedError(format!(
+            "symbol {}",
+            row.symbol
+        )));
+    };
+
+    let crypto = Amount {
+        amount: parse_number("qty", &row.qty)?,
+        asset: Asset {
+            name: crypto_name.to_string(),
+            contract_address: None,
+        },
+    };
+
+    let usdt = Amount {
+        amount: parse_number("quote_qty", &row.quote_qty)?,
+        asset: Asset {
+            name: "USDT".to_string(),
+            contract_address: None,
+        },
+    };
+
+    let usd_amount = usdt.amount;
+    let (source, destination) = if row.is_buyer != 1 {
+        // sold crypto
+        (crypto, usdt)
+    } else {
+        // bought crypto
+        (usdt, crypto)
+    };
+
+    let timestamp = Utc
+        .timestamp_millis_opt(row.time)
+        .single()
+        .ok_or(InputError::ParseError {
+            field: "time",
+            value: row.time.to_string(),
+        })?;
+
+    // The commission is a quantity of its asset, e.g. MX, and valued like one
+    let comission = if row.commission == "0" {
+        None
+    } else {
+        let commission = Amount {
+            amount: parse_number("commission", &row.commission)?,
+            asset: Asset {
+                name: row.commission_asset,
+                contract_address: None,
+            },
+        };
+
+        Some(Comission {
+            usd_amount: prices.fee_usd_value(
+                &commission,
+                &source,
+                &destination,
+                usd_amount,
+                timestamp.date_naive(),
+            ),
+            amount: commission,
         })
-        .collect::<Vec<_>>();
+    };
+
+    Ok(Transaction::Trade(Trade {
+        application: Application("MEXC".to_string()),
+        tx_id: row.id,
+        source,
+        destination,
+        comission,
+        usd_amount,
+        timestamp,
+    }))
+}
+
+pub async fn get_all_trades(
+    db: &Pool<Sqlite>,
+    errors: &ErrorCollector,
+) -> Result<Vec<MappedTransaction>, InputError> {
+    let rows = query_as!(
+        MyTradeRow,
+        "SELECT symbol, id, qty, quote_qty, commission, commission_asset, time, is_buyer
+        FROM mexc_my_trades"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let prices = PriceStore::load(db).await?;
+    let mut trades = vec![];
+    for row in rows {
+        let id = row.id.clone();
+        let trade = map_trade(row, &prices).map_err(|e| e.for_record(Source::Mexc, &id));
+
+        if let Some(trade) = errors.collect(trade)? {
+            trades.push(MappedTransaction::new("mexc_my_trades", &id, "MEXC", trade));
+        }
+    }
 
     Ok(trades)
 }
 
-pub async fn gather_data(db: &Pool<Sqlite>) -> Result<(), InputError> {
+pub async fn gather_data(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+    errors: &ErrorCollector,
+) -> Result<(), InputError> {
     let symbols = get_symbols().await?;
 
-    let retrieved_trades = symbols
-        .into_iter()
-        .map(|sym| retrieve_and_save_trades_for_symbol(db, sym));
+    let retrieved_trades = symbols.into_iter().map(|sym| async move {
+        retrieve_and_save_trades_for_symbol(db, client, sym.clone())
+            .await
+            .map_err(|e| e.for_record(Source::Mexc, &sym))
+    });
+
+    let mut retrieved_trades =
+        stream::iter(retrieved_trades).buffer_unordered(client.concurrency());
+    while let Some(result) = retrieved_trades.next().await {
+        errors.collect(result)?;
+    }
 
-    // The collect is necessary for easy error handling
-    join_all(retrieved_trades)
+    let result = retrieve_and_save_balances(db, client)
         .await
-        .into_iter()
-        .collect::<Result<Vec<_>, _>>()?;
+        .map_err(|e| e.for_record(Source::Mexc, "account"));
+    errors.collect(result)?;
 
     Ok(())
 }
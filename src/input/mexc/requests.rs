@@ -1,18 +1,43 @@
+use std::{collections::HashSet, time::Duration};
+
 use hmac::Mac;
-use reqwest::Response;
+use serde::de::DeserializeOwned;
 
 use super::InputError;
-use crate::input::HmacSha256;
+use crate::input::{
+    http::{HttpClient, HttpOptions, HttpResponse, RateLimit},
+    HmacSha256,
+};
 
-pub async fn request(url: &str) -> Result<Response, InputError> {
-    let client = reqwest::Client::new();
+/// MEXC allows a weight of 500 every 10 seconds per endpoint, shared here to stay on the safe side
+const RATE_LIMIT: RateLimit = RateLimit {
+    weight: 500,
+    per: Duration::from_secs(10),
+};
 
-    let resp = client
-        .get(format!("https://api.mexc.com/api/v3/{url}",))
-        .header("X-MEXC-APIKEY", dotenv!("MEXC_ACCESS_KEY"))
-        .header("Content-Type", "application/json");
+/// The documented request weight of the endpoints
+fn weight(url: &str) -> u32 {
+    match url {
+        "myTrades" | "account" | "allOrders" | "exchangeInfo" => 10,
+        _ => 1,
+    }
+}
 
-    Ok(resp.send().await?)
+/// The client for the MEXC API, `MEXC_BASE_URL` overrides the base url
+pub fn client(options: &HttpOptions) -> HttpClient {
+    HttpClient::from_env("mexc", "MEXC_BASE_URL", "https://api.mexc.com", RATE_LIMIT)
+        .with_options(options)
+}
+
+pub async fn request(client: &HttpClient, url: &str) -> Result<HttpResponse, InputError> {
+    client
+        .send(weight(url), || {
+            client
+                .get(&format!("/api/v3/{url}"))
+                .header("X-MEXC-APIKEY", dotenv!("MEXC_ACCESS_KEY"))
+                .header("Content-Type", "application/json")
+        })
+        .await
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -21,44 +46,110 @@ struct TimeResult {
     server_time: u64,
 }
 
-pub async fn request_signed(url: &str, parameters: &str) -> Result<Response, InputError> {
-    let client = reqwest::Client::new();
+/// Signs the query parameters as documented
+pub fn sign(secret: &str, parameters: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(parameters.as_bytes());
+    let signature = mac.finalize().into_bytes();
+
+    format!("{:#01x}", signature)
+}
+
+/// MEXC rejects timestamps outside of the receive window with code 700003
+fn is_timestamp_error(error: &InputError) -> bool {
+    matches!(error, InputError::StatusError(400, body) if body.contains("700003"))
+}
 
-    let now = request("time")
-        .await?
-        .json::<TimeResult>()
-        .await?
-        .server_time;
+pub async fn request_signed(
+    client: &HttpClient,
+    url: &str,
+    parameters: &str,
+) -> Result<HttpResponse, InputError> {
+    let clock = client.clock();
+    let mut retried = false;
 
-    let parameters = if parameters.is_empty() {
-        format!("timestamp={now}")
-    } else {
-        format!("timestamp={now}&{parameters}")
-    };
+    loop {
+        clock
+            .sync(|| async {
+                Ok(request(client, "time")
+                    .await?
+                    .json::<TimeResult>()?
+                    .server_time as i64)
+            })
+            .await?;
 
-    let mut mac = HmacSha256::new_from_slice(dotenv!("MEXC_SECRET_KEY").as_bytes()).unwrap();
-    mac.update(parameters.as_bytes());
-    let signature = mac.finalize().into_bytes();
+        let result = client
+            .send(weight(url), || {
+                let now = clock.now_millis();
+                let parameters = if parameters.is_empty() {
+                    format!("timestamp={now}")
+                } else {
+                    format!("timestamp={now}&{parameters}")
+                };
+                let signature = sign(dotenv!("MEXC_SECRET_KEY"), &parameters);
 
-    let resp = client
-        .get(format!(
-            "https://api.mexc.com/api/v3/{url}?signature={:#01x}&{parameters}",
-            signature
-        ))
-        .header("X-MEXC-APIKEY", dotenv!("MEXC_ACCESS_KEY"))
-        .header("Content-Type", "application/json")
-        .send()
-        .await?;
-
-    if resp.status() != 200 {
-        panic!(
-            "Status error {}, {}",
-            resp.status().as_u16(),
-            resp.text().await?
-        );
+                client
+                    .get(&format!("/api/v3/{url}?signature={signature}&{parameters}"))
+                    .header("X-MEXC-APIKEY", dotenv!("MEXC_ACCESS_KEY"))
+                    .header("Content-Type", "application/json")
+            })
+            .await;
+
+        match result {
+            Err(error) if !retried && is_timestamp_error(&error) => {
+                clock.invalidate();
+                retried = true;
+            }
+            result => return result,
+        }
     }
+}
+
+/// The most rows MEXC returns for one request of a list
+pub const PAGE_LIMIT: usize = 100;
+
+/// Requests a signed list page by page. MEXC has no cursor, so every further page ends at
+/// the oldest row of the one before, whose rows are asked for again so that none is missed.
+pub async fn request_all_pages<T: DeserializeOwned>(
+    client: &HttpClient,
+    url: &str,
+    parameters: &str,
+    id: impl Fn(&T) -> String,
+    time: impl Fn(&T) -> i64,
+) -> Result<Vec<T>, InputError> {
+    let mut rows = vec![];
+    let mut seen = HashSet::new();
+    let mut end_time = None;
+
+    loop {
+        let mut page_parameters = vec![format!("limit={PAGE_LIMIT}")];
+        if !parameters.is_empty() {
+            page_parameters.insert(0, parameters.to_string());
+        }
+        if let Some(end_time) = end_time {
+            page_parameters.push(format!("endTime={end_time}"));
+        }
+
+        let page = request_signed(client, url, &page_parameters.join("&"))
+            .await?
+            .json::<Vec<T>>()?;
+        let full = page.len() >= PAGE_LIMIT;
+        let oldest = page.iter().map(&time).min();
+
+        let mut new = 0;
+        for row in page {
+            if seen.insert(id(&row)) {
+                rows.push(row);
+                new += 1;
+            }
+        }
 
-    Ok(resp)
+        match oldest {
+            Some(oldest) if full && new > 0 => end_time = Some(oldest),
+            _ => return Ok(rows),
+        }
+    }
 }
 
 pub async fn get_symbols() -> Result<Vec<String>, InputError> {
@@ -87,3 +178,178 @@ pub async fn get_symbols() -> Result<Vec<String>, InputError> {
 
     Ok(symbols)
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path, query_param, query_param_is_missing},
+        Match, Mock, MockServer, Request, ResponseTemplate,
+    };
+
+    use super::*;
+
+    /// Matches requests whose signature fits their other query parameters
+    struct Signed;
+
+    impl Match for Signed {
+        fn matches(&self, request: &Request) -> bool {
+            let query = request.url.query().unwrap_or_default();
+            let Some((signature, parameters)) = query.split_once('&') else {
+                return false;
+            };
+
+            request
+                .headers
+                .get("X-MEXC-APIKEY")
+                .map(|key| key.as_bytes())
+                == Some(dotenv!("MEXC_ACCESS_KEY").as_bytes())
+                && signature.strip_prefix("signature=")
+                    == Some(&sign(dotenv!("MEXC_SECRET_KEY"), parameters))
+        }
+    }
+
+    async fn server() -> (MockServer, HttpClient) {
+        let server = MockServer::start().await;
+        let client = HttpClient::new("mexc", &server.uri(), RATE_LIMIT);
+        (server, client)
+    }
+
+    async fn mount_time(server: &MockServer, calls: u64) {
+        Mock::given(method("GET"))
+            .and(path("/api/v3/time"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "serverTime": chrono::Utc::now().timestamp_millis() })),
+            )
+            .expect(calls)
+            .mount(server)
+            .await;
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Row {
+        id: String,
+        time: i64,
+    }
+
+    fn rows(ids: std::ops::Range<i64>) -> serde_json::Value {
+        ids.map(|id| json!({ "id": id.to_string(), "time": 1000 - id / 10 }))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn signs_requests_with_the_server_time() {
+        let (server, client) = server().await;
+        mount_time(&server, 1).await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/account"))
+            .and(Signed)
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        request_signed(&client, "account", "").await.unwrap();
+        // The measured offset is shared, the time is not asked for again
+        request_signed(&client, "account", "").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pages_back_until_a_page_is_not_full() {
+        let (server, client) = server().await;
+        mount_time(&server, 1).await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/myTrades"))
+            .and(query_param("symbol", "KASUSDT"))
+            .and(query_param_is_missing("endTime"))
+            .and(Signed)
+            .respond_with(ResponseTemplate::new(200).set_body_json(rows(0..100)))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // The second page starts at the oldest time of the first, whose rows come again
+        Mock::given(method("GET"))
+            .and(path("/api/v3/myTrades"))
+            .and(query_param("endTime", "991"))
+            .and(Signed)
+            .respond_with(ResponseTemplate::new(200).set_body_json(rows(90..130)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let rows = request_all_pages::<Row>(
+            &client,
+            "myTrades",
+            "symbol=KASUSDT",
+            |row| row.id.clone(),
+            |row| row.time,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(rows.len(), 130);
+        assert_eq!(rows.last().unwrap().id, "129");
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_returned_without_retry() {
+        let (server, client) = server().await;
+        mount_time(&server, 1).await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/account"))
+            .respond_with(ResponseTemplate::new(403).set_body_string("forbidden"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let error = request_signed(&client, "account", "").await.unwrap_err();
+
+        assert!(matches!(error, InputError::StatusError(403, body) if body == "forbidden"));
+    }
+
+    #[tokio::test]
+    async fn rate_limits_are_retried_after_the_requested_wait() {
+        let (server, client) = server().await;
+        mount_time(&server, 1).await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/account"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/account"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        request_signed(&client, "account", "").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejected_timestamps_measure_the_clock_again() {
+        let (server, client) = server().await;
+        mount_time(&server, 2).await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/account"))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .set_body_string(r#"{"code":700003,"msg":"Timestamp outside recvWindow"}"#),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/account"))
+            .and(Signed)
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        request_signed(&client, "account", "").await.unwrap();
+    }
+}
@@ -0,0 +1,670 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    time::Duration,
+};
+
+use chrono::{TimeZone, Utc};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde_json::{json, Value};
+use sqlx::{query, query_as, Pool, Sqlite};
+
+use crate::{
+    data::{Airdrop, Amount, Application, Asset, Comission, Transaction, Transfer, ValueStore},
+    prices::PriceStore,
+};
+
+use super::{
+    env_var,
+    evm::{rpc::call, swap::net_amounts},
+    http::{HttpClient, HttpOptions, RateLimit},
+    ErrorCollector, InputError, MappedTransaction, Source,
+};
+
+pub mod airdrop;
+pub mod swap;
+
+/// The public endpoint allows about 10 calls per second, `SOLANA_RPC_URL` points elsewhere
+const RATE_LIMIT: RateLimit = RateLimit {
+    weight: 10,
+    per: Duration::from_secs(1),
+};
+const DEFAULT_RPC_URL: &str = "https://api.mainnet-beta.solana.com";
+
+/// The most signatures `getSignaturesForAddress` returns at once
+const SIGNATURE_PAGE: usize = 1_000;
+
+/// The programs owning token accounts, the original one and Token-2022
+const TOKEN_PROGRAMS: [&str; 2] = [
+    "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+    "TokenzQdBNbLqP5VEhdkAS6EPFLC1PE3zEHbhfwZ5aV",
+];
+
+/// Wrapped SOL is counted as SOL, swaps wrap and unwrap it within the transaction
+const WRAPPED_SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+
+const LAMPORTS_PER_SOL: f64 = 1e9;
+
+/// Well known token mints, `SOLANA_TOKENS` adds more as `symbol=mint` pairs
+const TOKENS: [(&str, &str); 10] = [
+    ("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v", "USDC"),
+    ("Es9vMFrzaCERmJfrF4H2FYD4KCoNkY11McCe8BenwNYB", "USDT"),
+    ("JUPyiwrYJFskUPiHa7hkeR8VUtAeFoSYbKedZNsDvCN", "JUP"),
+    ("4k3Dyjzvzp8eMZWUXbBCjEvwSkkk59S5iCNLY3QrkX6R", "RAY"),
+    ("DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263", "BONK"),
+    ("EKpQGSJtjMFqKZ9KQanSqYXRcF8fBopzLHYxdM65zcjm", "WIF"),
+    ("jtojtomepa8beP8AuQc6eXt5FriJwfFMwQx2v2f9mCL", "JTO"),
+    ("HZ1JovNiVvGrGNiiYvEozEVgZ58xaU3RKwX8eACQBCt3", "PYTH"),
+    ("mSoLzYCxHdYgdzU16g5QSh3i5K3z3KZK7ytfqcJm7So", "mSOL"),
+    ("J1toso1uCk3RLmjorhTtrVwY9HJ7X8V9yYac6Y7kGCPn", "JitoSOL"),
+];
+
+/// A wallet configured by `SOLANA_WALLETS` as comma separated `name=address` pairs
+struct SolanaWallet {
+    name: String,
+    address: String,
+}
+
+fn client(options: &HttpOptions) -> HttpClient {
+    let url = dotenv::var("SOLANA_RPC_URL").unwrap_or(DEFAULT_RPC_URL.to_string());
+    HttpClient::new("solana", &url, RATE_LIMIT).with_options(options)
+}
+
+fn wallets() -> Result<Vec<SolanaWallet>, InputError> {
+    env_var("SOLANA_WALLETS")?
+        .split(',')
+        .filter(|wallet| !wallet.trim().is_empty())
+        .map(|wallet| match wallet.split_once('=') {
+            Some((name, address)) if !address.trim().is_empty() => Ok(SolanaWallet {
+                name: name.trim().to_string(),
+                address: address.trim().to_string(),
+            }),
+            _ => Err(InputError::ConfigError(format!(
+                "Solana wallet '{wallet}' is not of the form name=address"
+            ))),
+        })
+        .collect()
+}
+
+/// The asset of a mint, tokens without a known symbol are named by their mint
+fn token_asset(mint: &str) -> Asset {
+    let configured = dotenv::var("SOLANA_TOKENS").unwrap_or_default();
+    let configured = configured
+        .split(',')
+        .filter_map(|token| token.split_once('='))
+        .map(|(symbol, mint)| (mint.trim().to_string(), symbol.trim().to_string()));
+
+    let name = TOKENS
+        .iter()
+        .map(|(mint, symbol)| (mint.to_string(), symbol.to_string()))
+        .chain(configured)
+        .find(|(known, _)| known == mint)
+        .map(|(_, symbol)| symbol)
+        .unwrap_or(mint.to_string());
+
+    Asset {
+        name,
+        contract_address: Some(mint.to_string()),
+    }
+}
+
+fn sol() -> Asset {
+    Asset {
+        name: "SOL".to_string(),
+        contract_address: None,
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SignatureResult {
+    signature: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TokenAccountsResult {
+    value: Vec<TokenAccount>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TokenAccount {
+    pubkey: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionResult {
+    slot: i64,
+    block_time: Option<i64>,
+    meta: Option<Meta>,
+    transaction: ParsedTransaction,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Meta {
+    err: Option<Value>,
+    fee: u64,
+    pre_balances: Vec<u64>,
+    post_balances: Vec<u64>,
+    #[serde(default)]
+    pre_token_balances: Vec<TokenBalance>,
+    #[serde(default)]
+    post_token_balances: Vec<TokenBalance>,
+    #[serde(default)]
+    inner_instructions: Vec<InnerInstructions>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenBalance {
+    account_index: usize,
+    mint: String,
+    owner: Option<String>,
+    ui_token_amount: TokenAmount,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TokenAmount {
+    amount: String,
+    decimals: i32,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ParsedTransaction {
+    message: Message,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Message {
+    account_keys: Vec<AccountKey>,
+    instructions: Vec<Instruction>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AccountKey {
+    pubkey: String,
+    signer: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Instruction {
+    program_id: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct InnerInstructions {
+    instructions: Vec<Instruction>,
+}
+
+/// The token accounts the wallet owns, transfers of tokens only name those
+async fn token_accounts(client: &HttpClient, owner: &str) -> Result<Vec<String>, InputError> {
+    let mut accounts = vec![];
+
+    for program in TOKEN_PROGRAMS {
+        let result = call::<TokenAccountsResult>(
+            client,
+            "getTokenAccountsByOwner",
+            json!([owner, {"programId": program}, {"encoding": "jsonParsed"}]),
+        )
+        .await?;
+        accounts.extend(result.value.into_iter().map(|account| account.pubkey));
+    }
+
+    Ok(accounts)
+}
+
+/// The signatures of the account newer than `until`, newest first
+async fn signatures(
+    client: &HttpClient,
+    account: &str,
+    until: Option<&str>,
+) -> Result<Vec<String>, InputError> {
+    let mut result = vec![];
+    let mut before: Option<String> = None;
+
+    loop {
+        let mut config = json!({ "limit": SIGNATURE_PAGE });
+        if let Some(until) = until {
+            config["until"] = json!(until);
+        }
+        if let Some(before) = &before {
+            config["before"] = json!(before);
+        }
+
+        let page = call::<Vec<SignatureResult>>(
+            client,
+            "getSignaturesForAddress",
+            json!([account, config]),
+        )
+        .await?;
+        let is_last = page.len() < SIGNATURE_PAGE;
+        before = page.last().map(|s| s.signature.clone());
+        result.extend(page.into_iter().map(|s| s.signature));
+
+        if is_last {
+            return Ok(result);
+        }
+    }
+}
+
+async fn save_transaction(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+    signature: &str,
+) -> Result<(), InputError> {
+    let data = call::<Value>(
+        client,
+        "getTransaction",
+        json!([signature, {"encoding": "jsonParsed", "maxSupportedTransactionVersion": 0}]),
+    )
+    .await?;
+    let transaction = serde_json::from_value::<TransactionResult>(data.clone())?;
+    let data = data.to_string();
+
+    query!(
+        "INSERT INTO solana_transactions (signature, slot, block_time, data, created_at)
+        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
+        ON CONFLICT (signature) DO NOTHING",
+        signature,
+        transaction.slot,
+        transaction.block_time,
+        data
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Imports the transactions of the wallet and its token accounts since the last import
+async fn retrieve_and_save_wallet(
+    db: &Pool<Sqlite>,
+    client: &HttpClient,
+    wallet: &SolanaWallet,
+) -> Result<(), InputError> {
+    query!(
+        "INSERT INTO solana_wallets (address, name, created_at)
+        VALUES ($1, $2, CURRENT_TIMESTAMP)
+        ON CONFLICT (address) DO UPDATE SET name = excluded.name",
+        wallet.address,
+        wallet.name
+    )
+    .execute(db)
+    .await?;
+
+    let mut accounts = vec![wallet.address.clone()];
+    accounts.extend(token_accounts(client, &wallet.address).await?);
+
+    let known = query!("SELECT signature FROM solana_transactions")
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| row.signature)
+        .collect::<HashSet<_>>();
+
+    for account in &accounts {
+        let last_signature = query!(
+            "SELECT last_signature FROM solana_accounts WHERE wallet = $1 AND account = $2",
+            wallet.address,
+            account
+        )
+        .fetch_optional(db)
+        .await?
+        .and_then(|row| row.last_signature);
+
+        let signatures = signatures(client, account, last_signature.as_deref()).await?;
+        let Some(newest) = signatures.first() else {
+            continue;
+        };
+
+        stream::iter(signatures.iter().filter(|s| !known.contains(*s)))
+            .map(|signature| save_transaction(db, client, signature))
+            .buffer_unordered(client.concurrency())
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        for signature in &signatures {
+            query!(
+                "INSERT INTO solana_wallet_transactions (wallet, signature, created_at)
+                VALUES ($1, $2, CURRENT_TIMESTAMP)
+                ON CONFLICT (wallet, signature) DO NOTHING",
+                wallet.address,
+                signature
+            )
+            .execute(db)
+            .await?;
+        }
+
+        query!(
+            "INSERT INTO solana_accounts (wallet, account, last_signature, created_at)
+            VALUES ($1, $2, $3, CURRENT_TIMESTAMP)
+            ON CONFLICT (wallet, account) DO UPDATE SET last_signature = excluded.last_signature",
+            wallet.address,
+            account,
+            newest
+        )
+        .execute(db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn gather_data(
+    db: &Pool<Sqlite>,
+    options: &HttpOptions,
+    errors: &ErrorCollector,
+) -> Result<(), InputError> {
+    let client = client(options);
+
+    for wallet in wallets()? {
+        errors.collect(
+            retrieve_and_save_wallet(db, &client, &wallet)
+                .await
+                .map_err(|e| e.for_record(Source::Solana, &wallet.name)),
+        )?;
+    }
+
+    Ok(())
+}
+
+struct WalletRow {
+    address: String,
+    name: String,
+}
+
+struct TransactionRow {
+    wallet: String,
+    signature: String,
+    block_time: Option<i64>,
+    data: String,
+}
+
+/// A change of the balance of the wallet, in the smallest unit of the asset
+struct BalanceChange {
+    asset: Asset,
+    units: i128,
+    decimals: i32,
+}
+
+impl BalanceChange {
+    fn amount(&self) -> Amount {
+        Amount {
+            amount: self.units.unsigned_abs() as f64 / 10f64.powi(self.decimals),
+            asset: self.asset.clone(),
+        }
+    }
+}
+
+fn parse_units(value: &str) -> Result<i128, InputError> {
+    value.parse().map_err(|_| InputError::ParseError {
+        field: "amount",
+        value: value.to_string(),
+    })
+}
+
+/// The net change of every token the wallet owns an account of, by mint
+fn token_changes(meta: &Meta, owner: &str) -> Result<Vec<(String, i128, i32)>, InputError> {
+    let mut changes: Vec<(String, i128, i32)> = vec![];
+
+    let balances = meta
+        .pre_token_balances
+        .iter()
+        .map(|b| (b, -1))
+        .chain(meta.post_token_balances.iter().map(|b| (b, 1)));
+    for (balance, sign) in balances {
+        if balance.owner.as_deref() != Some(owner) {
+            continue;
+        }
+
+        let units = sign * parse_units(&balance.ui_token_amount.amount)?;
+        match changes
+            .iter_mut()
+            .find(|(mint, _, _)| mint == &balance.mint)
+        {
+            Some((_, change, _)) => *change += units,
+            None => changes.push((
+                balance.mint.clone(),
+                units,
+                balance.ui_token_amount.decimals,
+            )),
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Maps the balance changes of the wallet. Swaps through a known program become trades,
+/// tokens claimed from a known distributor become airdrops, and the fee is paid by the fee payer.
+fn map_transaction(
+    row: &TransactionRow,
+    wallet: &WalletRow,
+    prices: &PriceStore,
+) -> Result<Vec<Transaction>, InputError> {
+    let transaction = serde_json::from_str::<TransactionResult>(&row.data)?;
+    let meta = transaction.meta.ok_or(InputError::UnsupportedError(
+        "transaction without meta data".to_string(),
+    ))?;
+    let block_time = row.block_time.ok_or(InputError::UnsupportedError(
+        "transaction without block time".to_string(),
+    ))?;
+    let timestamp = Utc
+        .timestamp_opt(block_time, 0)
+        .single()
+        .ok_or(InputError::ParseError {
+            field: "blockTime",
+            value: block_time.to_string(),
+        })?;
+    let date = timestamp.date_naive();
+
+    let keys = &transaction.transaction.message.account_keys;
+    let index = keys.iter().position(|key| key.pubkey == wallet.address);
+    let is_signer = index.is_some_and(|i| keys[i].signer);
+    let is_fee_payer = index == Some(0);
+
+    // The lamports of the token accounts of the wallet are its SOL as well: the rent locked
+    // by opening one is returned on closing it, and wrapped SOL is held in them
+    let mut accounts = meta
+        .pre_token_balances
+        .iter()
+        .chain(&meta.post_token_balances)
+        .filter(|b| b.owner.as_deref() == Some(wallet.address.as_str()))
+        .map(|b| b.account_index)
+        .collect::<BTreeSet<_>>();
+    accounts.extend(index);
+    let mut lamports = accounts
+        .iter()
+        .map(|i| {
+            let pre = *meta.pre_balances.get(*i).unwrap_or(&0) as i128;
+            let post = *meta.post_balances.get(*i).unwrap_or(&0) as i128;
+            post - pre
+        })
+        .sum::<i128>();
+    let fee = if is_fee_payer { meta.fee as i128 } else { 0 };
+    lamports += fee;
+
+    let mut changes = vec![];
+    for (mint, units, decimals) in token_changes(&meta, &wallet.address)? {
+        if mint != WRAPPED_SOL_MINT && units != 0 {
+            changes.push(BalanceChange {
+                asset: token_asset(&mint),
+                units,
+                decimals,
+            });
+        }
+    }
+    if lamports != 0 {
+        changes.push(BalanceChange {
+            asset: sol(),
+            units: lamports,
+            decimals: 9,
+        });
+    }
+
+    let comission = (fee > 0).then(|| {
+        let amount = Amount {
+            amount: fee as f64 / LAMPORTS_PER_SOL,
+            asset: sol(),
+        };
+        Comission {
+            usd_amount: prices.usd_value(&amount, date).unwrap_or(0.0),
+            amount,
+        }
+    });
+
+    let signature = &row.signature;
+    let spent = changes
+        .iter()
+        .filter(|c| c.units < 0)
+        .map(BalanceChange::amount)
+        .collect::<Vec<_>>();
+    let received = changes
+        .iter()
+        .filter(|c| c.units > 0)
+        .map(BalanceChange::amount)
+        .collect::<Vec<_>>();
+
+    let programs = transaction
+        .transaction
+        .message
+        .instructions
+        .iter()
+        .map(|i| i.program_id.as_str())
+        .collect::<Vec<_>>();
+    let inner_programs = meta
+        .inner_instructions
+        .iter()
+        .flat_map(|inner| &inner.instructions)
+        .map(|i| i.program_id.as_str())
+        .collect::<Vec<_>>();
+
+    if meta.err.is_none() && is_signer {
+        if let (Some(protocol), Some((source, destination))) = (
+            swap::protocol(&programs, &inner_programs),
+            net_amounts(&spent, &received),
+        ) {
+            // Swaps without a stablecoin side are unvalued until a price of either is known
+            let usd_amount = prices
+                .trade_usd_value(&source, &destination, date)
+                .unwrap_or(0.0);
+
+            return Ok(vec![Transaction::exchange(
+                Application(protocol),
+                signature.clone(),
+                source,
+                destination,
+                comission,
+                usd_amount,
+                timestamp,
+            )]);
+        }
+    }
+
+    let store = ValueStore::Wallet {
+        name: wallet.name.clone(),
+        network: "solana".to_string(),
+        address: wallet.address.clone(),
+    };
+    let tx_id = |change: &BalanceChange| match &change.asset.contract_address {
+        Some(mint) => format!("{signature}-{mint}"),
+        None => signature.clone(),
+    };
+    let transfer = |change: &BalanceChange, note: &str| {
+        let amount = change.amount();
+        Transfer {
+            tx_id: tx_id(change),
+            store: store.clone(),
+            usd_amount: prices.usd_value(&amount, date).unwrap_or(0.0),
+            amount,
+            comission: None,
+            timestamp,
+            note: note.to_string(),
+        }
+    };
+
+    // Tokens are only airdrops when claimed from a known distributor, everything else
+    // received, signed or not, is a deposit e.g. from another wallet of the owner
+    let distributor =
+        airdrop::distributor(&programs, &inner_programs).filter(|_| meta.err.is_none());
+
+    let mut withdrawals = vec![];
+    let mut result = vec![];
+    for change in &changes {
+        let is_token = change.asset.contract_address.is_some();
+        match (change.units > 0, distributor.as_ref().filter(|_| is_token)) {
+            (false, _) => withdrawals.push(transfer(change, "solana transfer")),
+            (true, None) => result.push(Transaction::Deposit(transfer(change, "solana transfer"))),
+            (true, Some(distributor)) => {
+                let amount = change.amount();
+                result.push(Transaction::Airdrop(Airdrop {
+                    tx_id: tx_id(change),
+                    usd_amount: prices.usd_value(&amount, date).unwrap_or(0.0),
+                    amount,
+                    timestamp,
+                    note: format!("{distributor} airdrop"),
+                }))
+            }
+        }
+    }
+
+    if let Some(comission) = comission {
+        match withdrawals.first_mut() {
+            Some(withdrawal) => withdrawal.comission = Some(comission),
+            // e.g. claims or failed transactions only cost the fee
+            None => withdrawals.push(Transfer {
+                tx_id: signature.clone(),
+                store: store.clone(),
+                amount: Amount {
+                    amount: 0.0,
+                    asset: sol(),
+                },
+                comission: Some(comission),
+                usd_amount: 0.0,
+                timestamp,
+                note: "solana fee".to_string(),
+            }),
+        }
+    }
+
+    result.extend(withdrawals.into_iter().map(Transaction::Withdrawal));
+
+    Ok(result)
+}
+
+pub async fn get_all_trades(
+    db: &Pool<Sqlite>,
+    errors: &ErrorCollector,
+) -> Result<Vec<MappedTransaction>, InputError> {
+    let wallets = query_as!(WalletRow, "SELECT address, name FROM solana_wallets")
+        .fetch_all(db)
+        .await?;
+
+    let rows = query_as!(
+        TransactionRow,
+        "SELECT w.wallet, t.signature, t.block_time, t.data
+        FROM solana_wallet_transactions w
+        JOIN solana_transactions t ON t.signature = w.signature
+        ORDER BY t.slot"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let prices = PriceStore::load(db).await?;
+    let mut result = vec![];
+    for row in &rows {
+        let Some(wallet) = wallets.iter().find(|w| w.address == row.wallet) else {
+            continue;
+        };
+
+        let transactions = errors.collect(
+            map_transaction(row, wallet, &prices)
+                .map_err(|e| e.for_record(Source::Solana, &row.signature)),
+        )?;
+        result.extend(transactions.into_iter().flatten().map(|t| {
+            MappedTransaction::new("solana_transactions", &row.signature, &wallet.address, t)
+        }));
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,30 @@
+/// Well known airdrop distributor programs, tokens claimed through them are airdrops.
+/// `SOLANA_AIRDROP_PROGRAMS` adds more as `name=program` pairs.
+const PROGRAMS: [(&str, &str); 2] = [
+    ("meRjbQXFNf5En86FXT2YPz1dQzLj4Yb3xK8u1MVgqpb", "Jupiter"),
+    ("mERKcfxMC5SqJn4Ld4BUris3WKZZ1ojjWJ3A3J5CKxv", "Jito"),
+];
+
+fn program(program_id: &str) -> Option<String> {
+    let configured = dotenv::var("SOLANA_AIRDROP_PROGRAMS").unwrap_or_default();
+    let configured = configured
+        .split(',')
+        .filter_map(|program| program.split_once('='))
+        .map(|(name, program)| (program.trim().to_string(), name.trim().to_string()));
+
+    PROGRAMS
+        .iter()
+        .map(|(program, name)| (program.to_string(), name.to_string()))
+        .chain(configured)
+        .find(|(program, _)| program == program_id)
+        .map(|(_, name)| name)
+}
+
+/// The distributor of an airdrop, from the programs the transaction called directly or
+/// else from those called by them
+pub fn distributor(programs: &[&str], inner_programs: &[&str]) -> Option<String> {
+    programs
+        .iter()
+        .find_map(|id| program(id))
+        .or_else(|| inner_programs.iter().find_map(|id| program(id)))
+}
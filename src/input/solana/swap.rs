@@ -0,0 +1,41 @@
+/// Well known swap programs, aggregators first as they call the pools of the others.
+/// `SOLANA_DEX_PROGRAMS` adds more as `name=program` pairs.
+const PROGRAMS: [(&str, &str); 7] = [
+    ("JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4", "Jupiter"),
+    ("JUP4Fb2cqiRUcaTHdrPC8h2gNsA2ETXiPDD33WcGuJB", "Jupiter"),
+    ("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8", "Raydium"),
+    (
+        "CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK",
+        "Raydium CLMM",
+    ),
+    (
+        "CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C",
+        "Raydium CPMM",
+    ),
+    ("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc", "Orca"),
+    ("LBUZKhRxPF3XUpBCjp4YzTKgLccjZhTSDM9YuVaPwxo", "Meteora"),
+];
+
+fn program(program_id: &str) -> Option<String> {
+    let configured = dotenv::var("SOLANA_DEX_PROGRAMS").unwrap_or_default();
+    let configured = configured
+        .split(',')
+        .filter_map(|program| program.split_once('='))
+        .map(|(name, program)| (program.trim().to_string(), name.trim().to_string()));
+
+    PROGRAMS
+        .iter()
+        .map(|(program, name)| (program.to_string(), name.to_string()))
+        .chain(configured)
+        .find(|(program, _)| program == program_id)
+        .map(|(_, name)| name)
+}
+
+/// The protocol of a swap, from the programs the transaction called directly or else
+/// from those called by them
+pub fn protocol(programs: &[&str], inner_programs: &[&str]) -> Option<String> {
+    programs
+        .iter()
+        .find_map(|id| program(id))
+        .or_else(|| inner_programs.iter().find_map(|id| program(id)))
+}
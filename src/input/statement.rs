@@ -0,0 +1,500 @@
+use std::{collections::BTreeMap, path::Path};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use csv::StringRecord;
+use sha2::{Digest, Sha256};
+use sqlx::{query, Pool, Sqlite};
+
+use crate::{command_line_interface::StatementFormat, data::Transaction, prices::PriceStore};
+
+use super::{ErrorCollector, InputError, MappedTransaction, Source};
+
+pub mod binance;
+pub mod bison;
+pub mod bitpanda;
+pub mod coinbase;
+pub mod kraken;
+pub mod mexc;
+
+impl StatementFormat {
+    /// The name the rows of the format are stored with
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatementFormat::Coinbase => "coinbase",
+            StatementFormat::MexcSpot => "mexc_spot",
+            StatementFormat::Binance => "binance",
+            StatementFormat::Kraken => "kraken",
+            StatementFormat::Bitpanda => "bitpanda",
+            StatementFormat::Bison => "bison",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<StatementFormat> {
+        match name {
+            "coinbase" => Some(StatementFormat::Coinbase),
+            "mexc_spot" => Some(StatementFormat::MexcSpot),
+            "binance" => Some(StatementFormat::Binance),
+            "kraken" => Some(StatementFormat::Kraken),
+            "bitpanda" => Some(StatementFormat::Bitpanda),
+            "bison" => Some(StatementFormat::Bison),
+            _ => None,
+        }
+    }
+
+    pub fn parser(&self) -> Box<dyn StatementParser> {
+        match self {
+            StatementFormat::Coinbase => Box::new(coinbase::CoinbaseStatement),
+            StatementFormat::MexcSpot => Box::new(mexc::MexcSpotStatement),
+            StatementFormat::Binance => Box::new(binance::BinanceStatement),
+            StatementFormat::Kraken => Box::new(kraken::KrakenStatement),
+            StatementFormat::Bitpanda => Box::new(bitpanda::BitpandaStatement),
+            StatementFormat::Bison => Box::new(bison::BisonStatement),
+        }
+    }
+}
+
+/// How the numbers of a statement are written, told by the delimiter it was read with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    /// `1,234.56`, in statements split by commas
+    English,
+    /// `1.234,56`, in statements split by semicolons
+    German,
+    /// Rows imported before the notation was stored, the decimal separator is guessed
+    Unknown,
+}
+
+impl Notation {
+    fn of_delimiter(delimiter: u8) -> Notation {
+        match delimiter {
+            b';' => Notation::German,
+            _ => Notation::English,
+        }
+    }
+
+    /// The name the notation is stored with
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Notation::English => Some("english"),
+            Notation::German => Some("german"),
+            Notation::Unknown => None,
+        }
+    }
+
+    pub fn from_name(name: Option<&str>) -> Notation {
+        match name {
+            Some("english") => Notation::English,
+            Some("german") => Notation::German,
+            _ => Notation::Unknown,
+        }
+    }
+}
+
+/// A raw row of an imported statement, with the values by column name
+#[derive(Debug, Clone)]
+pub struct StatementRow {
+    /// The id given by the exchange, or a hash of the row if the statement has none
+    pub id: String,
+    pub file: String,
+    pub line: i64,
+    pub fields: BTreeMap<String, String>,
+    pub notation: Notation,
+}
+
+impl StatementRow {
+    pub fn get(&self, column: &'static str) -> Result<&str, InputError> {
+        self.fields
+            .get(column)
+            .map(|value| value.trim())
+            .ok_or(InputError::ParseError {
+                field: "column",
+                value: column.to_string(),
+            })
+    }
+
+    /// The value of the first of the columns present, for statements that renamed columns
+    pub fn get_any(&self, columns: &[&'static str]) -> Result<&str, InputError> {
+        columns
+            .iter()
+            .find_map(|column| self.get(column).ok())
+            .ok_or(InputError::ParseError {
+                field: "column",
+                value: columns.join(" or "),
+            })
+    }
+
+    /// The number in the column, ignoring currency symbols and thousands separators
+    pub fn number(&self, column: &'static str) -> Result<f64, InputError> {
+        self.money(column, self.get(column)?)
+    }
+
+    /// Parses a value of the row in the notation of its statement
+    pub fn money(&self, field: &'static str, value: &str) -> Result<f64, InputError> {
+        parse_money(field, value, self.notation)
+    }
+
+    /// Where the row came from, used in errors
+    pub fn location(&self) -> String {
+        format!("{}:{}", self.file, self.line)
+    }
+}
+
+/// Parses amounts in English or German notation, like `€1,234.56`, `-$3.21`, `1.234,56 €`
+/// or `1E-8`. The notation tells the decimal separator, the other one groups thousands.
+/// Without one the last of two different separators is the decimal one, a separator that
+/// occurs several times groups thousands and a lone one is decimal.
+pub fn parse_money(
+    field: &'static str,
+    value: &str,
+    notation: Notation,
+) -> Result<f64, InputError> {
+    let error = || InputError::ParseError {
+        field,
+        value: value.to_string(),
+    };
+
+    // An exponent follows a digit directly, other letters belong to currency codes
+    let exponent_at = value.char_indices().find(|&(i, c)| {
+        matches!(c, 'e' | 'E')
+            && value[..i].ends_with(|c: char| c.is_ascii_digit())
+            && value[i + 1..]
+                .trim_start_matches(['-', '+'])
+                .starts_with(|c: char| c.is_ascii_digit())
+    });
+    let (mantissa, exponent) = match exponent_at {
+        Some((i, _)) => {
+            let exponent = value[i + 1..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || matches!(c, '-' | '+'))
+                .collect::<String>();
+            (&value[..i], exponent.parse::<i32>().map_err(|_| error())?)
+        }
+        None => (value, 0),
+    };
+
+    let number = mantissa
+        .chars()
+        .filter(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | '-'))
+        .collect::<String>();
+
+    let decimal = match notation {
+        Notation::English => Some('.'),
+        Notation::German => Some(','),
+        Notation::Unknown => match (number.rfind(','), number.rfind('.')) {
+            (Some(comma), Some(point)) => Some(if comma > point { ',' } else { '.' }),
+            (Some(_), None) => (number.matches(',').count() == 1).then_some(','),
+            (None, Some(_)) => (number.matches('.').count() == 1).then_some('.'),
+            (None, None) => None,
+        },
+    };
+    let number = number
+        .chars()
+        .filter_map(|c| match c {
+            '.' | ',' if Some(c) == decimal => Some('.'),
+            '.' | ',' => None,
+            c => Some(c),
+        })
+        .collect::<String>();
+
+    number
+        .parse::<f64>()
+        .map(|number| number * 10f64.powi(exponent))
+        .map_err(|_| error())
+}
+
+/// Parses timestamps in RFC 3339 or `2024-01-31 12:00:00` with an optional `UTC` suffix
+pub fn parse_utc_timestamp(field: &'static str, value: &str) -> Result<DateTime<Utc>, InputError> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+
+    let value_without_zone = value.trim().trim_end_matches("UTC").trim();
+    NaiveDateTime::parse_from_str(value_without_zone, "%Y-%m-%d %H:%M:%S")
+        .map(|timestamp| timestamp.and_utc())
+        .map_err(|_| InputError::ParseError {
+            field,
+            value: value.to_string(),
+        })
+}
+
+/// A parser for the CSV statement export of an exchange
+pub trait StatementParser {
+    /// The source errors of the rows are reported for
+    fn source(&self) -> Source;
+
+    /// Whether the record is the header row, everything above it is skipped
+    fn is_header(&self, record: &StringRecord) -> bool;
+
+    /// The id the exchange gave the row, if the statement has one
+    fn native_id(&self, _fields: &BTreeMap<String, String>) -> Option<String> {
+        None
+    }
+
+    /// Maps a single row to the transactions it contains, values missing from the statement
+    /// come from the prices
+    fn map_row(
+        &self,
+        row: &StatementRow,
+        prices: &PriceStore,
+    ) -> Result<Vec<Transaction>, InputError>;
+
+    /// Maps all rows of the format, formats that spread a transaction over several rows
+    /// override this to group them
+    fn map_rows(
+        &self,
+        rows: Vec<StatementRow>,
+        prices: &PriceStore,
+        errors: &ErrorCollector,
+    ) -> Result<Vec<Transaction>, InputError> {
+        let mut transactions = vec![];
+
+        for row in rows {
+            let mapped = self
+                .map_row(&row, prices)
+                .map_err(|e| e.for_record(self.source(), &row.location()));
+
+            if let Some(mut mapped) = errors.collect(mapped)? {
+                transactions.append(&mut mapped);
+            }
+        }
+
+        Ok(transactions)
+    }
+}
+
+/// Whether the id was made up for a row of a statement without ids of its own, the hash
+/// of the row numbered among identical rows
+pub fn is_made_up_id(id: &str) -> bool {
+    id.split_once('-').is_some_and(|(hash, occurrence)| {
+        hash.len() == 64
+            && hash.chars().all(|c| c.is_ascii_hexdigit())
+            && occurrence.parse::<usize>().is_ok()
+    })
+}
+
+/// The delimiters statements are tried with, exports in a German locale use semicolons
+const DELIMITERS: [u8; 2] = [b',', b';'];
+
+/// Reads the rows of the statement file, skipping everything above the header
+pub fn read_statement(
+    parser: &dyn StatementParser,
+    path: &Path,
+) -> Result<Vec<StatementRow>, InputError> {
+    for delimiter in DELIMITERS {
+        if let Some(rows) = read_statement_with(parser, path, delimiter)? {
+            return Ok(rows);
+        }
+    }
+
+    Err(InputError::UnsupportedError(format!(
+        "no header of the statement format found in {}",
+        path.display()
+    )))
+}
+
+/// Reads the rows split by the delimiter, or nothing if the header is not found with it
+fn read_statement_with(
+    parser: &dyn StatementParser,
+    path: &Path,
+    delimiter: u8,
+) -> Result<Option<Vec<StatementRow>>, InputError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_path(path)?;
+
+    let file = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut header = None;
+    let mut rows = vec![];
+    let mut occurrences = BTreeMap::<String, usize>::new();
+
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default() as i64;
+
+        let Some(header) = &header else {
+            if parser.is_header(&record) {
+                header = Some(
+                    record
+                        .iter()
+                        .map(|c| c.trim().to_string())
+                        .collect::<Vec<_>>(),
+                );
+            }
+            continue;
+        };
+
+        if record.iter().all(|value| value.trim().is_empty()) {
+            continue;
+        }
+
+        let fields = header
+            .iter()
+            .cloned()
+            .zip(record.iter().map(|value| value.to_string()))
+            .collect::<BTreeMap<_, _>>();
+
+        let id = match parser.native_id(&fields) {
+            Some(id) => id,
+            None => {
+                // Identical rows are numbered, so that they are not merged into one
+                let hash = format!("{:x}", Sha256::digest(serde_json::to_string(&fields)?));
+                let occurrence = occurrences.entry(hash.clone()).or_default();
+                *occurrence += 1;
+                format!("{hash}-{occurrence}")
+            }
+        };
+
+        rows.push(StatementRow {
+            id,
+            file: file.clone(),
+            line,
+            fields,
+            notation: Notation::of_delimiter(delimiter),
+        });
+    }
+
+    Ok(header.map(|_| rows))
+}
+
+/// Stores the raw rows of the statement, rows imported before are skipped
+pub async fn import_statement(
+    db: &Pool<Sqlite>,
+    format: StatementFormat,
+    path: &Path,
+) -> Result<(), InputError> {
+    let parser = format.parser();
+    let rows = read_statement(parser.as_ref(), path)?;
+    let format_name = format.name();
+
+    let mut imported = 0;
+    for row in &rows {
+        let fields = serde_json::to_string(&row.fields)?;
+        let notation = row.notation.name();
+        let result = query!(
+            "INSERT OR IGNORE INTO statement_rows
+                (format, id, file, line, fields, notation, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP)",
+            format_name,
+            row.id,
+            row.file,
+            row.line,
+            fields,
+            notation
+        )
+        .execute(db)
+        .await?;
+
+        imported += result.rows_affected();
+    }
+
+    println!(
+        "Imported {imported} new of {} rows from {}",
+        rows.len(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+pub async fn get_all_trades(
+    db: &Pool<Sqlite>,
+    errors: &ErrorCollector,
+) -> Result<Vec<MappedTransaction>, InputError> {
+    let stored = query!(
+        "SELECT format, id, file, line, fields, notation FROM statement_rows ORDER BY file, line"
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut rows_by_format = BTreeMap::<String, Vec<StatementRow>>::new();
+    for row in stored {
+        rows_by_format
+            .entry(row.format)
+            .or_default()
+            .push(StatementRow {
+                id: row.id,
+                file: row.file,
+                line: row.line,
+                fields: serde_json::from_str(&row.fields)?,
+                notation: Notation::from_name(row.notation.as_deref()),
+            });
+    }
+
+    let prices = PriceStore::load(db).await?;
+    let mut transactions = vec![];
+    for (format_name, rows) in rows_by_format {
+        let Some(format) = StatementFormat::from_name(&format_name) else {
+            errors.collect::<()>(Err(InputError::UnsupportedError(format!(
+                "statement format {format_name}"
+            ))))?;
+            continue;
+        };
+
+        // Transactions are named after the row they come from, or the first of a group
+        transactions.extend(
+            format
+                .parser()
+                .map_rows(rows, &prices, errors)?
+                .into_iter()
+                .map(|t| {
+                    let raw_id = t.tx_id().to_string();
+                    MappedTransaction::new("statement_rows", &raw_id, &format_name, t)
+                }),
+        );
+    }
+
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn money_is_parsed_in_english_and_german_notation() {
+        use Notation::{English, German, Unknown};
+        let cases = [
+            ("€1,234.56", English, 1234.56),
+            ("-$3.21", English, -3.21),
+            ("1,234", English, 1234.0),
+            ("1,234,567", English, 1234567.0),
+            ("0.00012345", English, 0.00012345),
+            ("0.0001 ETH", English, 0.0001),
+            ("42", English, 42.0),
+            ("1E-8", English, 1e-8),
+            ("-2.5e+3 USD", English, -2500.0),
+            ("1.234,56 €", German, 1234.56),
+            ("-1.234,56", German, -1234.56),
+            ("1.234", German, 1234.0),
+            ("0,5", German, 0.5),
+            ("1.234.567,8", German, 1234567.8),
+            ("1,5E-3 EUR", German, 0.0015),
+            ("1,234.56", Unknown, 1234.56),
+            ("1.234,56", Unknown, 1234.56),
+            ("1.234.567", Unknown, 1234567.0),
+            ("0,5", Unknown, 0.5),
+        ];
+
+        for (value, notation, expected) in cases {
+            assert_eq!(
+                parse_money("amount", value, notation).unwrap(),
+                expected,
+                "{value} in {notation:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn money_without_a_number_is_an_error() {
+        assert!(parse_money("amount", "-", Notation::English).is_err());
+        assert!(parse_money("amount", "", Notation::German).is_err());
+        assert!(parse_money("amount", "1E-", Notation::English).is_err());
+        assert!(parse_money("amount", "1E-+8", Notation::English).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+
+use csv::StringRecord;
+
+use crate::{
+    data::{Airdrop, Amount, Application, Asset, Comission, Transaction, Transfer, ValueStore},
+    input::{ErrorCollector, InputError, Source},
+    prices::PriceStore,
+};
+
+use super::{parse_utc_timestamp, StatementParser, StatementRow};
+
+/// The transaction history export of Binance, one row per balance change
+pub struct BinanceStatement;
+
+fn asset(name: &str) -> Asset {
+    Asset {
+        name: name.to_string(),
+        contract_address: None,
+    }
+}
+
+/// Operations whose rows are the legs of a trade, all legs share the time and account
+const TRADE_OPERATIONS: [&str; 13] = [
+    "Buy",
+    "Sell",
+    "Fee",
+    "Transaction Related",
+    "Transaction Buy",
+    "Transaction Spend",
+    "Transaction Fee",
+    "Transaction Sold",
+    "Transaction Revenue",
+    "Binance Convert",
+    "Large OTC trading",
+    "Small Assets Exchange BNB",
+    "Small assets exchange BNB",
+];
+
+/// Operations that only move assets between the accounts and products of Binance
+const INTERNAL_OPERATIONS: [&str; 16] = [
+    "Transfer Between Main and Funding Wallet",
+    "Transfer Between Spot Account and UM Futures Account",
+    "Main and Funding Account Transfer",
+    "Funding Account Transfer",
+    "transfer_in",
+    "transfer_out",
+    "Simple Earn Flexible Subscription",
+    "Simple Earn Flexible Redemption",
+    "Simple Earn Locked Subscription",
+    "Simple Earn Locked Redemption",
+    "Savings purchase",
+    "Savings Principal redemption",
+    "POS savings purchase",
+    "POS savings redemption",
+    "Staking Purchase",
+    "Staking Redemption",
+];
+
+fn is_fee(row: &StatementRow) -> bool {
+    matches!(row.get("Operation"), Ok("Fee" | "Transaction Fee"))
+}
+
+fn change(row: &StatementRow) -> Result<Amount, InputError> {
+    Ok(Amount {
+        amount: row.number("Change")?,
+        asset: asset(row.get("Coin")?),
+    })
+}
+
+/// Sums the changes of the rows per asset, keeping the order the assets appear in
+fn sum_by_asset(rows: &[&StatementRow]) -> Result<Vec<(Amount, String)>, InputError> {
+    let mut sums: Vec<(Amount, String)> = vec![];
+
+    for row in rows {
+        let amount = change(row)?;
+        match sums
+            .iter_mut()
+            .find(|(sum, _)| sum.asset.name == amount.asset.name)
+        {
+            Some((sum, _)) => sum.amount += amount.amount,
+            None => sums.push((amount, row.id.clone())),
+        }
+    }
+
+    Ok(sums)
+}
+
+fn exchange(
+    tx_id: String,
+    source: Amount,
+    destination: Amount,
+    fee: Option<Amount>,
+    operation: &str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    prices: &PriceStore,
+) -> Result<Transaction, InputError> {
+    let source = Amount {
+        amount: source.amount.abs(),
+        asset: source.asset,
+    };
+
+    let is_dust = matches!(
+        operation,
+        "Small Assets Exchange BNB" | "Small assets exchange BNB"
+    );
+    // Trades without a cash side are unvalued until a price of either asset is known
+    let date = timestamp.date_naive();
+    let usd_amount = prices
+        .trade_usd_value(&source, &destination, date)
+        .unwrap_or(0.0);
+
+    let comission = fee.map(|fee| {
+        let fee = Amount {
+            amount: fee.amount.abs(),
+            asset: fee.asset,
+        };
+        Comission {
+            usd_amount: prices.fee_usd_value(&fee, &source, &destination, usd_amount, date),
+            amount: fee,
+        }
+    });
+
+    let application = match operation {
+        "Binance Convert" => "Binance Convert",
+        _ if is_dust => "Binance Dust",
+        _ => "Binance",
+    };
+
+    Ok(Transaction::exchange(
+        Application(application.to_string()),
+        tx_id,
+        source,
+        destination,
+        comission,
+        usd_amount,
+        timestamp,
+    ))
+}
+
+/// Maps the legs of the trades that happened at the same time in the same account.
+/// Several trades at once are paired leg by leg in the order of the rows.
+fn map_trade_group(
+    rows: &[StatementRow],
+    prices: &PriceStore,
+) -> Result<Vec<Transaction>, InputError> {
+    let first = rows.first().ok_or(InputError::UnsupportedError(
+        "trade without rows".to_string(),
+    ))?;
+    let timestamp = parse_utc_timestamp("UTC_Time", first.get("UTC_Time")?)?;
+    let operation = first.get("Operation")?;
+
+    let fees = rows.iter().filter(|r| is_fee(r)).collect::<Vec<_>>();
+    let legs = rows.iter().filter(|r| !is_fee(r)).collect::<Vec<_>>();
+    let spent = legs
+        .iter()
+        .copied()
+        .filter(|r| r.number("Change").is_ok_and(|c| c < 0.0))
+        .collect::<Vec<_>>();
+    let received = legs
+        .iter()
+        .copied()
+        .filter(|r| r.number("Change").is_ok_and(|c| c > 0.0))
+        .collect::<Vec<_>>();
+
+    let sources = sum_by_asset(&spent)?;
+    let destinations = sum_by_asset(&received)?;
+    let fees = sum_by_asset(&fees)?;
+
+    match (&sources[..], &destinations[..]) {
+        ([(source, tx_id)], [(destination, _)]) => {
+            let fee = match &fees[..] {
+                [] => None,
+                [(fee, _)] => Some(fee.clone()),
+                _ => {
+                    return Err(InputError::UnsupportedError(format!(
+                        "trade with fees in several assets at {}",
+                        first.location()
+                    )))
+                }
+            };
+
+            Ok(vec![exchange(
+                tx_id.clone(),
+                source.clone(),
+                destination.clone(),
+                fee,
+                operation,
+                timestamp,
+                prices,
+            )?])
+        }
+        _ if spent.len() == received.len() && !spent.is_empty() => {
+            // e.g. small assets exchanged to BNB, every asset has its own BNB row
+            spent
+                .iter()
+                .zip(received.iter())
+                .enumerate()
+                .map(|(i, (source, destination))| {
+                    let fee = if fees.len() == spent.len() {
+                        Some(fees[i].0.clone())
+                    } else {
+                        None
+                    };
+
+                    exchange(
+                        source.id.clone(),
+                        change(source)?,
+                        change(destination)?,
+                        fee,
+                        operation,
+                        timestamp,
+                        prices,
+                    )
+                })
+                .collect()
+        }
+        _ => Err(InputError::UnsupportedError(format!(
+            "ambiguous trade of {} spent and {} received assets at {}",
+            sources.len(),
+            destinations.len(),
+            first.location()
+        ))),
+    }
+}
+
+impl StatementParser for BinanceStatement {
+    fn source(&self) -> Source {
+        Source::Binance
+    }
+
+    fn is_header(&self, record: &StringRecord) -> bool {
+        record.iter().any(|c| c.trim() == "UTC_Time")
+            && record.iter().any(|c| c.trim() == "Operation")
+    }
+
+    fn map_row(
+        &self,
+        row: &StatementRow,
+        prices: &PriceStore,
+    ) -> Result<Vec<Transaction>, InputError> {
+        let operation = row.get("Operation")?;
+        let timestamp = parse_utc_timestamp("UTC_Time", row.get("UTC_Time")?)?;
+        let amount = change(row)?;
+        let amount = Amount {
+            amount: amount.amount.abs(),
+            asset: amount.asset,
+        };
+        // The statement has no prices, they come from the price store
+        let usd_amount = prices
+            .usd_value(&amount, timestamp.date_naive())
+            .unwrap_or(0.0);
+
+        let transaction = match operation {
+            _ if TRADE_OPERATIONS.contains(&operation) => {
+                return map_trade_group(std::slice::from_ref(row), prices)
+            }
+            _ if INTERNAL_OPERATIONS.contains(&operation) => return Ok(vec![]),
+            "Deposit" | "Fiat Deposit" => Transaction::Deposit(Transfer {
+                tx_id: row.id.clone(),
+                store: ValueStore::Cex("Binance".to_string()),
+                amount,
+                comission: None,
+                usd_amount,
+                timestamp,
+                note: format!("Binance '{operation}'"),
+            }),
+            "Withdraw" | "Fiat Withdraw" | "Fiat Withdrawal" => Transaction::Withdrawal(Transfer {
+                tx_id: row.id.clone(),
+                store: ValueStore::Cex("Binance".to_string()),
+                amount,
+                comission: None,
+                usd_amount,
+                timestamp,
+                note: format!("Binance '{operation}'"),
+            }),
+            "Staking Rewards"
+            | "ETH 2.0 Staking Rewards"
+            | "Simple Earn Flexible Interest"
+            | "Simple Earn Locked Rewards"
+            | "Savings Interest"
+            | "POS savings interest"
+            | "Launchpool Interest"
+            | "BNB Vault Rewards"
+            | "Distribution"
+            | "Airdrop Assets"
+            | "Cash Voucher Distribution"
+            | "Commission History"
+            | "Commission Rebate"
+            | "Referral Kickback" => Transaction::Airdrop(Airdrop {
+                tx_id: row.id.clone(),
+                amount,
+                usd_amount,
+                timestamp,
+                note: format!("Binance '{operation}'"),
+            }),
+            _ => {
+                return Err(InputError::UnsupportedError(format!(
+                    "Binance statement operation '{operation}'"
+                )))
+            }
+        };
+
+        Ok(vec![transaction])
+    }
+
+    fn map_rows(
+        &self,
+        rows: Vec<StatementRow>,
+        prices: &PriceStore,
+        errors: &ErrorCollector,
+    ) -> Result<Vec<Transaction>, InputError> {
+        let mut transactions = vec![];
+        let mut trades = BTreeMap::<(String, String), Vec<StatementRow>>::new();
+
+        for row in rows {
+            if row
+                .get("Operation")
+                .is_ok_and(|o| TRADE_OPERATIONS.contains(&o))
+            {
+                let key = (
+                    row.get("UTC_Time").unwrap_or_default().to_string(),
+                    row.get("Account").unwrap_or_default().to_string(),
+                );
+                trades.entry(key).or_default().push(row);
+                continue;
+            }
+
+            let mapped = self
+                .map_row(&row, prices)
+                .map_err(|e| e.for_record(self.source(), &row.location()));
+            if let Some(mut mapped) = errors.collect(mapped)? {
+                transactions.append(&mut mapped);
+            }
+        }
+
+        for rows in trades.into_values() {
+            let location = rows.first().map(|r| r.location()).unwrap_or_default();
+            let mapped =
+                map_trade_group(&rows, prices).map_err(|e| e.for_record(self.source(), &location));
+            if let Some(mut mapped) = errors.collect(mapped)? {
+                transactions.append(&mut mapped);
+            }
+        }
+
+        Ok(transactions)
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+
+use csv::StringRecord;
+
+use crate::{
+    data::{Amount, Application, Asset, Comission, Transaction, Transfer, ValueStore},
+    input::{InputError, Source},
+    prices::PriceStore,
+};
+
+use super::{parse_utc_timestamp, StatementParser, StatementRow};
+
+/// The transaction export of Bison
+pub struct BisonStatement;
+
+fn asset(name: &str) -> Asset {
+    Asset {
+        name: name.to_string(),
+        contract_address: None,
+    }
+}
+
+impl StatementParser for BisonStatement {
+    fn source(&self) -> Source {
+        Source::Bison
+    }
+
+    fn is_header(&self, record: &StringRecord) -> bool {
+        record
+            .iter()
+            .any(|c| matches!(c.trim(), "Transaction type" | "TransactionType"))
+            && record.iter().any(|c| c.trim() == "Asset")
+    }
+
+    fn native_id(&self, fields: &BTreeMap<String, String>) -> Option<String> {
+        fields
+            .get("Transaction ID")
+            .or(fields.get("TransactionId"))
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+    }
+
+    fn map_row(
+        &self,
+        row: &StatementRow,
+        prices: &PriceStore,
+    ) -> Result<Vec<Transaction>, InputError> {
+        let r#type = row.get_any(&["Transaction type", "TransactionType"])?;
+        let timestamp = parse_utc_timestamp(
+            "Date",
+            row.get_any(&["Date (UTC - Coordinated Universal Time)", "Date"])?,
+        )?;
+
+        // Bison trades against euros only, the euro amount values every row
+        let currency = row.get("Currency").unwrap_or("EUR");
+        let fiat_amount = row.money("Eur (amount)", row.get("Eur (amount)")?)?;
+        let date = timestamp.date_naive();
+        let usd_amount = prices.convert_to_usd(currency, fiat_amount, date)?;
+
+        let crypto = Amount {
+            amount: match row.get("Asset (amount)")? {
+                "" | "-" => 0.0,
+                amount => row.money("Asset (amount)", amount)?,
+            },
+            asset: asset(row.get("Asset")?),
+        };
+        // Euro deposits and withdrawals have no asset
+        let is_fiat = crypto.asset.name.is_empty() || crypto.asset.name == currency;
+        let fiat = Amount {
+            amount: fiat_amount,
+            asset: asset(currency),
+        };
+
+        let fee = match row.get("Fee") {
+            Ok(fee) if !fee.is_empty() => row.money("Fee", fee)?,
+            _ => 0.0,
+        };
+        // Fees are charged in euros, Bison mostly prices them into the spread
+        let comission = (fee != 0.0).then(|| Comission {
+            amount: Amount {
+                amount: fee,
+                asset: asset(currency),
+            },
+            usd_amount: prices.convert_to_usd(currency, fee, date).unwrap_or(0.0),
+        });
+
+        let transfer = |amount: Amount| Transfer {
+            tx_id: row.id.clone(),
+            store: ValueStore::Cex("Bison".to_string()),
+            amount,
+            comission: None,
+            usd_amount,
+            timestamp,
+            note: format!("Bison '{type}'"),
+        };
+
+        let transaction = match r#type {
+            "Buy" => Transaction::exchange(
+                Application("Bison".to_string()),
+                row.id.clone(),
+                fiat,
+                crypto,
+                comission,
+                usd_amount,
+                timestamp,
+            ),
+            "Sell" => Transaction::exchange(
+                Application("Bison".to_string()),
+                row.id.clone(),
+                crypto,
+                fiat,
+                comission,
+                usd_amount,
+                timestamp,
+            ),
+            "Deposit" if is_fiat => Transaction::Deposit(transfer(fiat)),
+            "Withdraw" | "Withdrawal" if is_fiat => Transaction::Withdrawal(transfer(fiat)),
+            "Deposit" => Transaction::Deposit(transfer(crypto)),
+            "Withdraw" | "Withdrawal" => Transaction::Withdrawal(transfer(crypto)),
+            _ => {
+                return Err(InputError::UnsupportedError(format!(
+                    "Bison transaction type '{type}'"
+                )))
+            }
+        };
+
+        Ok(vec![transaction])
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+
+use csv::StringRecord;
+
+use crate::{
+    data::{Airdrop, Amount, Application, Asset, Comission, Transaction, Transfer, ValueStore},
+    input::{InputError, Source},
+    prices::PriceStore,
+};
+
+use super::{parse_utc_timestamp, StatementParser, StatementRow};
+
+/// The transaction history export of Bitpanda
+pub struct BitpandaStatement;
+
+fn asset(name: &str) -> Asset {
+    Asset {
+        name: name.to_string(),
+        contract_address: None,
+    }
+}
+
+/// Bitpanda fills columns that do not apply with `-`
+fn optional_number(row: &StatementRow, column: &'static str) -> Result<Option<f64>, InputError> {
+    match row.get(column)? {
+        "" | "-" => Ok(None),
+        value => row.money(column, value).map(Some),
+    }
+}
+
+impl StatementParser for BitpandaStatement {
+    fn source(&self) -> Source {
+        Source::Bitpanda
+    }
+
+    fn is_header(&self, record: &StringRecord) -> bool {
+        record.iter().any(|c| c.trim() == "Transaction ID")
+            && record.iter().any(|c| c.trim() == "In/Out")
+    }
+
+    fn native_id(&self, fields: &BTreeMap<String, String>) -> Option<String> {
+        fields
+            .get("Transaction ID")
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+    }
+
+    fn map_row(
+        &self,
+        row: &StatementRow,
+        prices: &PriceStore,
+    ) -> Result<Vec<Transaction>, InputError> {
+        let r#type = row.get("Transaction Type")?;
+        let direction = row.get("In/Out")?;
+        let timestamp = parse_utc_timestamp("Timestamp", row.get("Timestamp")?)?;
+
+        let asset_class = row.get("Asset class")?;
+        if !matches!(asset_class, "Fiat" | "Cryptocurrency") {
+            return Err(InputError::UnsupportedError(format!(
+                "Bitpanda asset class '{asset_class}'"
+            )));
+        }
+
+        // Every row is valued in the fiat currency of the account, no price lookup is needed
+        let fiat = row.get("Fiat")?;
+        let fiat_amount = optional_number(row, "Amount Fiat")?.unwrap_or(0.0);
+        let date = timestamp.date_naive();
+        let usd_amount = prices.convert_to_usd(fiat, fiat_amount, date)?;
+
+        let amount = Amount {
+            amount: match optional_number(row, "Amount Asset")? {
+                Some(amount) => amount,
+                None => fiat_amount,
+            },
+            asset: asset(row.get("Asset")?),
+        };
+
+        let fee = match (optional_number(row, "Fee")?, row.get("Fee asset")?) {
+            (Some(fee), fee_asset) if fee != 0.0 && fee_asset != "-" => Some(Amount {
+                amount: fee,
+                asset: asset(fee_asset),
+            }),
+            _ => None,
+        };
+
+        let transfer = |fee: Option<Amount>| Transfer {
+            tx_id: row.id.clone(),
+            store: ValueStore::Cex("Bitpanda".to_string()),
+            amount: amount.clone(),
+            comission: fee.map(|fee| Comission {
+                usd_amount: prices.fee_usd_value(&fee, &amount, &amount, usd_amount, date),
+                amount: fee,
+            }),
+            usd_amount,
+            timestamp,
+            note: format!("Bitpanda '{type}'"),
+        };
+
+        let transaction = match (r#type, direction) {
+            ("buy" | "sell", _) => {
+                let fiat_amount = Amount {
+                    amount: fiat_amount,
+                    asset: asset(fiat),
+                };
+                let (source, destination) = if r#type == "buy" {
+                    (fiat_amount, amount)
+                } else {
+                    (amount, fiat_amount)
+                };
+
+                let comission = fee.map(|fee| Comission {
+                    usd_amount: prices.fee_usd_value(&fee, &source, &destination, usd_amount, date),
+                    amount: fee,
+                });
+
+                Transaction::exchange(
+                    Application("Bitpanda".to_string()),
+                    row.id.clone(),
+                    source,
+                    destination,
+                    comission,
+                    usd_amount,
+                    timestamp,
+                )
+            }
+            // Bitpanda Best rewards are credited as incoming BEST transfers
+            ("reward", _) | ("transfer", "incoming") if amount.asset.name == "BEST" => {
+                Transaction::Airdrop(Airdrop {
+                    tx_id: row.id.clone(),
+                    amount,
+                    usd_amount,
+                    timestamp,
+                    note: "Bitpanda Best reward".to_string(),
+                })
+            }
+            ("reward", _) => Transaction::Airdrop(Airdrop {
+                tx_id: row.id.clone(),
+                amount,
+                usd_amount,
+                timestamp,
+                note: format!("Bitpanda '{type}'"),
+            }),
+            ("deposit", _) | ("transfer", "incoming") => Transaction::Deposit(transfer(fee)),
+            ("withdrawal", _) | ("transfer", "outgoing") => Transaction::Withdrawal(transfer(fee)),
+            _ => {
+                return Err(InputError::UnsupportedError(format!(
+                    "Bitpanda transaction type '{type}' {direction}"
+                )))
+            }
+        };
+
+        Ok(vec![transaction])
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+
+use csv::StringRecord;
+
+use crate::{
+    data::{Airdrop, Amount, Application, Asset, Comission, Transaction, Transfer, ValueStore},
+    input::{InputError, Source},
+    prices::PriceStore,
+};
+
+use super::{parse_money, parse_utc_timestamp, Notation, StatementParser, StatementRow};
+
+/// The transaction history report of Coinbase, in its current and its older layout
+pub struct CoinbaseStatement;
+
+fn asset(name: &str) -> Asset {
+    Asset {
+        name: name.to_string(),
+        contract_address: None,
+    }
+}
+
+/// Parses the notes of a conversion, e.g. `Converted 0.01 ETH to 25.12 USDC`
+fn parse_conversion(notes: &str, notation: Notation) -> Result<(Amount, Amount), InputError> {
+    let error = || InputError::ParseError {
+        field: "Notes",
+        value: notes.to_string(),
+    };
+
+    match notes.split_whitespace().collect::<Vec<_>>()[..] {
+        ["Converted", source_amount, source_asset, "to", destination_amount, destination_asset] => {
+            Ok((
+                Amount {
+                    amount: parse_money("Notes", source_amount, notation).map_err(|_| error())?,
+                    asset: asset(source_asset),
+                },
+                Amount {
+                    amount: parse_money("Notes", destination_amount, notation)
+                        .map_err(|_| error())?,
+                    asset: asset(destination_asset),
+                },
+            ))
+        }
+        _ => Err(error()),
+    }
+}
+
+impl StatementParser for CoinbaseStatement {
+    fn source(&self) -> Source {
+        Source::Coinbase
+    }
+
+    fn is_header(&self, record: &StringRecord) -> bool {
+        record.iter().any(|c| c.trim() == "Timestamp")
+            && record.iter().any(|c| c.trim() == "Transaction Type")
+    }
+
+    fn native_id(&self, fields: &BTreeMap<String, String>) -> Option<String> {
+        fields
+            .get("ID")
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+    }
+
+    fn map_row(
+        &self,
+        row: &StatementRow,
+        prices: &PriceStore,
+    ) -> Result<Vec<Transaction>, InputError> {
+        let r#type = row.get("Transaction Type")?;
+        let timestamp = parse_utc_timestamp("Timestamp", row.get("Timestamp")?)?;
+        let quantity = row.number("Quantity Transacted")?.abs();
+        let crypto = Amount {
+            amount: quantity,
+            asset: asset(row.get("Asset")?),
+        };
+
+        let currency = row.get_any(&["Price Currency", "Spot Price Currency"])?;
+        let to_usd = |amount: f64| prices.convert_to_usd(currency, amount, timestamp.date_naive());
+        let subtotal = row
+            .money("Subtotal", row.get("Subtotal")?)
+            .unwrap_or(0.0)
+            .abs();
+        let total = row
+            .money(
+                "Total",
+                row.get_any(&["Total (inclusive of fees and/or spread)", "Total"])?,
+            )
+            .unwrap_or(subtotal)
+            .abs();
+        let fees = row
+            .money("Fees", row.get_any(&["Fees and/or Spread", "Fees"])?)
+            .unwrap_or(0.0)
+            .abs();
+        let comission = if fees == 0.0 {
+            None
+        } else {
+            Some(Comission {
+                amount: Amount {
+                    amount: fees,
+                    asset: asset(currency),
+                },
+                usd_amount: to_usd(fees)?,
+            })
+        };
+
+        let application = Application("Coinbase".to_string());
+        let transaction = match r#type {
+            "Buy" | "Advanced Trade Buy" => Transaction::exchange(
+                application,
+                row.id.clone(),
+                Amount {
+                    amount: total,
+                    asset: asset(currency),
+                },
+                crypto,
+                comission,
+                to_usd(total)?,
+                timestamp,
+            ),
+            "Sell" | "Advanced Trade Sell" => Transaction::exchange(
+                application,
+                row.id.clone(),
+                crypto,
+                Amount {
+                    amount: total,
+                    asset: asset(currency),
+                },
+                comission,
+                to_usd(subtotal)?,
+                timestamp,
+            ),
+            "Convert" => {
+                let (source, destination) = parse_conversion(row.get("Notes")?, row.notation)?;
+
+                Transaction::exchange(
+                    application,
+                    row.id.clone(),
+                    source,
+                    destination,
+                    comission,
+                    to_usd(subtotal)?,
+                    timestamp,
+                )
+            }
+            "Rewards Income"
+            | "Staking Income"
+            | "Learning Reward"
+            | "Inflation Reward"
+            | "Coinbase Earn"
+            | "Reward Income"
+            | "Incentives Rewards Payout" => Transaction::Airdrop(Airdrop {
+                tx_id: row.id.clone(),
+                amount: crypto,
+                usd_amount: to_usd(subtotal)?,
+                timestamp,
+                note: format!("Coinbase '{}'", r#type),
+            }),
+            "Send"
+            | "Withdrawal"
+            | "Exchange Deposit"
+            | "Pro Deposit"
+            | "Retail Staking Transfer" => Transaction::Withdrawal(Transfer {
+                tx_id: row.id.clone(),
+                store: ValueStore::Cex("Coinbase".to_string()),
+                amount: crypto,
+                comission,
+                usd_amount: to_usd(subtotal)?,
+                timestamp,
+                note: format!("Coinbase '{}'", r#type),
+            }),
+            "Receive"
+            | "Deposit"
+            | "Exchange Withdrawal"
+            | "Pro Withdrawal"
+            | "Retail Unstaking Transfer" => Transaction::Deposit(Transfer {
+                tx_id: row.id.clone(),
+                store: ValueStore::Cex("Coinbase".to_string()),
+                amount: crypto,
+                comission,
+                usd_amount: to_usd(subtotal)?,
+                timestamp,
+                note: format!("Coinbase '{}'", r#type),
+            }),
+            _ => {
+                return Err(InputError::UnsupportedError(format!(
+                    "Coinbase statement type '{type}'"
+                )))
+            }
+        };
+
+        Ok(vec![transaction])
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+use csv::StringRecord;
+
+use crate::{
+    data::Transaction,
+    input::{
+        kraken::{kraken_asset, map_ledger, LedgerEntry},
+        ErrorCollector, InputError, Source,
+    },
+    prices::PriceStore,
+};
+
+use super::{parse_utc_timestamp, StatementParser, StatementRow};
+
+/// The `ledgers.csv` export of Kraken, the same entries as the API ledger
+pub struct KrakenStatement;
+
+fn ledger_entry(row: &StatementRow) -> Result<LedgerEntry, InputError> {
+    // Newer exports have fractions of a second
+    let time = row.get("time")?;
+    let time = time.split_once('.').map(|(time, _)| time).unwrap_or(time);
+
+    Ok(LedgerEntry {
+        id: row.get("txid")?.to_string(),
+        refid: row.get("refid")?.to_string(),
+        timestamp: parse_utc_timestamp("time", time)?,
+        r#type: row.get("type")?.to_string(),
+        subtype: row.get("subtype").unwrap_or_default().to_string(),
+        asset: kraken_asset(row.get("asset")?),
+        amount: row.money("amount", row.get("amount")?)?,
+        fee: row.money("fee", row.get("fee")?)?,
+    })
+}
+
+/// Pending entries have no txid yet, they are repeated with one once completed
+fn is_pending(row: &StatementRow) -> bool {
+    row.get("txid").map_or(true, |txid| txid.is_empty())
+}
+
+impl StatementParser for KrakenStatement {
+    fn source(&self) -> Source {
+        Source::Kraken
+    }
+
+    fn is_header(&self, record: &StringRecord) -> bool {
+        record.iter().any(|c| c.trim() == "refid") && record.iter().any(|c| c.trim() == "txid")
+    }
+
+    fn native_id(&self, fields: &BTreeMap<String, String>) -> Option<String> {
+        fields
+            .get("txid")
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+    }
+
+    fn map_row(
+        &self,
+        row: &StatementRow,
+        prices: &PriceStore,
+    ) -> Result<Vec<Transaction>, InputError> {
+        if is_pending(row) {
+            return Ok(vec![]);
+        }
+
+        map_ledger(
+            vec![ledger_entry(row)?],
+            prices,
+            &ErrorCollector::new(false),
+        )
+    }
+
+    fn map_rows(
+        &self,
+        rows: Vec<StatementRow>,
+        prices: &PriceStore,
+        errors: &ErrorCollector,
+    ) -> Result<Vec<Transaction>, InputError> {
+        let mut entries = vec![];
+
+        for row in rows.iter().filter(|row| !is_pending(row)) {
+            let entry = ledger_entry(row).map_err(|e| e.for_record(self.source(), &row.location()));
+            entries.extend(errors.collect(entry)?);
+        }
+
+        map_ledger(entries, prices, errors)
+    }
+}
@@ -0,0 +1,120 @@
+use csv::StringRecord;
+
+use crate::{
+    data::{Amount, Application, Asset, Comission, Transaction},
+    input::{InputError, Source},
+    prices::PriceStore,
+};
+
+use super::{parse_utc_timestamp, StatementParser, StatementRow};
+
+/// Quote assets a pair without separator can end with
+const QUOTE_ASSETS: [&str; 4] = ["USDT", "USDC", "BTC", "ETH"];
+
+/// The spot trade history export of MEXC
+pub struct MexcSpotStatement;
+
+fn asset(name: &str) -> Asset {
+    Asset {
+        name: name.to_string(),
+        contract_address: None,
+    }
+}
+
+/// Splits pairs like `ETH_USDT` or `ETHUSDT` into base and quote asset
+fn split_pair(pair: &str) -> Result<(&str, &str), InputError> {
+    if let Some(split) = pair.split_once('_') {
+        return Ok(split);
+    }
+
+    QUOTE_ASSETS
+        .iter()
+        .find_map(|quote| pair.strip_suffix(quote).map(|base| (base, *quote)))
+        .ok_or(InputError::UnsupportedError(format!("symbol {pair}")))
+}
+
+impl StatementParser for MexcSpotStatement {
+    fn source(&self) -> Source {
+        Source::Mexc
+    }
+
+    fn is_header(&self, record: &StringRecord) -> bool {
+        record.iter().any(|c| c.trim() == "Pairs") && record.iter().any(|c| c.trim() == "Side")
+    }
+
+    fn map_row(
+        &self,
+        row: &StatementRow,
+        _prices: &PriceStore,
+    ) -> Result<Vec<Transaction>, InputError> {
+        let (base, quote) = split_pair(row.get("Pairs")?)?;
+        let timestamp = parse_utc_timestamp("Time", row.get("Time")?)?;
+
+        let crypto = Amount {
+            amount: row.number("Executed Amount")?,
+            asset: asset(base),
+        };
+        let total = Amount {
+            amount: row.number("Total")?,
+            asset: asset(quote),
+        };
+
+        // The fee is either a plain number in the quote asset or e.g. `0.0001 ETH`
+        let fee = row.get("Fee")?;
+        let fee_amount = row.money("Fee", fee)?;
+        let fee_asset = fee
+            .trim_start_matches(|c: char| c.is_ascii_digit() || c == '.' || c == '-')
+            .trim();
+        let fee_asset = if fee_asset.is_empty() {
+            quote
+        } else {
+            fee_asset
+        };
+
+        let Some("USD") = total.asset.stablecoin_peg() else {
+            return Err(InputError::UnsupportedError(format!(
+                "quote asset {quote} without usd value"
+            )));
+        };
+        let usd_amount = total.amount;
+
+        let comission = if fee_amount == 0.0 {
+            None
+        } else {
+            let usd_fee = if fee_asset == quote {
+                fee_amount
+            } else {
+                fee_amount * usd_amount / crypto.amount
+            };
+
+            Some(Comission {
+                amount: Amount {
+                    amount: fee_amount,
+                    asset: asset(fee_asset),
+                },
+                usd_amount: usd_fee,
+            })
+        };
+
+        let (source, destination) = match row.get("Side")?.to_uppercase().as_str() {
+            "BUY" => (total, crypto),
+            "SELL" => (crypto, total),
+            side => {
+                return Err(InputError::ParseError {
+                    field: "Side",
+                    value: side.to_string(),
+                })
+            }
+        };
+
+        Ok(vec![Transaction::exchange(
+            Application("MEXC".to_string()),
+            row.id.clone(),
+            source,
+            destination,
+            comission,
+            usd_amount,
+            timestamp,
+        )])
+    }
+}
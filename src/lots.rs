@@ -0,0 +1,849 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    balances::{account_stores, store_label, store_of, AccountStores, DUST},
+    command_line_interface::CostMethod,
+    data::{Amount, Asset, Comission, Transaction},
+    normalized::Record,
+};
+
+/// The value of a usd amount of a transaction, mappers give what they could not value 0
+fn valued(usd_amount: f64) -> Option<f64> {
+    (usd_amount != 0.0).then_some(usd_amount)
+}
+
+/// A quantity of an asset acquired at once, with what it cost in usd
+#[derive(Debug, Clone)]
+pub struct Lot {
+    pub asset: Asset,
+    pub quantity: f64,
+    /// None if the transaction it was acquired in has no value
+    pub cost: Option<f64>,
+    pub acquired_at: DateTime<Utc>,
+}
+
+impl Lot {
+    fn unit_cost(&self) -> Option<f64> {
+        let cost = self.cost?;
+        Some(if self.quantity > 0.0 {
+            cost / self.quantity
+        } else {
+            0.0
+        })
+    }
+
+    /// Splits off the given quantity at its share of the cost
+    fn split(&mut self, quantity: f64) -> Lot {
+        let quantity = quantity.min(self.quantity);
+        let cost = self.unit_cost().map(|unit_cost| unit_cost * quantity);
+        self.quantity -= quantity;
+        self.cost = self.cost.zip(cost).map(|(left, cost)| left - cost);
+
+        Lot {
+            quantity,
+            cost,
+            ..self.clone()
+        }
+    }
+}
+
+/// A disposal of (a part of) one lot, e.g. a sale or the sold side of a swap
+#[derive(Debug, Clone)]
+pub struct Disposal {
+    pub tx_id: String,
+    pub store: String,
+    pub asset: Asset,
+    pub quantity: f64,
+    /// The usd the disposed quantity was sold for, after fees, none if the disposal has no
+    /// value
+    pub proceeds: Option<f64>,
+    /// None if the lot has no cost, a missing lot costs nothing
+    pub cost: Option<f64>,
+    /// When the lot was acquired, none if no lot was left to dispose of
+    pub acquired_at: Option<DateTime<Utc>>,
+    pub disposed_at: DateTime<Utc>,
+}
+
+impl Disposal {
+    /// The gain, none if the proceeds or the cost are not known
+    pub fn gain(&self) -> Option<f64> {
+        Some(self.proceeds? - self.cost?)
+    }
+}
+
+/// An asset received for free, e.g. an airdrop or a staking reward
+#[derive(Debug, Clone)]
+pub struct Income {
+    pub tx_id: String,
+    pub store: String,
+    pub asset: Asset,
+    pub quantity: f64,
+    /// The usd value at the time it was received, which is also the cost of its lot,
+    /// 0 if it has none
+    pub value: f64,
+    pub received_at: DateTime<Utc>,
+    pub note: String,
+}
+
+/// How long a withdrawal may take to arrive as a deposit in another own store
+const TRANSIT_DAYS: i64 = 7;
+
+/// The share of a withdrawal the network may keep on the way to the deposit
+const TRANSIT_LOSS: f64 = 0.02;
+
+/// What a withdrawal took out of a store until a deposit takes it up
+struct Transit {
+    tx_id: String,
+    store: String,
+    amount: Amount,
+    usd_amount: f64,
+    lots: Vec<Lot>,
+    /// The quantity the store lacked lots for
+    missing: f64,
+    at: DateTime<Utc>,
+}
+
+impl Transit {
+    /// Whether the deposit is the arrival of the withdrawal in another store, the same
+    /// transaction in both stores or else about the same quantity shortly after
+    fn arrives_as(&self, tx_id: &str, store: &str, amount: &Amount, at: DateTime<Utc>) -> bool {
+        if self.store == store || asset_key(&self.amount.asset) != asset_key(&amount.asset) {
+            return false;
+        }
+
+        self.tx_id == tx_id
+            || (self.at <= at
+                && amount.amount <= self.amount.amount * (1.0 + 1e-6)
+                && amount.amount >= self.amount.amount * (1.0 - TRANSIT_LOSS))
+    }
+}
+
+type AssetKey = (String, Option<String>);
+
+fn asset_key(asset: &Asset) -> AssetKey {
+    (asset.name.clone(), asset.contract_address.clone())
+}
+
+/// Replays the transactions into lots per store and asset. Transfers and bridges move
+/// lots between stores without realizing gains, fiat is money and its disposals no gains.
+pub struct LotEngine {
+    method: CostMethod,
+    stores: AccountStores,
+    lots: BTreeMap<(String, AssetKey), Vec<Lot>>,
+    /// What was withdrawn and not yet deposited again, oldest first
+    in_transit: Vec<Transit>,
+    pub disposals: Vec<Disposal>,
+    pub income: Vec<Income>,
+    /// The tx_ids of the withdrawals no deposit took up in time, they count as disposals
+    pub unmatched: Vec<String>,
+    /// The tx_ids of the transfers and bridges that moved more than their store had lots for
+    pub unsourced: Vec<String>,
+}
+
+impl LotEngine {
+    pub fn run(records: &[Record], method: CostMethod) -> LotEngine {
+        LotEngine::run_until(records, method, DateTime::<Utc>::MAX_UTC)
+    }
+
+    /// Replays the transactions before the time only, e.g. for the holdings of a day
+    pub fn run_until(records: &[Record], method: CostMethod, until: DateTime<Utc>) -> LotEngine {
+        let mut engine = LotEngine {
+            method,
+            stores: account_stores(records),
+            lots: BTreeMap::new(),
+            in_transit: vec![],
+            disposals: vec![],
+            income: vec![],
+            unmatched: vec![],
+            unsourced: vec![],
+        };
+
+        for record in records.iter().filter(|r| r.transaction.timestamp() < until) {
+            engine.settle(record.transaction.timestamp());
+            engine.apply(record);
+        }
+        engine.settle(until.min(Utc::now()));
+
+        engine
+    }
+
+    /// The lots still held by store
+    pub fn holdings(&self) -> impl Iterator<Item = (&str, &Lot)> {
+        self.lots.iter().flat_map(|((store, _), lots)| {
+            lots.iter()
+                .filter(|lot| lot.quantity > DUST)
+                .map(move |lot| (store.as_str(), lot))
+        })
+    }
+
+    fn acquire(&mut self, store: &str, amount: &Amount, cost: Option<f64>, at: DateTime<Utc>) {
+        if amount.amount <= 0.0 {
+            return;
+        }
+
+        self.lots
+            .entry((store.to_string(), asset_key(&amount.asset)))
+            .or_default()
+            .push(Lot {
+                asset: amount.asset.clone(),
+                quantity: amount.amount,
+                cost,
+                acquired_at: at,
+            });
+    }
+
+    /// Takes the quantity out of the lots of the store in the order of the cost method,
+    /// what the lots lack is returned as the missing quantity
+    fn take(&mut self, store: &str, amount: &Amount) -> (Vec<Lot>, f64) {
+        let lots = self
+            .lots
+            .entry((store.to_string(), asset_key(&amount.asset)))
+            .or_default();
+        let mut left = amount.amount;
+        let mut taken = vec![];
+
+        match self.method {
+            // Every lot gives its share, so all are sold at the average cost
+            CostMethod::Average => {
+                let total = lots.iter().map(|lot| lot.quantity).sum::<f64>();
+                if total > 0.0 {
+                    let share = (left / total).min(1.0);
+                    for lot in lots.iter_mut() {
+                        let quantity = lot.quantity * share;
+                        taken.push(lot.split(quantity));
+                    }
+                    left -= total * share;
+                }
+            }
+            method => {
+                let mut order = (0..lots.len()).collect::<Vec<_>>();
+                match method {
+                    CostMethod::Fifo => order.sort_by_key(|&i| lots[i].acquired_at),
+                    CostMethod::Lifo => {
+                        order.sort_by_key(|&i| std::cmp::Reverse(lots[i].acquired_at))
+                    }
+                    CostMethod::Hifo => {
+                        // Lots without a cost go last, their cost is not known to be high
+                        let unit_cost = |i: usize| lots[i].unit_cost().unwrap_or(f64::MIN);
+                        order.sort_by(|&a, &b| unit_cost(b).total_cmp(&unit_cost(a)))
+                    }
+                    CostMethod::Average => {}
+                }
+
+                for i in order {
+                    if left <= DUST {
+                        break;
+                    }
+                    let lot = lots[i].split(left);
+                    left -= lot.quantity;
+                    taken.push(lot);
+                }
+            }
+        }
+
+        lots.retain(|lot| lot.quantity > DUST);
+        taken.retain(|lot| lot.quantity > 0.0);
+        (taken, left.max(0.0))
+    }
+
+    fn dispose(
+        &mut self,
+        tx_id: &str,
+        store: &str,
+        amount: &Amount,
+        proceeds: Option<f64>,
+        at: DateTime<Utc>,
+    ) {
+        if amount.amount <= 0.0 {
+            return;
+        }
+
+        let (lots, missing) = self.take(store, amount);
+        self.realize(tx_id, store, amount, lots, missing, proceeds, at);
+    }
+
+    /// Records the disposal of the lots taken out for the amount, money realizes no gains
+    #[allow(clippy::too_many_arguments)]
+    fn realize(
+        &mut self,
+        tx_id: &str,
+        store: &str,
+        amount: &Amount,
+        lots: Vec<Lot>,
+        missing: f64,
+        proceeds: Option<f64>,
+        at: DateTime<Utc>,
+    ) {
+        if amount.asset.is_fiat() {
+            return;
+        }
+        let mut disposal = |quantity: f64, cost, acquired_at| {
+            self.disposals.push(Disposal {
+                tx_id: tx_id.to_string(),
+                store: store.to_string(),
+                asset: amount.asset.clone(),
+                quantity,
+                proceeds: proceeds.map(|proceeds| proceeds * quantity / amount.amount),
+                cost,
+                acquired_at,
+                disposed_at: at,
+            });
+        };
+
+        for lot in lots {
+            disposal(lot.quantity, lot.cost, Some(lot.acquired_at));
+        }
+        if missing > DUST {
+            disposal(missing, Some(0.0), None);
+        }
+    }
+
+    /// Fees in an asset that was not traded are spent at their value
+    fn spend_fee(&mut self, tx_id: &str, store: &str, comission: &Comission, at: DateTime<Utc>) {
+        let proceeds = valued(comission.usd_amount);
+        self.dispose(tx_id, store, &comission.amount, proceeds, at);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn exchange(
+        &mut self,
+        tx_id: &str,
+        store: &str,
+        source: &Amount,
+        destination: &Amount,
+        comission: &Option<Comission>,
+        usd_amount: f64,
+        at: DateTime<Utc>,
+    ) {
+        let fee = comission.as_ref().map_or(0.0, |c| c.usd_amount);
+
+        // The value of the trade includes the fee, which lowers the proceeds of the sold
+        // side and adds to the cost of the bought one. Money sold realizes no gain.
+        let value = valued(usd_amount);
+        self.dispose(tx_id, store, source, value.map(|value| value - fee), at);
+        self.acquire(store, destination, value, at);
+
+        if let Some(comission) = comission.as_ref().filter(|c| {
+            c.amount.asset.name != source.asset.name
+                && c.amount.asset.name != destination.asset.name
+        }) {
+            self.spend_fee(tx_id, store, comission, at);
+        }
+    }
+
+    fn apply(&mut self, record: &Record) {
+        match &record.transaction {
+            Transaction::Trade(trade) => {
+                let store = store_of(&self.stores, record, Some(&trade.application.0));
+                self.exchange(
+                    &trade.tx_id,
+                    &store,
+                    &trade.source,
+                    &trade.destination,
+                    &trade.comission,
+                    trade.usd_amount,
+                    trade.timestamp,
+                );
+            }
+            Transaction::CurrencyExchange(exchange) => {
+                let store = store_of(&self.stores, record, Some(&exchange.application.0));
+                self.exchange(
+                    &exchange.tx_id,
+                    &store,
+                    &exchange.source,
+                    &exchange.destination,
+                    &exchange.comission,
+                    exchange.usd_amount,
+                    exchange.timestamp,
+                );
+            }
+            Transaction::Airdrop(airdrop) => {
+                let store = store_of(&self.stores, record, None);
+                self.acquire(
+                    &store,
+                    &airdrop.amount,
+                    valued(airdrop.usd_amount),
+                    airdrop.timestamp,
+                );
+                self.income.push(Income {
+                    tx_id: airdrop.tx_id.clone(),
+                    store,
+                    asset: airdrop.amount.asset.clone(),
+                    quantity: airdrop.amount.amount,
+                    value: airdrop.usd_amount,
+                    received_at: airdrop.timestamp,
+                    note: airdrop.note.clone(),
+                });
+            }
+            // The lots arrive less the fee of the bridge, which adds to their cost. What the
+            // source had no lots for arrives without a cost.
+            Transaction::Bridge(bridge) => {
+                let (mut lots, missing) = self.take(&store_label(&bridge.source), &bridge.amount);
+                if missing > DUST {
+                    self.unsourced.push(bridge.tx_id.clone());
+                    lots.push(Lot {
+                        asset: bridge.amount.asset.clone(),
+                        quantity: missing,
+                        cost: None,
+                        acquired_at: bridge.timestamp,
+                    });
+                }
+                let received = if bridge.amount.amount > 0.0 {
+                    (bridge.amount.amount - bridge.comission.amount) / bridge.amount.amount
+                } else {
+                    0.0
+                };
+
+                let key = (
+                    store_label(&bridge.destination),
+                    asset_key(&bridge.amount.asset),
+                );
+                let destination = self.lots.entry(key).or_default();
+                destination.extend(lots.into_iter().map(|lot| Lot {
+                    quantity: lot.quantity * received,
+                    ..lot
+                }));
+            }
+            Transaction::Deposit(transfer) => {
+                let store = store_label(&transfer.store);
+                self.deposit(
+                    &transfer.tx_id,
+                    &store,
+                    &transfer.amount,
+                    transfer.usd_amount,
+                    transfer.timestamp,
+                );
+                if let Some(comission) = &transfer.comission {
+                    self.spend_fee(&transfer.tx_id, &store, comission, transfer.timestamp);
+                }
+            }
+            Transaction::Withdrawal(transfer) => {
+                let store = store_label(&transfer.store);
+                if transfer.amount.amount > 0.0 {
+                    let (lots, missing) = self.take(&store, &transfer.amount);
+                    self.in_transit.push(Transit {
+                        tx_id: transfer.tx_id.clone(),
+                        store: store.clone(),
+                        amount: transfer.amount.clone(),
+                        usd_amount: transfer.usd_amount,
+                        lots,
+                        missing,
+                        at: transfer.timestamp,
+                    });
+                }
+                if let Some(comission) = &transfer.comission {
+                    self.spend_fee(&transfer.tx_id, &store, comission, transfer.timestamp);
+                }
+            }
+        }
+    }
+
+    /// Withdrawals no deposit took up within the window left the own stores, e.g. to pay
+    /// someone, and are disposed of at their value when they were withdrawn
+    fn settle(&mut self, now: DateTime<Utc>) {
+        let deadline = now - Duration::days(TRANSIT_DAYS);
+        let (expired, pending) = std::mem::take(&mut self.in_transit)
+            .into_iter()
+            .partition::<Vec<_>, _>(|transit| transit.at < deadline);
+        self.in_transit = pending;
+
+        for transit in expired {
+            if !transit.amount.asset.is_fiat() {
+                self.unmatched.push(transit.tx_id.clone());
+            }
+            self.realize(
+                &transit.tx_id,
+                &transit.store,
+                &transit.amount,
+                transit.lots,
+                transit.missing,
+                valued(transit.usd_amount),
+                transit.at,
+            );
+        }
+    }
+
+    /// Deposits take the lots of the withdrawal they are the arrival of, so that moving
+    /// between own stores keeps them, the rest is acquired at its value
+    fn deposit(
+        &mut self,
+        tx_id: &str,
+        store: &str,
+        amount: &Amount,
+        usd_amount: f64,
+        at: DateTime<Utc>,
+    ) {
+        if amount.amount <= 0.0 {
+            return;
+        }
+
+        let position = self
+            .in_transit
+            .iter()
+            .position(|transit| {
+                transit.tx_id == tx_id && transit.arrives_as(tx_id, store, amount, at)
+            })
+            .or_else(|| {
+                self.in_transit
+                    .iter()
+                    .position(|transit| transit.arrives_as(tx_id, store, amount, at))
+            });
+
+        let mut left = amount.amount;
+        if let Some(position) = position {
+            let transit = self.in_transit.remove(position);
+            // What the withdrawing store had no lots for arrives at the value of the deposit
+            if transit.missing > DUST && !amount.asset.is_fiat() {
+                self.unsourced.push(transit.tx_id.clone());
+            }
+            // The lots arrive less what the network kept on the way, which adds to their cost
+            let share = (amount.amount / transit.amount.amount).min(1.0);
+            let arrived = transit
+                .lots
+                .into_iter()
+                .map(|lot| Lot {
+                    quantity: lot.quantity * share,
+                    ..lot
+                })
+                .collect::<Vec<_>>();
+            left -= arrived.iter().map(|lot| lot.quantity).sum::<f64>();
+
+            self.lots
+                .entry((store.to_string(), asset_key(&amount.asset)))
+                .or_default()
+                .extend(arrived);
+        }
+
+        if left > DUST {
+            let rest = Amount {
+                amount: left,
+                asset: amount.asset.clone(),
+            };
+            let cost = valued(usd_amount).map(|usd_amount| usd_amount * left / amount.amount);
+            self.acquire(store, &rest, cost, at);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::data::{Application, Bridge, Trade, Transfer, ValueStore};
+
+    fn amount(amount: f64, name: &str) -> Amount {
+        Amount {
+            amount,
+            asset: Asset {
+                name: name.to_string(),
+                contract_address: None,
+            },
+        }
+    }
+
+    fn day(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, 12, 0, 0).unwrap()
+    }
+
+    fn record(transaction: Transaction) -> Record {
+        Record {
+            source: "Kraken".to_string(),
+            account: "kraken".to_string(),
+            transaction,
+        }
+    }
+
+    fn trade(
+        tx_id: &str,
+        source: Amount,
+        destination: Amount,
+        fee: f64,
+        usd_amount: f64,
+        at: DateTime<Utc>,
+    ) -> Record {
+        record(Transaction::Trade(Trade {
+            application: Application("Kraken".to_string()),
+            tx_id: tx_id.to_string(),
+            comission: (fee > 0.0).then(|| Comission {
+                amount: amount(fee, "USD"),
+                usd_amount: fee,
+            }),
+            source,
+            destination,
+            usd_amount,
+            timestamp: at,
+        }))
+    }
+
+    fn transfer(tx_id: &str, store: &str, amount: Amount, at: DateTime<Utc>) -> Transfer {
+        Transfer {
+            tx_id: tx_id.to_string(),
+            store: ValueStore::Cex(store.to_string()),
+            usd_amount: amount.amount * 3000.0,
+            amount,
+            comission: None,
+            timestamp: at,
+            note: "".to_string(),
+        }
+    }
+
+    fn held(engine: &LotEngine, store: &str) -> Vec<(f64, Option<f64>)> {
+        engine
+            .holdings()
+            .filter(|(s, lot)| *s == store && lot.asset.name == "ETH")
+            .map(|(_, lot)| (lot.quantity, lot.cost))
+            .collect()
+    }
+
+    fn buy_eth(quantity: f64, at: DateTime<Utc>) -> Record {
+        trade(
+            "buy",
+            amount(quantity * 1000.0, "USD"),
+            amount(quantity, "ETH"),
+            0.0,
+            quantity * 1000.0,
+            at,
+        )
+    }
+
+    #[test]
+    fn a_deposit_takes_the_lots_of_the_withdrawal_with_its_tx_id() {
+        let records = [
+            buy_eth(2.0, day(1)),
+            record(Transaction::Withdrawal(transfer(
+                "other",
+                "Kraken",
+                amount(1.0, "ETH"),
+                day(2),
+            ))),
+            record(Transaction::Withdrawal(transfer(
+                "0xabc",
+                "Kraken",
+                amount(1.0, "ETH"),
+                day(2),
+            ))),
+            record(Transaction::Deposit(transfer(
+                "0xabc",
+                "Binance",
+                amount(0.5, "ETH"),
+                day(3),
+            ))),
+        ];
+
+        let engine = LotEngine::run_until(&records, CostMethod::Fifo, day(4));
+
+        // The network kept half of it, the whole cost arrives with the rest
+        assert_eq!(held(&engine, "Binance"), [(0.5, Some(1000.0))]);
+        assert!(engine.disposals.is_empty());
+    }
+
+    #[test]
+    fn a_deposit_of_about_the_withdrawn_quantity_shortly_after_takes_its_lots() {
+        let records = [
+            buy_eth(1.0, day(1)),
+            record(Transaction::Withdrawal(transfer(
+                "W1",
+                "Kraken",
+                amount(1.0, "ETH"),
+                day(2),
+            ))),
+            record(Transaction::Deposit(transfer(
+                "D1",
+                "Binance",
+                amount(0.995, "ETH"),
+                day(3),
+            ))),
+        ];
+
+        let engine = LotEngine::run_until(&records, CostMethod::Fifo, day(4));
+
+        assert_eq!(held(&engine, "Binance"), [(0.995, Some(1000.0))]);
+        assert!(engine.disposals.is_empty());
+    }
+
+    #[test]
+    fn withdrawals_no_deposit_takes_up_in_time_are_disposals() {
+        let records = [
+            buy_eth(1.0, day(1)),
+            record(Transaction::Withdrawal(transfer(
+                "W1",
+                "Kraken",
+                amount(1.0, "ETH"),
+                day(2),
+            ))),
+            // Too late and too little to be the arrival of the withdrawal
+            record(Transaction::Deposit(transfer(
+                "D1",
+                "Binance",
+                amount(1.0, "ETH"),
+                day(20),
+            ))),
+            record(Transaction::Deposit(transfer(
+                "D2",
+                "Binance",
+                amount(0.5, "ETH"),
+                day(21),
+            ))),
+        ];
+
+        let engine = LotEngine::run(&records, CostMethod::Fifo);
+
+        assert_eq!(engine.unmatched, ["W1"]);
+        let [disposal] = &engine.disposals[..] else {
+            panic!("expected one disposal, got {:?}", engine.disposals);
+        };
+        assert_eq!(disposal.tx_id, "W1");
+        assert_eq!(disposal.proceeds, Some(3000.0));
+        assert_eq!(disposal.cost, Some(1000.0));
+        assert_eq!(disposal.disposed_at, day(2));
+        assert_eq!(
+            held(&engine, "Binance"),
+            [(1.0, Some(3000.0)), (0.5, Some(1500.0))]
+        );
+    }
+
+    #[test]
+    fn a_deposit_into_the_withdrawing_store_is_no_arrival() {
+        let records = [
+            buy_eth(1.0, day(1)),
+            record(Transaction::Withdrawal(transfer(
+                "W1",
+                "Kraken",
+                amount(1.0, "ETH"),
+                day(2),
+            ))),
+            record(Transaction::Deposit(transfer(
+                "D1",
+                "Kraken",
+                amount(1.0, "ETH"),
+                day(3),
+            ))),
+            record(Transaction::Deposit(transfer(
+                "D2",
+                "Binance",
+                amount(1.0, "ETH"),
+                day(4),
+            ))),
+        ];
+
+        let engine = LotEngine::run_until(&records, CostMethod::Fifo, day(5));
+
+        assert_eq!(held(&engine, "Kraken"), [(1.0, Some(3000.0))]);
+        assert_eq!(held(&engine, "Binance"), [(1.0, Some(1000.0))]);
+    }
+
+    #[test]
+    fn moving_more_than_the_store_held_is_reported() {
+        let records = [
+            buy_eth(1.0, day(1)),
+            record(Transaction::Bridge(Bridge {
+                application: Application("Across".to_string()),
+                tx_id: "B1".to_string(),
+                source: ValueStore::Cex("Kraken".to_string()),
+                destination: ValueStore::Cex("Arbitrum".to_string()),
+                amount: amount(1.5, "ETH"),
+                comission: amount(0.0, "ETH"),
+                timestamp: day(2),
+            })),
+            record(Transaction::Withdrawal(transfer(
+                "W1",
+                "Arbitrum",
+                amount(2.0, "ETH"),
+                day(3),
+            ))),
+            record(Transaction::Deposit(transfer(
+                "W1",
+                "Binance",
+                amount(2.0, "ETH"),
+                day(4),
+            ))),
+        ];
+
+        let engine = LotEngine::run_until(&records, CostMethod::Fifo, day(5));
+
+        assert_eq!(engine.unsourced, ["B1", "W1"]);
+        // The bridge brought the half it had no lots for without a cost, the withdrawal
+        // lacked another half, which arrives at the value of the deposit
+        assert_eq!(
+            held(&engine, "Binance"),
+            [(1.0, Some(1000.0)), (0.5, None), (0.5, Some(1500.0))]
+        );
+        assert!(engine.disposals.is_empty());
+    }
+
+    #[test]
+    fn the_fee_of_a_swap_lowers_the_proceeds_but_not_the_new_cost() {
+        let records = [
+            trade(
+                "buy",
+                amount(1000.0, "USD"),
+                amount(1.0, "ETH"),
+                0.0,
+                1000.0,
+                day(1),
+            ),
+            trade(
+                "swap",
+                amount(1.0, "ETH"),
+                amount(0.05, "BTC"),
+                10.0,
+                2000.0,
+                day(2),
+            ),
+        ];
+
+        let engine = LotEngine::run(&records, CostMethod::Fifo);
+
+        let [disposal] = &engine.disposals[..] else {
+            panic!("expected one disposal, got {:?}", engine.disposals);
+        };
+        assert_eq!(disposal.proceeds, Some(1990.0));
+        assert_eq!(disposal.cost, Some(1000.0));
+        let btc = engine
+            .holdings()
+            .find(|(_, lot)| lot.asset.name == "BTC")
+            .unwrap()
+            .1;
+        assert_eq!(btc.cost, Some(2000.0));
+    }
+
+    #[test]
+    fn unvalued_trades_leave_disposals_and_lots_without_value() {
+        let records = [
+            buy_eth(1.0, day(1)),
+            trade(
+                "swap",
+                amount(1.0, "ETH"),
+                amount(50.0, "UNI"),
+                0.0,
+                0.0,
+                day(2),
+            ),
+            trade(
+                "sell",
+                amount(50.0, "UNI"),
+                amount(400.0, "USD"),
+                0.0,
+                400.0,
+                day(3),
+            ),
+        ];
+
+        let engine = LotEngine::run(&records, CostMethod::Fifo);
+
+        let [swap, sale] = &engine.disposals[..] else {
+            panic!("expected two disposals, got {:?}", engine.disposals);
+        };
+        assert_eq!(
+            (swap.proceeds, swap.cost, swap.gain()),
+            (None, Some(1000.0), None)
+        );
+        assert_eq!(
+            (sale.proceeds, sale.cost, sale.gain()),
+            (Some(400.0), None, None)
+        );
+    }
+}
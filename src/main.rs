@@ -1,42 +1,200 @@
 #![deny(clippy::all)]
 
+use std::process::ExitCode;
+
+use chrono::Utc;
 use clap::Parser;
 use command_line_interface::Cli;
 use command_line_interface::Command;
-use input::InputError;
+use data::Asset;
+use input::{
+    http::{HttpMode, HttpOptions},
+    ErrorCollector, InputError, MappedSource,
+};
+use overrides::Override;
 use sqlx::migrate;
 
+pub mod balances;
 pub mod command_line_interface;
 pub mod data;
 pub mod export;
+pub mod gains;
 pub mod input;
+pub mod lots;
+pub mod normalized;
+pub mod overrides;
+pub mod prices;
+pub mod reconcile;
+pub mod tax;
 
 #[macro_use]
 extern crate dotenv_codegen;
 
-#[tokio::main]
-async fn main() -> Result<(), InputError> {
-    // async fn main() {
-    let cli = Cli::parse();
+/// An empty database in memory with all migrations run, for the tests of the modules
+/// that read and write it
+#[cfg(test)]
+pub async fn memory_db() -> sqlx::Pool<sqlx::Sqlite> {
+    // Every connection to memory opens a database of its own
+    let db = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("opening a database in memory");
+    migrate!("./migrations")
+        .run(&db)
+        .await
+        .expect("migrating the database in memory");
+
+    db
+}
 
+async fn run(cli: Cli, errors: &ErrorCollector) -> Result<(), InputError> {
     let db = sqlx::sqlite::SqlitePoolOptions::new()
         .max_connections(5)
         .connect(dotenv!("DATABASE_URL"))
-        .await
-        .expect("Failed to connect to the database");
+        .await?;
 
     migrate!("./migrations")
         .run(&db)
         .await
-        .expect("Failed to run migrations");
+        .map_err(sqlx::Error::from)?;
+
+    match cli.command {
+        Command::Fetch {
+            exchange,
+            record,
+            replay,
+            concurrency,
+        } => {
+            let mode = match (record, replay) {
+                (Some(directory), _) => HttpMode::Record(directory),
+                (_, Some(directory)) => HttpMode::Replay(directory),
+                _ => HttpMode::Live,
+            };
+
+            let options = HttpOptions { mode, concurrency };
+
+            // Whatever gets fetched is mapped anew, even if the fetch fails halfway
+            normalized::invalidate(&db, &MappedSource::fetched_by(exchange.as_ref())).await?;
+            input::gather_data(&db, exchange, &options, errors).await?;
+            normalized::update(&db, errors).await
+        }
+        Command::Import { format, file } => {
+            normalized::invalidate(&db, &[MappedSource::Statement]).await?;
+            input::statement::import_statement(&db, format, &file).await?;
+            normalized::update(&db, errors).await
+        }
+        Command::Add { transaction } => {
+            // Manual transactions are validated against the up to date sources
+            normalized::update(&db, errors).await?;
+            input::manual::add_transaction(&db, &transaction).await?;
+            normalized::invalidate(&db, &[MappedSource::Manual]).await?;
+            normalized::update(&db, errors).await
+        }
+        Command::Edit { tx_id, transaction } => {
+            normalized::update(&db, errors).await?;
+            input::manual::edit_transaction(&db, &tx_id, &transaction).await?;
+            normalized::invalidate(&db, &[MappedSource::Manual]).await?;
+            normalized::update(&db, errors).await
+        }
+        Command::Delete { tx_id } => {
+            input::manual::delete_transaction(&db, &tx_id).await?;
+            normalized::invalidate(&db, &[MappedSource::Manual]).await?;
+            normalized::update(&db, errors).await
+        }
+        Command::Override {
+            id,
+            kind,
+            amount,
+            source_amount,
+            destination_amount,
+            usd_amount,
+            note,
+            exclude,
+            include,
+            clear,
+        } => {
+            normalized::update(&db, errors).await?;
+            if clear {
+                return overrides::clear_override(&db, &id).await;
+            }
+
+            let changes = Override {
+                kind,
+                amount,
+                source_amount,
+                destination_amount,
+                usd_amount,
+                note,
+                excluded: false,
+            };
+            let excluded = (exclude || include).then_some(exclude);
+            overrides::set_override(&db, &id, changes, excluded).await
+        }
+        Command::Balances { date } => {
+            normalized::update(&db, errors).await?;
+            let date = date.unwrap_or_else(|| Utc::now().date_naive());
+            balances::print_balances(&db, date).await
+        }
+        Command::Gains { method, year } => {
+            normalized::update(&db, errors).await?;
+            gains::print_gains(&db, method, year).await
+        }
+        Command::Tax { year } => {
+            normalized::update(&db, errors).await?;
+            tax::print_tax_report(&db, year).await
+        }
+        Command::Reconcile => {
+            normalized::update(&db, errors).await?;
+            reconcile::print_reconciliation(&db).await
+        }
+        Command::Price {
+            asset,
+            usd_price,
+            date,
+            contract_address,
+        } => {
+            let asset = Asset {
+                name: asset,
+                contract_address,
+            };
+            let date = date.unwrap_or_else(|| Utc::now().date_naive());
+            prices::set_price(&db, &asset, date, usd_price).await?;
+            // Trades without a cash side are valued by the prices
+            normalized::invalidate(&db, &MappedSource::ALL).await?;
+            normalized::update(&db, errors).await
+        }
+        Command::Rates => {
+            prices::fetch_rates(&db, &prices::ecb_client(&HttpOptions::default())).await?;
+            // Fiat amounts are converted at the rates of their day
+            normalized::invalidate(&db, &MappedSource::ALL).await?;
+            normalized::update(&db, errors).await
+        }
+        Command::Display => {
+            normalized::update(&db, errors).await?;
+            normalized::list_all_trades(&db).await
+        }
+        Command::Export => {
+            normalized::update(&db, errors).await?;
+            export::export_data(&db).await
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let errors = ErrorCollector::new(cli.keep_going);
 
-    let result = match cli.command {
-        Command::Fetch { exchange } => input::gather_data(&db, exchange).await,
-        Command::Display => input::list_all_trades(&db).await,
-        Command::Export => export::export_data(&db).await,
-    };
+    let result = run(cli, &errors).await;
 
-    result.expect("Failed to gather data");
+    let mut errors = errors.take_errors();
+    if let Err(error) = result {
+        errors.push(error);
+    }
 
-    Ok(())
+    match input::print_error_summary(&errors) {
+        Some(exit_code) => ExitCode::from(exit_code),
+        None => ExitCode::SUCCESS,
+    }
 }
@@ -0,0 +1,860 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use sqlx::{query, query_as, Pool, Sqlite};
+
+use crate::{
+    data::{
+        Airdrop, Amount, Application, Asset, Bridge, Comission, CurrencyExchange, Trade,
+        Transaction, Transfer, ValueStore,
+    },
+    input::{self, statement, ErrorCollector, InputError, MappedSource, MappedTransaction},
+    overrides,
+};
+
+/// The version of the mappers, sources mapped by another version are mapped anew.
+/// Raise it whenever a mapper changes what it makes of the same raw rows.
+const MAPPING_VERSION: i64 = 14;
+
+/// One asset a transaction moves, with the store it moves into or out of
+struct Leg<'a> {
+    role: &'static str,
+    amount: Amount,
+    store: Option<&'a ValueStore>,
+}
+
+/// A transaction split into the columns of `transactions` and its legs and fee
+struct Parts<'a> {
+    application: Option<&'a str>,
+    usd_amount: f64,
+    timestamp: DateTime<Utc>,
+    note: &'a str,
+    legs: Vec<Leg<'a>>,
+    fee: Option<Comission>,
+}
+
+fn parts(transaction: &Transaction) -> Parts<'_> {
+    let leg = |role, amount: &Amount, store| Leg {
+        role,
+        amount: amount.clone(),
+        store,
+    };
+
+    match transaction {
+        Transaction::Trade(Trade {
+            application,
+            source,
+            destination,
+            comission,
+            usd_amount,
+            timestamp,
+            ..
+        })
+        | Transaction::CurrencyExchange(CurrencyExchange {
+            application,
+            source,
+            destination,
+            comission,
+            usd_amount,
+            timestamp,
+            ..
+        }) => Parts {
+            application: Some(&application.0),
+            usd_amount: *usd_amount,
+            timestamp: *timestamp,
+            note: "",
+            legs: vec![
+                leg("source", source, None),
+                leg("destination", destination, None),
+            ],
+            fee: comission.clone(),
+        },
+        Transaction::Airdrop(airdrop) => Parts {
+            application: None,
+            usd_amount: airdrop.usd_amount,
+            timestamp: airdrop.timestamp,
+            note: &airdrop.note,
+            legs: vec![leg("amount", &airdrop.amount, None)],
+            fee: None,
+        },
+        // The destination receives what the bridge did not keep as its fee
+        Transaction::Bridge(bridge) => Parts {
+            application: Some(&bridge.application.0),
+            usd_amount: 0.0,
+            timestamp: bridge.timestamp,
+            note: "",
+            legs: vec![
+                leg("source", &bridge.amount, Some(&bridge.source)),
+                Leg {
+                    role: "destination",
+                    amount: Amount {
+                        amount: bridge.amount.amount - bridge.comission.amount,
+                        asset: bridge.amount.asset.clone(),
+                    },
+                    store: Some(&bridge.destination),
+                },
+            ],
+            fee: Some(Comission {
+                amount: bridge.comission.clone(),
+                usd_amount: 0.0,
+            }),
+        },
+        Transaction::Deposit(transfer) | Transaction::Withdrawal(transfer) => Parts {
+            application: None,
+            usd_amount: transfer.usd_amount,
+            timestamp: transfer.timestamp,
+            note: &transfer.note,
+            legs: vec![leg("amount", &transfer.amount, Some(&transfer.store))],
+            fee: transfer.comission.clone(),
+        },
+    }
+}
+
+/// Marks the sources to be mapped anew, e.g. before their raw rows change
+pub async fn invalidate(db: &Pool<Sqlite>, sources: &[MappedSource]) -> Result<(), InputError> {
+    for source in sources {
+        let name = source.name();
+        query!(
+            "INSERT INTO sources (name, mapping_version, mapped_at) VALUES ($1, 0, NULL)
+            ON CONFLICT (name) DO UPDATE SET mapping_version = 0",
+            name
+        )
+        .execute(db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Upserts the newly mapped transactions of the source by their raw row, all at once.
+/// Transactions of the same raw row keep their id, those whose raw row no longer maps to
+/// anything are removed.
+async fn replace(
+    db: &Pool<Sqlite>,
+    source: MappedSource,
+    mapped: &[MappedTransaction],
+) -> Result<(), InputError> {
+    let name = source.name();
+    let mut tx = db.begin().await?;
+
+    query!(
+        "INSERT INTO sources (name, mapping_version, mapped_at)
+        VALUES ($1, $2, CURRENT_TIMESTAMP)
+        ON CONFLICT (name) DO UPDATE
+        SET mapping_version = excluded.mapping_version, mapped_at = excluded.mapped_at",
+        name,
+        MAPPING_VERSION
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let mut stale = query!(
+        "SELECT id, raw_table, raw_id, raw_index FROM transactions WHERE source = $1",
+        name
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|row| ((row.raw_table, row.raw_id, row.raw_index), row.id))
+    .collect::<HashMap<_, _>>();
+
+    let mut indexes = HashMap::<(&str, &str), i64>::new();
+    for mapped in mapped {
+        let parts = parts(&mapped.transaction);
+        let tx_id = mapped.transaction.tx_id();
+        let kind = mapped.transaction.kind();
+        // The position of the transaction among those of its raw row
+        let index = indexes
+            .entry((mapped.raw_table, &mapped.raw_id))
+            .or_default();
+        let raw_index = *index;
+        *index += 1;
+
+        let id = query!(
+            "INSERT INTO transactions (source, raw_table, raw_id, raw_index, account, tx_id,
+                kind, application, usd_amount, timestamp, note, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, CURRENT_TIMESTAMP)
+            ON CONFLICT (source, raw_table, raw_id, raw_index) DO UPDATE
+            SET account = excluded.account, tx_id = excluded.tx_id, kind = excluded.kind,
+                application = excluded.application, usd_amount = excluded.usd_amount,
+                timestamp = excluded.timestamp, note = excluded.note
+            RETURNING id",
+            name,
+            mapped.raw_table,
+            mapped.raw_id,
+            raw_index,
+            mapped.account,
+            tx_id,
+            kind,
+            parts.application,
+            parts.usd_amount,
+            parts.timestamp,
+            parts.note
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .id;
+        stale.remove(&(
+            mapped.raw_table.to_string(),
+            mapped.raw_id.clone(),
+            raw_index,
+        ));
+
+        // The legs and fee of a known transaction may have changed with the mapping
+        query!("DELETE FROM transaction_legs WHERE transaction_id = $1", id)
+            .execute(&mut *tx)
+            .await?;
+        query!("DELETE FROM transaction_fees WHERE transaction_id = $1", id)
+            .execute(&mut *tx)
+            .await?;
+
+        for leg in &parts.legs {
+            let (store_type, store_name, store_network, store_address) = match leg.store {
+                Some(ValueStore::Cex(name)) => (Some("cex"), Some(name), None, None),
+                Some(ValueStore::Wallet {
+                    name,
+                    network,
+                    address,
+                }) => (Some("wallet"), Some(name), Some(network), Some(address)),
+                None => (None, None, None, None),
+            };
+
+            query!(
+                "INSERT INTO transaction_legs (transaction_id, role, asset, contract_address,
+                    amount, store_type, store_name, store_network, store_address)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                id,
+                leg.role,
+                leg.amount.asset.name,
+                leg.amount.asset.contract_address,
+                leg.amount.amount,
+                store_type,
+                store_name,
+                store_network,
+                store_address
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(fee) = &parts.fee {
+            query!(
+                "INSERT INTO transaction_fees (transaction_id, asset, contract_address,
+                    amount, usd_amount)
+                VALUES ($1, $2, $3, $4, $5)",
+                id,
+                fee.amount.asset.name,
+                fee.amount.asset.contract_address,
+                fee.amount.amount,
+                fee.usd_amount
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    // Legs and fees go with their transaction
+    for id in stale.into_values() {
+        query!("DELETE FROM transactions WHERE id = $1", id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// What a trade or transfer of an exchange is known by in both its API and its statements,
+/// whose ids differ: the application or store and the assets or the direction
+type BackfillKey = (String, String, String);
+
+/// How far apart the times of a transaction in its API and its statement may be, statements
+/// round to the second
+const BACKFILL_WINDOW_MILLIS: i64 = 2_000;
+
+/// The key of the transaction with its time and the quantity it moves, trades by their
+/// traded crypto
+fn backfill_key(transaction: &Transaction) -> Option<(BackfillKey, DateTime<Utc>, f64)> {
+    match transaction {
+        Transaction::Trade(Trade {
+            application,
+            source,
+            destination,
+            timestamp,
+            ..
+        })
+        | Transaction::CurrencyExchange(CurrencyExchange {
+            application,
+            source,
+            destination,
+            timestamp,
+            ..
+        }) => {
+            let quantity = if source.asset.cash_currency().is_some() {
+                destination.amount
+            } else {
+                source.amount
+            };
+            let key = (
+                application.0.clone(),
+                source.asset.name.clone(),
+                destination.asset.name.clone(),
+            );
+            Some((key, *timestamp, quantity))
+        }
+        Transaction::Deposit(Transfer {
+            store: ValueStore::Cex(name),
+            amount,
+            timestamp,
+            ..
+        })
+        | Transaction::Withdrawal(Transfer {
+            store: ValueStore::Cex(name),
+            amount,
+            timestamp,
+            ..
+        }) => {
+            let key = (
+                name.clone(),
+                transaction.kind().to_string(),
+                amount.asset.name.clone(),
+            );
+            Some((key, *timestamp, amount.amount))
+        }
+        _ => None,
+    }
+}
+
+/// Leaves out the statement transactions the other sources already know. Those with an id
+/// of the exchange are known by it, those with a made up id by their key, time and
+/// quantity. Fills of one order may be split differently, so statement transactions use up
+/// the known quantity of their key close to their time in turn.
+fn without_known(
+    statement: Vec<MappedTransaction>,
+    known: &[Transaction],
+) -> Vec<MappedTransaction> {
+    let known_ids = known.iter().map(Transaction::tx_id).collect::<HashSet<_>>();
+    let mut left = HashMap::<BackfillKey, Vec<(DateTime<Utc>, f64)>>::new();
+    for (key, at, quantity) in known.iter().filter_map(backfill_key) {
+        left.entry(key).or_default().push((at, quantity));
+    }
+
+    statement
+        .into_iter()
+        .filter(|mapped| {
+            let tx_id = mapped.transaction.tx_id();
+            if known_ids.contains(tx_id) {
+                return false;
+            }
+            if !statement::is_made_up_id(tx_id) {
+                return true;
+            }
+            let Some((key, at, quantity)) = backfill_key(&mapped.transaction) else {
+                return true;
+            };
+            let Some(candidates) = left.get_mut(&key) else {
+                return true;
+            };
+
+            let distance = |known_at: DateTime<Utc>| (known_at - at).num_milliseconds().abs();
+            let mut near = candidates
+                .iter_mut()
+                .filter(|(known_at, left)| {
+                    distance(*known_at) <= BACKFILL_WINDOW_MILLIS && *left > 0.0
+                })
+                .collect::<Vec<_>>();
+            near.sort_by_key(|(known_at, _)| distance(*known_at));
+
+            // Rounding of the statement is tolerated
+            let available = near.iter().map(|(_, left)| *left).sum::<f64>();
+            if quantity > available * (1.0 + 1e-6) {
+                return true;
+            }
+            let mut rest = quantity;
+            for (_, left) in near {
+                let used = rest.min(*left);
+                *left -= used;
+                rest -= used;
+            }
+            false
+        })
+        .collect()
+}
+
+/// Maps the sources that were invalidated or mapped by another version of the mappers
+pub async fn update(db: &Pool<Sqlite>, errors: &ErrorCollector) -> Result<(), InputError> {
+    let versions = query!("SELECT name, mapping_version FROM sources")
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| (row.name, row.mapping_version))
+        .collect::<HashMap<_, _>>();
+
+    let mut stale = MappedSource::ALL
+        .into_iter()
+        .filter(|source| versions.get(source.name()) != Some(&MAPPING_VERSION))
+        .collect::<Vec<_>>();
+    // Statements backfill what the other sources miss, so they follow every change of those
+    if !stale.is_empty() && !stale.contains(&MappedSource::Statement) {
+        stale.push(MappedSource::Statement);
+    }
+
+    for source in stale {
+        let mut mapped = input::map_source(db, source, errors).await?;
+
+        if source == MappedSource::Statement {
+            let statement = MappedSource::Statement.name();
+            let known = load_mapped(db)
+                .await?
+                .into_iter()
+                .filter(|record| record.source != statement)
+                .map(|record| record.transaction)
+                .collect::<Vec<_>>();
+
+            mapped = without_known(mapped, &known);
+        }
+
+        replace(db, source, &mapped).await?;
+    }
+
+    Ok(())
+}
+
+struct TransactionRow {
+    id: i64,
+    source: String,
+    account: String,
+    tx_id: String,
+    kind: String,
+    application: Option<String>,
+    usd_amount: f64,
+    timestamp: DateTime<Utc>,
+    note: String,
+}
+
+struct LegRow {
+    transaction_id: i64,
+    role: String,
+    asset: String,
+    contract_address: Option<String>,
+    amount: f64,
+    store_type: Option<String>,
+    store_name: Option<String>,
+    store_network: Option<String>,
+    store_address: Option<String>,
+}
+
+struct FeeRow {
+    transaction_id: i64,
+    asset: String,
+    contract_address: Option<String>,
+    amount: f64,
+    usd_amount: f64,
+}
+
+impl LegRow {
+    fn amount(&self) -> Amount {
+        Amount {
+            amount: self.amount,
+            asset: Asset {
+                name: self.asset.clone(),
+                contract_address: self.contract_address.clone(),
+            },
+        }
+    }
+
+    fn store(&self) -> Result<ValueStore, InputError> {
+        match (
+            self.store_type.as_deref(),
+            &self.store_name,
+            &self.store_network,
+            &self.store_address,
+        ) {
+            (Some("cex"), Some(name), _, _) => Ok(ValueStore::Cex(name.clone())),
+            (Some("wallet"), Some(name), Some(network), Some(address)) => Ok(ValueStore::Wallet {
+                name: name.clone(),
+                network: network.clone(),
+                address: address.clone(),
+            }),
+            _ => Err(InputError::UnsupportedError(format!(
+                "{} leg of transaction {} without store",
+                self.role, self.transaction_id
+            ))),
+        }
+    }
+}
+
+fn to_transaction(
+    row: TransactionRow,
+    legs: &[LegRow],
+    fee: Option<&FeeRow>,
+) -> Result<Transaction, InputError> {
+    let leg = |role: &str| {
+        legs.iter()
+            .find(|leg| leg.role == role)
+            .ok_or(InputError::UnsupportedError(format!(
+                "transaction {} without {role} leg",
+                row.id
+            )))
+    };
+    let comission = fee.map(|fee| Comission {
+        amount: Amount {
+            amount: fee.amount,
+            asset: Asset {
+                name: fee.asset.clone(),
+                contract_address: fee.contract_address.clone(),
+            },
+        },
+        usd_amount: fee.usd_amount,
+    });
+    let application = Application(row.application.clone().unwrap_or_default());
+
+    Ok(match row.kind.as_str() {
+        "Trade" => Transaction::Trade(Trade {
+            application,
+            tx_id: row.tx_id,
+            source: leg("source")?.amount(),
+            destination: leg("destination")?.amount(),
+            comission,
+            usd_amount: row.usd_amount,
+            timestamp: row.timestamp,
+        }),
+        "CurrencyExchange" => Transaction::CurrencyExchange(CurrencyExchange {
+            application,
+            tx_id: row.tx_id,
+            source: leg("source")?.amount(),
+            destination: leg("destination")?.amount(),
+            comission,
+            usd_amount: row.usd_amount,
+            timestamp: row.timestamp,
+        }),
+        "Airdrop" => Transaction::Airdrop(Airdrop {
+            tx_id: row.tx_id,
+            amount: leg("amount")?.amount(),
+            usd_amount: row.usd_amount,
+            timestamp: row.timestamp,
+            note: row.note,
+        }),
+        "Bridge" => {
+            let source = leg("source")?;
+            Transaction::Bridge(Bridge {
+                application,
+                tx_id: row.tx_id,
+                source: source.store()?,
+                destination: leg("destination")?.store()?,
+                amount: source.amount(),
+                comission: comission
+                    .map(|comission| comission.amount)
+                    .unwrap_or(Amount {
+                        amount: 0.0,
+                        asset: source.amount().asset,
+                    }),
+                timestamp: row.timestamp,
+            })
+        }
+        "Deposit" | "Withdrawal" => {
+            let amount = leg("amount")?;
+            let transfer = Transfer {
+                tx_id: row.tx_id,
+                store: amount.store()?,
+                amount: amount.amount(),
+                comission,
+                usd_amount: row.usd_amount,
+                timestamp: row.timestamp,
+                note: row.note,
+            };
+            match row.kind.as_str() {
+                "Deposit" => Transaction::Deposit(transfer),
+                _ => Transaction::Withdrawal(transfer),
+            }
+        }
+        kind => {
+            return Err(InputError::UnsupportedError(format!(
+                "transaction kind '{kind}'"
+            )))
+        }
+    })
+}
+
+/// A transaction with the source and the account it was mapped from
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub source: String,
+    pub account: String,
+    pub transaction: Transaction,
+}
+
+impl Record {
+    /// The id of a leg of the transaction, e.g. the sold side of a trade, which stays
+    /// the same across fetches and mappings and is unique across all sources
+    pub fn global_id(&self, leg: &str) -> String {
+        format!("{}/{leg}", self.id())
+    }
+
+    /// The id of the transaction, unique across all sources: its source, account and tx_id
+    pub fn id(&self) -> String {
+        format!(
+            "{}/{}/{}",
+            self.source,
+            self.account,
+            self.transaction.tx_id()
+        )
+    }
+}
+
+/// All mapped transactions of all sources, oldest first
+pub async fn load_mapped(db: &Pool<Sqlite>) -> Result<Vec<Record>, InputError> {
+    let rows = query_as!(
+        TransactionRow,
+        r#"SELECT id, source, account, tx_id, kind, application, usd_amount,
+            timestamp AS "timestamp: DateTime<Utc>", note
+        FROM transactions ORDER BY timestamp, id"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut legs = HashMap::<i64, Vec<LegRow>>::new();
+    for leg in query_as!(
+        LegRow,
+        "SELECT transaction_id, role, asset, contract_address, amount,
+            store_type, store_name, store_network, store_address
+        FROM transaction_legs"
+    )
+    .fetch_all(db)
+    .await?
+    {
+        legs.entry(leg.transaction_id).or_default().push(leg);
+    }
+
+    let fees = query_as!(
+        FeeRow,
+        "SELECT transaction_id, asset, contract_address, amount, usd_amount
+        FROM transaction_fees"
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|fee| (fee.transaction_id, fee))
+    .collect::<HashMap<_, _>>();
+
+    rows.into_iter()
+        .map(|row| {
+            let id = row.id;
+            let source = row.source.clone();
+            let account = row.account.clone();
+            let transaction = to_transaction(
+                row,
+                legs.get(&id).map(|legs| &legs[..]).unwrap_or_default(),
+                fees.get(&id),
+            )?;
+            Ok(Record {
+                source,
+                account,
+                transaction,
+            })
+        })
+        .collect()
+}
+
+/// The records with their overrides applied, with a warning for each override that no
+/// longer fits its transaction. The transaction may have been mapped anew into something the
+/// override does not fit anymore, it is kept as mapped until the override is corrected or
+/// cleared.
+fn apply_overrides(
+    mapped: Vec<Record>,
+    overrides: &HashMap<String, overrides::Override>,
+) -> (Vec<Record>, Vec<String>) {
+    let mut records = vec![];
+    let mut warnings = vec![];
+    for record in mapped {
+        let Some(changes) = overrides.get(&record.id()) else {
+            records.push(record);
+            continue;
+        };
+        match changes.apply(record.transaction.clone()) {
+            Ok(Some(transaction)) => records.push(Record {
+                transaction,
+                ..record
+            }),
+            Ok(None) => {}
+            Err(error) => {
+                warnings.push(format!(
+                    "Skipped the override of transaction {}: {error}",
+                    record.id()
+                ));
+                records.push(record);
+            }
+        }
+    }
+
+    (records, warnings)
+}
+
+/// All transactions of all sources with their overrides applied, oldest first
+pub async fn load_all(db: &Pool<Sqlite>) -> Result<Vec<Record>, InputError> {
+    let overrides = overrides::load(db).await?;
+    let (records, warnings) = apply_overrides(load_mapped(db).await?, &overrides);
+    for warning in warnings {
+        eprintln!("{warning}");
+    }
+
+    Ok(records)
+}
+
+pub async fn list_all_trades(db: &Pool<Sqlite>) -> Result<(), InputError> {
+    let trades = load_all(db)
+        .await?
+        .into_iter()
+        .map(|record| record.transaction)
+        .collect::<Vec<_>>();
+    let json = serde_json::to_string(&trades)?;
+
+    println!("{}", json);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn amount(amount: f64, name: &str) -> Amount {
+        Amount {
+            amount,
+            asset: Asset {
+                name: name.to_string(),
+                contract_address: None,
+            },
+        }
+    }
+
+    fn buy(tx_id: &str, kas: f64, millis: i64) -> Transaction {
+        Transaction::exchange(
+            Application("MEXC".to_string()),
+            tx_id.to_string(),
+            amount(kas / 10.0, "USDT"),
+            amount(kas, "KAS"),
+            None,
+            kas / 10.0,
+            Utc.timestamp_millis_opt(1_700_000_000_000 + millis)
+                .unwrap(),
+        )
+    }
+
+    fn statement(transactions: Vec<Transaction>) -> Vec<MappedTransaction> {
+        transactions
+            .into_iter()
+            .map(|t| {
+                let raw_id = t.tx_id().to_string();
+                MappedTransaction::new("statement_rows", &raw_id, "mexc_spot", t)
+            })
+            .collect()
+    }
+
+    fn ids(mapped: &[MappedTransaction]) -> Vec<&str> {
+        mapped.iter().map(|m| m.transaction.tx_id()).collect()
+    }
+
+    /// An id like those made up for statement rows without ids of their own
+    fn hash(occurrence: usize) -> String {
+        format!("{}-{occurrence}", "ab".repeat(32))
+    }
+
+    #[test]
+    fn statement_trades_the_api_knows_by_other_ids_are_left_out() {
+        let known = [buy("api-1", 100.0, 250), buy("api-2", 50.0, 5_000)];
+        let mapped = statement(vec![
+            buy(&hash(1), 100.0, 0),
+            buy(&hash(2), 50.0, 5_000),
+            buy(&hash(3), 70.0, 9_000),
+        ]);
+
+        assert_eq!(ids(&without_known(mapped, &known)), [hash(3)]);
+    }
+
+    #[test]
+    fn fills_split_differently_are_compared_in_sum() {
+        let known = [buy("api-1", 60.0, 100), buy("api-2", 40.0, 1_200)];
+        let mapped = statement(vec![buy(&hash(1), 100.0, 0)]);
+
+        assert!(without_known(mapped, &known).is_empty());
+    }
+
+    #[test]
+    fn statement_trades_beyond_the_api_are_kept() {
+        let known = [buy("api-1", 60.0, 100)];
+        let mapped = statement(vec![buy(&hash(1), 60.0, 0), buy(&hash(2), 40.0, 0)]);
+
+        assert_eq!(ids(&without_known(mapped, &known)), [hash(2)]);
+    }
+
+    #[test]
+    fn trades_are_matched_within_a_window_across_seconds() {
+        // The first pair lies in two seconds, the second pair too far apart to be one trade
+        let known = [buy("api-1", 60.0, 900), buy("api-2", 60.0, 9_000)];
+        let mapped = statement(vec![buy(&hash(1), 60.0, 1_100), buy(&hash(2), 60.0, 4_000)]);
+
+        assert_eq!(ids(&without_known(mapped, &known)), [hash(2)]);
+    }
+
+    #[test]
+    fn statement_trades_with_ids_of_the_exchange_are_known_by_them_only() {
+        let known = [buy("api-1", 100.0, 0)];
+        let mapped = statement(vec![buy("api-1", 100.0, 0), buy("T-2", 100.0, 0)]);
+
+        assert_eq!(ids(&without_known(mapped, &known)), ["T-2"]);
+    }
+
+    #[test]
+    fn overrides_apply_by_id_and_those_that_no_longer_fit_are_skipped() {
+        let record = |tx_id: &str, kas: f64| Record {
+            source: "MEXC".to_string(),
+            account: "MEXC".to_string(),
+            transaction: buy(tx_id, kas, 0),
+        };
+        let overrides = HashMap::from([
+            (
+                "MEXC/MEXC/t1".to_string(),
+                overrides::Override {
+                    destination_amount: Some(90.0),
+                    ..Default::default()
+                },
+            ),
+            (
+                "MEXC/MEXC/t2".to_string(),
+                overrides::Override {
+                    note: Some("a trade has no note".to_string()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "MEXC/MEXC/t3".to_string(),
+                overrides::Override {
+                    excluded: true,
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        let (records, warnings) = apply_overrides(
+            vec![record("t1", 100.0), record("t2", 50.0), record("t3", 10.0)],
+            &overrides,
+        );
+
+        let amounts = records
+            .iter()
+            .map(|record| match &record.transaction {
+                Transaction::Trade(trade) => (trade.tx_id.as_str(), trade.destination.amount),
+                transaction => panic!("no trade: {transaction:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(amounts, [("t1", 90.0), ("t2", 50.0)]);
+        let [warning] = &warnings[..] else {
+            panic!("expected one warning, got {warnings:?}");
+        };
+        assert!(warning.contains("MEXC/MEXC/t2") && warning.contains("has no note"));
+    }
+}
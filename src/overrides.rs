@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+
+use sqlx::{query, Pool, Sqlite};
+
+use crate::{
+    command_line_interface::TransactionKind,
+    data::{Airdrop, CurrencyExchange, Trade, Transaction},
+    input::{InputError, MappedSource},
+    normalized,
+};
+
+impl TransactionKind {
+    /// The name the kind is stored with, the same as `Transaction::kind`
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionKind::Trade => "Trade",
+            TransactionKind::CurrencyExchange => "CurrencyExchange",
+            TransactionKind::Airdrop => "Airdrop",
+            TransactionKind::Deposit => "Deposit",
+            TransactionKind::Withdrawal => "Withdrawal",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TransactionKind> {
+        match name {
+            "Trade" => Some(TransactionKind::Trade),
+            "CurrencyExchange" => Some(TransactionKind::CurrencyExchange),
+            "Airdrop" => Some(TransactionKind::Airdrop),
+            "Deposit" => Some(TransactionKind::Deposit),
+            "Withdrawal" => Some(TransactionKind::Withdrawal),
+            _ => None,
+        }
+    }
+}
+
+/// The corrections of a mapped transaction, kept apart from the raw rows so that
+/// fetching and mapping them anew keeps the corrections
+#[derive(Debug, Clone, Default)]
+pub struct Override {
+    /// Reclassifies the transaction
+    pub kind: Option<TransactionKind>,
+    /// The amount of an airdrop, bridge, deposit or withdrawal
+    pub amount: Option<f64>,
+    /// The sold amount of a trade or currency exchange
+    pub source_amount: Option<f64>,
+    /// The bought amount of a trade or currency exchange
+    pub destination_amount: Option<f64>,
+    pub usd_amount: Option<f64>,
+    /// The note of an airdrop, deposit or withdrawal
+    pub note: Option<String>,
+    /// Leaves the transaction out of everything
+    pub excluded: bool,
+}
+
+fn set<T>(
+    value: &Option<T>,
+    target: Option<&mut T>,
+    field: &str,
+    kind: &str,
+) -> Result<(), InputError>
+where
+    T: Clone,
+{
+    match (value, target) {
+        (Some(value), Some(target)) => {
+            *target = value.clone();
+            Ok(())
+        }
+        (Some(_), None) => Err(InputError::InvalidError(format!(
+            "a {kind} has no {field} to override"
+        ))),
+        (None, _) => Ok(()),
+    }
+}
+
+/// Turns the transaction into one of another kind, as far as it carries what that kind needs
+fn reclassify(transaction: Transaction, kind: TransactionKind) -> Result<Transaction, InputError> {
+    if transaction.kind() == kind.name() {
+        return Ok(transaction);
+    }
+
+    Ok(match (transaction, kind) {
+        (Transaction::Trade(trade), TransactionKind::CurrencyExchange) => {
+            Transaction::CurrencyExchange(CurrencyExchange {
+                application: trade.application,
+                tx_id: trade.tx_id,
+                source: trade.source,
+                destination: trade.destination,
+                comission: trade.comission,
+                usd_amount: trade.usd_amount,
+                timestamp: trade.timestamp,
+            })
+        }
+        (Transaction::CurrencyExchange(exchange), TransactionKind::Trade) => {
+            Transaction::Trade(Trade {
+                application: exchange.application,
+                tx_id: exchange.tx_id,
+                source: exchange.source,
+                destination: exchange.destination,
+                comission: exchange.comission,
+                usd_amount: exchange.usd_amount,
+                timestamp: exchange.timestamp,
+            })
+        }
+        (Transaction::Deposit(transfer), TransactionKind::Withdrawal) => {
+            Transaction::Withdrawal(transfer)
+        }
+        (Transaction::Withdrawal(transfer), TransactionKind::Deposit) => {
+            Transaction::Deposit(transfer)
+        }
+        // A deposit that was given for free, e.g. a referral bonus
+        (Transaction::Deposit(transfer), TransactionKind::Airdrop) => {
+            Transaction::Airdrop(Airdrop {
+                tx_id: transfer.tx_id,
+                amount: transfer.amount,
+                usd_amount: transfer.usd_amount,
+                timestamp: transfer.timestamp,
+                note: transfer.note,
+            })
+        }
+        (transaction, kind) => {
+            return Err(InputError::InvalidError(format!(
+                "a {} can not become a {}",
+                transaction.kind(),
+                kind.name()
+            )))
+        }
+    })
+}
+
+impl Override {
+    /// The corrected transaction, or none if the transaction is excluded
+    pub fn apply(&self, transaction: Transaction) -> Result<Option<Transaction>, InputError> {
+        if self.excluded {
+            return Ok(None);
+        }
+
+        let mut transaction = match self.kind {
+            Some(kind) => reclassify(transaction, kind)?,
+            None => transaction,
+        };
+
+        let kind = transaction.kind();
+        let (amount, source_amount, destination_amount, usd_amount, note) = match &mut transaction {
+            Transaction::Trade(Trade {
+                source,
+                destination,
+                usd_amount,
+                ..
+            })
+            | Transaction::CurrencyExchange(CurrencyExchange {
+                source,
+                destination,
+                usd_amount,
+                ..
+            }) => (
+                None,
+                Some(&mut source.amount),
+                Some(&mut destination.amount),
+                Some(usd_amount),
+                None,
+            ),
+            Transaction::Airdrop(airdrop) => (
+                Some(&mut airdrop.amount.amount),
+                None,
+                None,
+                Some(&mut airdrop.usd_amount),
+                Some(&mut airdrop.note),
+            ),
+            Transaction::Bridge(bridge) => {
+                (Some(&mut bridge.amount.amount), None, None, None, None)
+            }
+            Transaction::Deposit(transfer) | Transaction::Withdrawal(transfer) => (
+                Some(&mut transfer.amount.amount),
+                None,
+                None,
+                Some(&mut transfer.usd_amount),
+                Some(&mut transfer.note),
+            ),
+        };
+
+        set(&self.amount, amount, "amount", kind)?;
+        set(&self.source_amount, source_amount, "source amount", kind)?;
+        set(
+            &self.destination_amount,
+            destination_amount,
+            "destination amount",
+            kind,
+        )?;
+        set(&self.usd_amount, usd_amount, "usd amount", kind)?;
+        set(&self.note, note, "note", kind)?;
+
+        Ok(Some(transaction))
+    }
+
+    /// The override with the given changes on top
+    fn merge(self, changes: Override, excluded: Option<bool>) -> Override {
+        Override {
+            kind: changes.kind.or(self.kind),
+            amount: changes.amount.or(self.amount),
+            source_amount: changes.source_amount.or(self.source_amount),
+            destination_amount: changes.destination_amount.or(self.destination_amount),
+            usd_amount: changes.usd_amount.or(self.usd_amount),
+            note: changes.note.or(self.note),
+            excluded: excluded.unwrap_or(self.excluded),
+        }
+    }
+}
+
+/// All overrides by the id of the transaction they correct, see `Record::id`
+pub async fn load(db: &Pool<Sqlite>) -> Result<HashMap<String, Override>, InputError> {
+    let rows = query!(
+        r#"SELECT source, account, tx_id, kind, amount, source_amount, destination_amount,
+            usd_amount, note, excluded AS "excluded: bool"
+        FROM transaction_overrides"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut overrides = HashMap::new();
+    for row in rows {
+        let kind = match row.kind {
+            Some(kind) => Some(TransactionKind::from_name(&kind).ok_or(
+                InputError::UnsupportedError(format!("transaction kind '{kind}'")),
+            )?),
+            None => None,
+        };
+
+        overrides.insert(
+            format!("{}/{}/{}", row.source, row.account, row.tx_id),
+            Override {
+                kind,
+                amount: row.amount,
+                source_amount: row.source_amount,
+                destination_amount: row.destination_amount,
+                usd_amount: row.usd_amount,
+                note: row.note,
+                excluded: row.excluded,
+            },
+        );
+    }
+
+    Ok(overrides)
+}
+
+/// The source, account and tx_id of the id of a transaction
+fn parse_id(id: &str) -> Result<(&str, &str, &str), InputError> {
+    let mut parts = id.splitn(3, '/');
+    let (Some(source), Some(account), Some(tx_id)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(InputError::InvalidError(format!(
+            "'{id}' is no transaction id, expected source/account/tx_id"
+        )));
+    };
+
+    if MappedSource::ALL.iter().any(|known| known.name() == source) {
+        Ok((source, account, tx_id))
+    } else {
+        let names = MappedSource::ALL.map(|known| known.name()).join(", ");
+        Err(InputError::InvalidError(format!(
+            "unknown source '{source}', expected one of {names}"
+        )))
+    }
+}
+
+/// Adds the changes to the override of the transaction, which they must apply to
+pub async fn set_override(
+    db: &Pool<Sqlite>,
+    id: &str,
+    changes: Override,
+    excluded: Option<bool>,
+) -> Result<(), InputError> {
+    let (source, account, tx_id) = parse_id(id)?;
+
+    let current = load(db).await?.remove(id).unwrap_or_default();
+    let merged = current.merge(changes, excluded);
+
+    let transactions = normalized::load_mapped(db)
+        .await?
+        .into_iter()
+        .filter(|record| record.id() == id)
+        .collect::<Vec<_>>();
+    if transactions.is_empty() {
+        return Err(InputError::InvalidError(format!(
+            "there is no transaction '{id}'"
+        )));
+    }
+    for record in transactions {
+        merged.apply(record.transaction)?;
+    }
+
+    let kind = merged.kind.map(|kind| kind.name());
+    query!(
+        "INSERT INTO transaction_overrides (source, account, tx_id, kind, amount,
+            source_amount, destination_amount, usd_amount, note, excluded, created_at,
+            updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+        ON CONFLICT (source, account, tx_id) DO UPDATE SET kind = excluded.kind,
+            amount = excluded.amount, source_amount = excluded.source_amount,
+            destination_amount = excluded.destination_amount,
+            usd_amount = excluded.usd_amount, note = excluded.note,
+            excluded = excluded.excluded, updated_at = excluded.updated_at",
+        source,
+        account,
+        tx_id,
+        kind,
+        merged.amount,
+        merged.source_amount,
+        merged.destination_amount,
+        merged.usd_amount,
+        merged.note,
+        merged.excluded
+    )
+    .execute(db)
+    .await?;
+
+    println!("Overrode transaction {id}");
+
+    Ok(())
+}
+
+pub async fn clear_override(db: &Pool<Sqlite>, id: &str) -> Result<(), InputError> {
+    let (source, account, tx_id) = parse_id(id)?;
+
+    let result = query!(
+        "DELETE FROM transaction_overrides WHERE source = $1 AND account = $2 AND tx_id = $3",
+        source,
+        account,
+        tx_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(InputError::InvalidError(format!(
+            "there is no override of transaction '{id}'"
+        )));
+    }
+
+    println!("Cleared the override of transaction {id}");
+
+    Ok(())
+}
@@ -0,0 +1,444 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, NaiveDate};
+use sqlx::{query, Pool, Sqlite};
+
+use crate::{
+    data::{Amount, Asset},
+    input::{
+        http::{HttpClient, HttpOptions, RateLimit},
+        InputError,
+    },
+};
+
+/// Fiat rates are published on working days only, older ones do not count for a day
+const MAX_RATE_AGE: Duration = Duration::days(7);
+
+/// The currencies the euro reference rates are fetched of, all are converted through usd
+const ECB_CURRENCIES: [&str; 3] = ["USD", "GBP", "CHF"];
+
+const ECB_RATE_LIMIT: RateLimit = RateLimit {
+    weight: 1,
+    per: std::time::Duration::from_secs(1),
+};
+
+/// The usd prices of assets by day
+#[derive(Default)]
+pub struct PriceStore {
+    /// The prices by asset name and contract address, oldest first
+    prices: HashMap<(String, String), Vec<(NaiveDate, f64)>>,
+}
+
+fn key(asset: &Asset) -> (String, String) {
+    (
+        asset.name.clone(),
+        asset.contract_address.clone().unwrap_or_default(),
+    )
+}
+
+impl PriceStore {
+    pub async fn load(db: &Pool<Sqlite>) -> Result<PriceStore, InputError> {
+        let rows = query!(
+            r#"SELECT asset, contract_address, date AS "date: NaiveDate", usd_price
+            FROM prices ORDER BY date"#
+        )
+        .fetch_all(db)
+        .await?;
+
+        let mut prices = HashMap::<_, Vec<_>>::new();
+        for row in rows {
+            prices
+                .entry((row.asset, row.contract_address))
+                .or_default()
+                .push((row.date, row.usd_price));
+        }
+
+        Ok(PriceStore { prices })
+    }
+
+    /// The latest stored price of the asset on or before the date, with its day
+    fn stored_price(&self, asset: &Asset, date: NaiveDate) -> Option<(NaiveDate, f64)> {
+        self.prices
+            .get(&key(asset))?
+            .iter()
+            .take_while(|(day, _)| *day <= date)
+            .last()
+            .copied()
+    }
+
+    /// The usd rate of the fiat currency on the day, from the last working day before it
+    fn fiat_usd_rate(&self, currency: &str, date: NaiveDate) -> Option<f64> {
+        if currency == "USD" {
+            return Some(1.0);
+        }
+
+        let currency = Asset {
+            name: currency.to_string(),
+            contract_address: None,
+        };
+        self.stored_price(&currency, date)
+            .filter(|(day, _)| date - *day <= MAX_RATE_AGE)
+            .map(|(_, rate)| rate)
+    }
+
+    /// The latest price of the asset on or before the date,
+    /// fiat and stablecoins are worth their currency on the day
+    pub fn usd_price(&self, asset: &Asset, date: NaiveDate) -> Option<f64> {
+        match asset.cash_currency() {
+            Some(currency) => self.fiat_usd_rate(currency, date),
+            None => self.stored_price(asset, date).map(|(_, price)| price),
+        }
+    }
+
+    /// Converts the amount of the fiat currency to usd at the rate of the day
+    pub fn convert_to_usd(
+        &self,
+        currency: &str,
+        amount: f64,
+        date: NaiveDate,
+    ) -> Result<f64, InputError> {
+        self.fiat_usd_rate(currency, date)
+            .map(|rate| amount * rate)
+            .ok_or_else(|| InputError::MissingRateError {
+                currency: currency.to_string(),
+                date,
+            })
+    }
+
+    /// The usd value of fiat and stablecoin amounts on the day, other assets have none
+    pub fn cash_usd_value(&self, amount: &Amount, date: NaiveDate) -> Option<f64> {
+        amount
+            .asset
+            .cash_currency()
+            .and_then(|currency| self.fiat_usd_rate(currency, date))
+            .map(|rate| amount.amount * rate)
+    }
+
+    /// The usd value of a trade's fee, derived from the trade if the fee is paid in one of its
+    /// assets
+    pub fn fee_usd_value(
+        &self,
+        fee: &Amount,
+        source: &Amount,
+        destination: &Amount,
+        usd_amount: f64,
+        date: NaiveDate,
+    ) -> f64 {
+        if let Some(usd) = self.cash_usd_value(fee, date) {
+            usd
+        } else if fee.asset.name == destination.asset.name {
+            fee.amount * usd_amount / destination.amount
+        } else if fee.asset.name == source.asset.name {
+            fee.amount * usd_amount / source.amount
+        } else {
+            // e.g. fees paid in an exchange token are valued at its price, if there is one
+            self.usd_value(fee, date).unwrap_or(0.0)
+        }
+    }
+
+    /// Adds the price of the asset on the day, days are to be added in order
+    pub fn insert(&mut self, asset: &Asset, date: NaiveDate, usd_price: f64) {
+        self.prices
+            .entry(key(asset))
+            .or_default()
+            .push((date, usd_price));
+    }
+
+    /// The usd value of the amount on the day, if a price is known
+    pub fn usd_value(&self, amount: &Amount, date: NaiveDate) -> Option<f64> {
+        self.usd_price(&amount.asset, date)
+            .map(|price| price * amount.amount)
+    }
+
+    /// The usd value of a trade, taken from a cash side if there is one and else from the
+    /// price of either side on the day
+    pub fn trade_usd_value(
+        &self,
+        source: &Amount,
+        destination: &Amount,
+        date: NaiveDate,
+    ) -> Option<f64> {
+        self.cash_usd_value(source, date)
+            .or_else(|| self.cash_usd_value(destination, date))
+            .or_else(|| self.usd_value(destination, date))
+            .or_else(|| self.usd_value(source, date))
+    }
+}
+
+/// Stores the usd price of the asset on the day, replacing an earlier one of the day
+pub async fn set_price(
+    db: &Pool<Sqlite>,
+    asset: &Asset,
+    date: NaiveDate,
+    usd_price: f64,
+) -> Result<(), InputError> {
+    if !usd_price.is_finite() || usd_price < 0.0 {
+        return Err(InputError::InvalidError(format!(
+            "price {usd_price} is negative or not a number"
+        )));
+    }
+
+    let (name, contract_address) = key(asset);
+    query!(
+        "INSERT INTO prices (asset, contract_address, date, usd_price, source, created_at)
+        VALUES ($1, $2, $3, $4, 'manual', CURRENT_TIMESTAMP)
+        ON CONFLICT (asset, contract_address, date) DO UPDATE
+        SET usd_price = excluded.usd_price, source = excluded.source,
+            created_at = excluded.created_at",
+        name,
+        contract_address,
+        date,
+        usd_price
+    )
+    .execute(db)
+    .await?;
+
+    println!("Set the price of {name} on {date} to {usd_price} USD");
+
+    Ok(())
+}
+
+/// The client of the ECB data portal, `ECB_BASE_URL` points elsewhere
+pub fn ecb_client(options: &HttpOptions) -> HttpClient {
+    HttpClient::from_env(
+        "ecb",
+        "ECB_BASE_URL",
+        "https://data-api.ecb.europa.eu",
+        ECB_RATE_LIMIT,
+    )
+    .with_options(options)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct EcbRateRow {
+    #[serde(rename = "CURRENCY")]
+    currency: String,
+    #[serde(rename = "TIME_PERIOD")]
+    date: NaiveDate,
+    /// Empty on days without a fixing
+    #[serde(rename = "OBS_VALUE")]
+    per_eur: Option<f64>,
+}
+
+/// Requests the euro reference rates from the day on and turns them into usd prices of the
+/// euro and the other currencies
+async fn request_rates(
+    client: &HttpClient,
+    since: NaiveDate,
+) -> Result<Vec<(String, NaiveDate, f64)>, InputError> {
+    let path = format!(
+        "/service/data/EXR/D.{}.EUR.SP00.A?format=csvdata&startPeriod={since}",
+        ECB_CURRENCIES.join("+")
+    );
+    let response = match client.send(1, || client.get(&path)).await {
+        Ok(response) => response,
+        // There are no rates since the day yet
+        Err(InputError::StatusError(404, _)) => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+
+    let mut days = BTreeMap::<NaiveDate, BTreeMap<String, f64>>::new();
+    for row in csv::Reader::from_reader(response.body.as_bytes()).deserialize::<EcbRateRow>() {
+        let row = row?;
+        if let Some(per_eur) = row.per_eur.filter(|per_eur| *per_eur > 0.0) {
+            days.entry(row.date)
+                .or_default()
+                .insert(row.currency, per_eur);
+        }
+    }
+
+    let mut rates = vec![];
+    for (date, per_eur) in days {
+        let Some(usd_per_eur) = per_eur.get("USD").copied() else {
+            continue;
+        };
+        rates.push(("EUR".to_string(), date, usd_per_eur));
+        for (currency, currency_per_eur) in per_eur.into_iter().filter(|(c, _)| c != "USD") {
+            rates.push((currency, date, usd_per_eur / currency_per_eur));
+        }
+    }
+
+    Ok(rates)
+}
+
+/// Fetches the euro reference rates of the ECB since the last fetched day into the prices,
+/// rates set by hand are kept
+pub async fn fetch_rates(db: &Pool<Sqlite>, client: &HttpClient) -> Result<(), InputError> {
+    let last = query!(r#"SELECT MAX(date) AS "date?: NaiveDate" FROM prices WHERE source = 'ecb'"#)
+        .fetch_one(db)
+        .await?
+        .date;
+    // Long before the first exchange accepted euros
+    let since = last.unwrap_or(NaiveDate::from_ymd_opt(2010, 1, 1).expect("a valid day"));
+
+    let rates = request_rates(client, since).await?;
+
+    let mut tx = db.begin().await?;
+    for (currency, date, usd_price) in &rates {
+        query!(
+            "INSERT INTO prices (asset, contract_address, date, usd_price, source, created_at)
+            VALUES ($1, '', $2, $3, 'ecb', CURRENT_TIMESTAMP)
+            ON CONFLICT (asset, contract_address, date) DO UPDATE
+            SET usd_price = excluded.usd_price, created_at = excluded.created_at
+            WHERE prices.source = 'ecb'",
+            currency,
+            date,
+            usd_price
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    match rates.last() {
+        Some((_, date, _)) => println!("Fetched {} rates up to {date}", rates.len()),
+        None => println!("No rates since {since}"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+    }
+
+    fn eur(amount: f64) -> Amount {
+        Amount {
+            amount,
+            asset: Asset {
+                name: "EUR".to_string(),
+                contract_address: None,
+            },
+        }
+    }
+
+    #[test]
+    fn fiat_is_converted_at_the_rate_of_the_last_working_day() {
+        let mut prices = PriceStore::default();
+        prices.insert(&eur(1.0).asset, day(1), 1.25);
+        prices.insert(&eur(1.0).asset, day(4), 1.5);
+
+        // Saturday takes Friday's rate
+        assert_eq!(prices.convert_to_usd("EUR", 100.0, day(2)).unwrap(), 125.0);
+        assert_eq!(prices.convert_to_usd("EUR", 100.0, day(4)).unwrap(), 150.0);
+        assert_eq!(prices.convert_to_usd("USD", 100.0, day(4)).unwrap(), 100.0);
+        assert_eq!(prices.cash_usd_value(&eur(10.0), day(5)), Some(15.0));
+    }
+
+    #[test]
+    fn missing_or_outdated_rates_are_errors() {
+        let mut prices = PriceStore::default();
+        prices.insert(&eur(1.0).asset, day(4), 1.09);
+
+        assert!(matches!(
+            prices.convert_to_usd("EUR", 1.0, day(1)),
+            Err(InputError::MissingRateError { .. })
+        ));
+        assert!(prices.convert_to_usd("EUR", 1.0, day(20)).is_err());
+        assert!(prices.convert_to_usd("GBP", 1.0, day(4)).is_err());
+        assert_eq!(prices.cash_usd_value(&eur(1.0), day(20)), None);
+    }
+
+    #[test]
+    fn token_swaps_are_valued_from_either_side() {
+        let token = |name: &str, amount: f64| Amount {
+            amount,
+            asset: Asset {
+                name: name.to_string(),
+                contract_address: Some(format!("0x{name}")),
+            },
+        };
+        let mut prices = PriceStore::default();
+
+        assert_eq!(
+            prices.trade_usd_value(&token("UNI", 10.0), &token("LINK", 5.0), day(4)),
+            None
+        );
+
+        prices.insert(&token("UNI", 1.0).asset, day(1), 7.0);
+        assert_eq!(
+            prices.trade_usd_value(&token("UNI", 10.0), &token("LINK", 5.0), day(4)),
+            Some(70.0)
+        );
+
+        // The received side is preferred, it is what the wallet holds afterwards
+        prices.insert(&token("LINK", 1.0).asset, day(1), 15.0);
+        assert_eq!(
+            prices.trade_usd_value(&token("UNI", 10.0), &token("LINK", 5.0), day(4)),
+            Some(75.0)
+        );
+    }
+
+    #[test]
+    fn fees_are_valued_in_usd_not_in_their_quantity() {
+        let coin = |name: &str, amount: f64| Amount {
+            amount,
+            asset: Asset {
+                name: name.to_string(),
+                contract_address: None,
+            },
+        };
+        let (source, destination) = (coin("USDT", 100.0), coin("KAS", 1000.0));
+        let mut prices = PriceStore::default();
+
+        // A fee in the bought asset takes its share of the trade value
+        let fee = prices.fee_usd_value(&coin("KAS", 1.0), &source, &destination, 100.0, day(4));
+        assert_eq!(fee, 0.1);
+
+        assert_eq!(
+            prices.fee_usd_value(&coin("MX", 0.5), &source, &destination, 100.0, day(4)),
+            0.0
+        );
+        prices.insert(&coin("MX", 1.0).asset, day(1), 3.0);
+        assert_eq!(
+            prices.fee_usd_value(&coin("MX", 0.5), &source, &destination, 100.0, day(4)),
+            1.5
+        );
+    }
+
+    #[tokio::test]
+    async fn reference_rates_become_usd_prices() {
+        let server = MockServer::start().await;
+        let body = "KEY,FREQ,CURRENCY,CURRENCY_DENOM,EXR_TYPE,EXR_SUFFIX,TIME_PERIOD,OBS_VALUE\n\
+            EXR.D.CHF.EUR.SP00.A,D,CHF,EUR,SP00,A,2024-03-01,0.9550\n\
+            EXR.D.GBP.EUR.SP00.A,D,GBP,EUR,SP00,A,2024-03-01,0.8560\n\
+            EXR.D.USD.EUR.SP00.A,D,USD,EUR,SP00,A,2024-03-01,1.0830\n\
+            EXR.D.USD.EUR.SP00.A,D,USD,EUR,SP00,A,2024-03-04,\n";
+        Mock::given(method("GET"))
+            .and(path("/service/data/EXR/D.USD+GBP+CHF.EUR.SP00.A"))
+            .and(query_param("startPeriod", "2024-03-01"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new("ecb", &server.uri(), ECB_RATE_LIMIT);
+
+        let rates = request_rates(&client, day(1)).await.unwrap();
+
+        assert_eq!(rates.len(), 3);
+        assert_eq!(rates[0], ("EUR".to_string(), day(1), 1.083));
+        assert_eq!(rates[1].0, "CHF");
+        assert!((rates[1].2 - 1.083 / 0.955).abs() < 1e-9);
+        assert_eq!(rates[2].0, "GBP");
+    }
+
+    #[tokio::test]
+    async fn no_rates_yet_is_no_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("No results found."))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new("ecb", &server.uri(), ECB_RATE_LIMIT);
+
+        assert!(request_rates(&client, day(1)).await.unwrap().is_empty());
+    }
+}
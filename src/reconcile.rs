@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::{query, Pool, Sqlite};
+
+use crate::{
+    balances::{self, DUST},
+    input::InputError,
+    normalized,
+};
+
+/// The relative difference up to which balances are taken as equal, exchanges round
+const TOLERANCE: f64 = 1e-6;
+
+fn differs(reported: f64, computed: f64) -> bool {
+    (reported - computed).abs() > DUST.max(reported.abs() * TOLERANCE)
+}
+
+/// The reported and computed balance of each asset either side holds, and whether they differ
+fn compare(
+    reported: &BTreeMap<String, f64>,
+    computed: &BTreeMap<String, f64>,
+) -> Vec<(String, f64, f64, bool)> {
+    let mut assets = reported.keys().chain(computed.keys()).collect::<Vec<_>>();
+    assets.sort();
+    assets.dedup();
+
+    assets
+        .into_iter()
+        .filter_map(|asset| {
+            let reported = reported.get(asset).copied().unwrap_or_default();
+            let computed = computed.get(asset).copied().unwrap_or_default();
+            if reported.abs() < DUST && computed.abs() < DUST {
+                return None;
+            }
+            Some((
+                asset.clone(),
+                reported,
+                computed,
+                differs(reported, computed),
+            ))
+        })
+        .collect()
+}
+
+/// Compares the latest balances each exchange reported with the holdings computed from
+/// the transactions at the time, a difference tells that transactions are missing or wrong
+pub async fn print_reconciliation(db: &Pool<Sqlite>) -> Result<(), InputError> {
+    let snapshots = query!(
+        r#"SELECT id, source, taken_at AS "taken_at: DateTime<Utc>"
+        FROM balance_snapshots
+        WHERE id IN (SELECT MAX(id) FROM balance_snapshots GROUP BY source)
+        ORDER BY source"#
+    )
+    .fetch_all(db)
+    .await?;
+
+    if snapshots.is_empty() {
+        println!("No balances were reported yet, fetch MEXC or Coinbase first");
+        return Ok(());
+    }
+
+    let records = normalized::load_all(db).await?;
+
+    let mut mismatches = 0;
+    for snapshot in snapshots {
+        let mut reported = BTreeMap::new();
+        for row in query!(
+            r#"SELECT asset, SUM(amount) AS "amount!: f64" FROM balance_snapshot_entries
+            WHERE snapshot_id = $1 GROUP BY asset"#,
+            snapshot.id
+        )
+        .fetch_all(db)
+        .await?
+        {
+            reported.insert(row.asset, row.amount);
+        }
+
+        let mut computed = BTreeMap::<String, f64>::new();
+        for (store, asset, holding) in balances::holdings(&records, snapshot.taken_at) {
+            if store == snapshot.source {
+                *computed.entry(asset.name).or_default() += holding.quantity;
+            }
+        }
+
+        println!(
+            "{} as reported at {}",
+            snapshot.source,
+            snapshot.taken_at.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+        println!(
+            "{:<12} {:>20} {:>20} {:>20}",
+            "Asset", "Reported", "Computed", "Difference"
+        );
+
+        for (asset, reported, computed, differs) in compare(&reported, &computed) {
+            let marker = if differs {
+                mismatches += 1;
+                "  <- transactions missing or wrong"
+            } else {
+                ""
+            };
+            println!(
+                "{:<12} {:>20.8} {:>20.8} {:>20.8}{marker}",
+                asset,
+                reported,
+                computed,
+                reported - computed
+            );
+        }
+        println!();
+    }
+
+    match mismatches {
+        0 => println!("All computed balances match the reported ones"),
+        mismatches => println!("{mismatches} balances do not match the reported ones"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balances(entries: &[(&str, f64)]) -> BTreeMap<String, f64> {
+        entries
+            .iter()
+            .map(|(asset, amount)| (asset.to_string(), *amount))
+            .collect()
+    }
+
+    #[test]
+    fn missing_and_different_balances_are_mismatches() {
+        let reported = balances(&[("BTC", 0.5), ("ETH", 2.0), ("USDT", 100.0), ("XRP", 0.0)]);
+        let computed = balances(&[("BTC", 0.5000000001), ("ETH", 1.5), ("SOL", 3.0)]);
+
+        let compared = compare(&reported, &computed)
+            .into_iter()
+            .map(|(asset, _, _, differs)| (asset, differs))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            compared,
+            [
+                ("BTC".to_string(), false),
+                ("ETH".to_string(), true),
+                ("SOL".to_string(), true),
+                ("USDT".to_string(), true),
+            ]
+        );
+    }
+}
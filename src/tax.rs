@@ -0,0 +1,333 @@
+use chrono::{DateTime, Datelike, Months, Utc};
+use sqlx::{Pool, Sqlite};
+
+use crate::{
+    command_line_interface::CostMethod,
+    input::InputError,
+    lots::{Disposal, Income, LotEngine},
+    normalized,
+    prices::PriceStore,
+};
+
+/// Gains from private sales below this sum in a year are not taxed at all (§23 Abs. 3 EStG)
+fn freigrenze(year: i32) -> f64 {
+    if year >= 2024 {
+        1000.0
+    } else {
+        600.0
+    }
+}
+
+/// Other income below this sum in a year is not taxed at all (§22 Nr. 3 EStG)
+const FREIGRENZE_OTHER_INCOME: f64 = 256.0;
+
+/// Disposals of assets held for more than one year are no private sale (§23 Abs. 1 Nr. 2 EStG)
+fn tax_free(acquired_at: DateTime<Utc>, disposed_at: DateTime<Utc>) -> bool {
+    acquired_at
+        .date_naive()
+        .checked_add_months(Months::new(12))
+        .is_some_and(|year_later| disposed_at.date_naive() > year_later)
+}
+
+/// The euro value of an usd amount at the euro rate of the day it was valued at
+fn eur(prices: &PriceStore, usd: f64, at: DateTime<Utc>) -> Option<f64> {
+    prices
+        .convert_to_usd("EUR", 1.0, at.date_naive())
+        .ok()
+        .map(|rate| usd / rate)
+}
+
+/// The proceeds and cost of the disposal in eur, the proceeds at the rate of the day of the
+/// disposal and the cost at the rate of the day the lot was acquired, none without a value
+/// or a rate
+fn disposal_eur(prices: &PriceStore, disposal: &Disposal) -> Option<(f64, f64)> {
+    let proceeds = eur(prices, disposal.proceeds?, disposal.disposed_at)?;
+    let cost = match disposal.acquired_at {
+        Some(acquired_at) => eur(prices, disposal.cost?, acquired_at)?,
+        None => 0.0,
+    };
+
+    Some((proceeds, cost))
+}
+
+/// The value of the income in eur at the rate of the day it was received, none if it was
+/// not valued or there is no rate
+fn income_eur(prices: &PriceStore, income: &Income) -> Option<f64> {
+    if income.value == 0.0 {
+        return None;
+    }
+
+    eur(prices, income.value, income.received_at)
+}
+
+/// Prints the disposals of the year with their holding period and gain in eur, lots are
+/// used up first in, first out per wallet. Disposals without a known lot count as taxable.
+pub async fn print_tax_report(db: &Pool<Sqlite>, year: i32) -> Result<(), InputError> {
+    let records = normalized::load_all(db).await?;
+    let engine = LotEngine::run(&records, CostMethod::Fifo);
+    let prices = PriceStore::load(db).await?;
+
+    println!("Private sales in {year} (§23 EStG)");
+    println!(
+        "{:<10} {:<20} {:<10} {:>18} {:>10} {:>6} {:>14} {:>14} {:>14} {:<8}",
+        "Disposed",
+        "Store",
+        "Asset",
+        "Quantity",
+        "Acquired",
+        "Days",
+        "Proceeds EUR",
+        "Cost EUR",
+        "Gain EUR",
+        "Status"
+    );
+
+    let mut taxable = 0.0;
+    let mut tax_free_gains = 0.0;
+    let mut unknown = 0;
+    let mut unrated = 0;
+    let mut unvalued = 0;
+    for disposal in engine
+        .disposals
+        .iter()
+        .filter(|d| d.disposed_at.year() == year)
+    {
+        let Disposal {
+            store,
+            asset,
+            quantity,
+            acquired_at,
+            disposed_at,
+            ..
+        } = disposal;
+        let amounts = disposal_eur(&prices, disposal);
+        let gain = amounts.map(|(proceeds, cost)| proceeds - cost);
+        let (acquired, days, status) = match acquired_at {
+            Some(acquired_at) => {
+                let days = (*disposed_at - *acquired_at).num_days().to_string();
+                let status = if tax_free(*acquired_at, *disposed_at) {
+                    tax_free_gains += gain.unwrap_or_default();
+                    "tax-free"
+                } else {
+                    taxable += gain.unwrap_or_default();
+                    "taxable"
+                };
+                (acquired_at.date_naive().to_string(), days, status)
+            }
+            None => {
+                unknown += 1;
+                taxable += gain.unwrap_or_default();
+                ("unknown".to_string(), "".to_string(), "taxable")
+            }
+        };
+        // Without a value or a rate the disposal is left out of the sums
+        let status = if disposal.gain().is_none() {
+            unvalued += 1;
+            "unvalued"
+        } else if amounts.is_none() {
+            unrated += 1;
+            "no rate"
+        } else {
+            status
+        };
+        let (proceeds, cost, gain) = match amounts {
+            Some((proceeds, cost)) => (
+                format!("{proceeds:.2}"),
+                format!("{cost:.2}"),
+                format!("{:.2}", proceeds - cost),
+            ),
+            None => ("".to_string(), "".to_string(), "".to_string()),
+        };
+
+        println!(
+            "{:<10} {:<20} {:<10} {:>18.8} {:>10} {:>6} {:>14} {:>14} {:>14} {:<8}",
+            disposed_at.date_naive(),
+            store,
+            asset.name,
+            quantity,
+            acquired,
+            days,
+            proceeds,
+            cost,
+            gain,
+            status
+        );
+    }
+
+    let limit = freigrenze(year);
+    println!();
+    println!("Gains held more than one year (tax-free): {tax_free_gains:.2} EUR");
+    println!("Gains from private sales: {taxable:.2} EUR");
+    if taxable < limit {
+        println!("Below the Freigrenze of {limit:.0} EUR, no tax is due on them");
+    } else {
+        println!("Not below the Freigrenze of {limit:.0} EUR, all of it is taxable");
+    }
+    if unknown > 0 {
+        println!(
+            "{unknown} disposals have no lot to come from and are taken as taxable at no cost, \
+            their history is missing"
+        );
+    }
+    if unrated > 0 {
+        println!(
+            "{unrated} disposals have no euro rate on the day they were acquired or disposed \
+            of and are left out, fetch the rates with `rates` or set one with `price EUR`"
+        );
+    }
+    if unvalued > 0 {
+        println!(
+            "{unvalued} disposals were traded without a value and are left out, set the price \
+            of the asset with `price` or override their usd amount"
+        );
+    }
+
+    println!();
+    println!("Other income in {year} from staking and airdrops (§22 Nr. 3 EStG)");
+    println!(
+        "{:<10} {:<20} {:<10} {:>18} {:>14} Note",
+        "Received", "Store", "Asset", "Quantity", "Value EUR"
+    );
+
+    let mut income = 0.0;
+    let mut unvalued_income = 0;
+    for received in engine
+        .income
+        .iter()
+        .filter(|income| income.received_at.year() == year)
+    {
+        // Income without a value is flagged like disposals without a lot, it is not tax-free
+        let value = match income_eur(&prices, received) {
+            Some(value) => {
+                income += value;
+                format!("{value:.2}")
+            }
+            None => {
+                unvalued_income += 1;
+                "unvalued".to_string()
+            }
+        };
+        println!(
+            "{:<10} {:<20} {:<10} {:>18.8} {:>14} {}",
+            received.received_at.date_naive(),
+            received.store,
+            received.asset.name,
+            received.quantity,
+            value,
+            received.note
+        );
+    }
+
+    println!();
+    println!("Other income: {income:.2} EUR");
+    if income < FREIGRENZE_OTHER_INCOME {
+        println!("Below the Freigrenze of {FREIGRENZE_OTHER_INCOME:.0} EUR, no tax is due on it");
+    } else {
+        println!(
+            "Not below the Freigrenze of {FREIGRENZE_OTHER_INCOME:.0} EUR, all of it is taxable"
+        );
+    }
+    if unvalued_income > 0 {
+        println!(
+            "{unvalued_income} incomes have no value or euro rate and are left out, set the \
+            price of the asset with `price` or override their usd amount"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone};
+
+    use super::*;
+    use crate::data::Asset;
+
+    fn at(month: u32, day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, month, day, 12, 0, 0).unwrap()
+    }
+
+    fn prices() -> PriceStore {
+        let mut prices = PriceStore::default();
+        let eur = Asset {
+            name: "EUR".to_string(),
+            contract_address: None,
+        };
+        prices.insert(&eur, NaiveDate::from_ymd_opt(2024, 1, 2).unwrap(), 1.25);
+        prices.insert(&eur, NaiveDate::from_ymd_opt(2024, 6, 3).unwrap(), 1.5);
+        prices
+    }
+
+    fn disposal(acquired_at: Option<DateTime<Utc>>, disposed_at: DateTime<Utc>) -> Disposal {
+        Disposal {
+            tx_id: "T1".to_string(),
+            store: "Kraken".to_string(),
+            asset: Asset {
+                name: "BTC".to_string(),
+                contract_address: None,
+            },
+            quantity: 1.0,
+            proceeds: Some(3000.0),
+            cost: Some(1000.0),
+            acquired_at,
+            disposed_at,
+        }
+    }
+
+    #[test]
+    fn the_cost_is_converted_at_the_rate_of_the_acquisition() {
+        let amounts = disposal_eur(&prices(), &disposal(Some(at(1, 3)), at(6, 4)));
+
+        assert_eq!(amounts, Some((2000.0, 800.0)));
+    }
+
+    #[test]
+    fn disposals_without_a_rate_are_flagged() {
+        assert_eq!(
+            disposal_eur(&prices(), &disposal(Some(at(3, 1)), at(6, 4))),
+            None
+        );
+        assert_eq!(disposal_eur(&prices(), &disposal(None, at(9, 1))), None);
+        assert_eq!(
+            disposal_eur(&prices(), &disposal(None, at(6, 4))),
+            Some((2000.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn disposals_without_a_value_are_left_out() {
+        let unvalued = Disposal {
+            proceeds: None,
+            ..disposal(Some(at(1, 3)), at(6, 4))
+        };
+        let uncosted = Disposal {
+            cost: None,
+            ..disposal(Some(at(1, 3)), at(6, 4))
+        };
+
+        assert_eq!(unvalued.gain(), None);
+        assert_eq!(disposal_eur(&prices(), &unvalued), None);
+        assert_eq!(uncosted.gain(), None);
+        assert_eq!(disposal_eur(&prices(), &uncosted), None);
+    }
+
+    #[test]
+    fn income_without_a_value_is_flagged() {
+        let income = |value| Income {
+            tx_id: "A1".to_string(),
+            store: "Solana".to_string(),
+            asset: Asset {
+                name: "JUP".to_string(),
+                contract_address: None,
+            },
+            quantity: 100.0,
+            value,
+            received_at: at(1, 3),
+            note: "Jupiter airdrop".to_string(),
+        };
+
+        assert_eq!(income_eur(&prices(), &income(0.0)), None);
+        assert_eq!(income_eur(&prices(), &income(50.0)), Some(40.0));
+    }
+}